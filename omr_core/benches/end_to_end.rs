@@ -0,0 +1,40 @@
+// cargo +nightly bench --package omr_core --bench end_to_end --features="nightly"
+// cargo bench --package omr_core --bench end_to_end
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use omr_core::{OmrContext, OmrParameters};
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let board_size = 1000;
+    let pertinent_count = 50;
+
+    let mut group = c.benchmark_group("end to end");
+    group.sample_size(10);
+
+    group.bench_function(
+        format!("single recipient, {board_size} messages, {pertinent_count} pertinent"),
+        |b| {
+            b.iter_custom(|iters| {
+                (0..iters)
+                    .map(|seed| {
+                        OmrContext::benchmark_end_to_end(
+                            OmrParameters::new(),
+                            board_size,
+                            pertinent_count,
+                            seed,
+                        )
+                        .unwrap()
+                        .total
+                    })
+                    .sum::<Duration>()
+            });
+        },
+    );
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -4,8 +4,7 @@ use std::collections::HashSet;
 
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 use fhe_core::CmLweCiphertext;
-use lattice::NttRlwe;
-use omr_core::{KeyGen, OmrParameters, SecondLevelField};
+use omr_core::{KeyGen, OmrParameters, PertinencyCiphertext};
 use rand::seq::SliceRandom;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
@@ -61,12 +60,14 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         })
         .collect();
 
-    let detect_list: Vec<NttRlwe<SecondLevelField>> = clues_list
+    let detect_list: Vec<PertinencyCiphertext> = clues_list
         .par_iter()
         .map(|clues| detector.detect(clues))
         .collect();
 
-    let retriever = secret_key_pack.generate_retriever(all_payloads_count, pertinent_count);
+    let retriever = secret_key_pack
+        .generate_retriever(all_payloads_count, pertinent_count)
+        .unwrap();
     let retrieval_params = retriever.params();
 
     c.bench_function(
@@ -0,0 +1,120 @@
+// cargo +nightly bench --package omr_core --bench retrieve --features="nightly"
+// cargo bench --package omr_core --bench retrieve
+
+use algebra::modulus::BarrettModulus;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use fhe_core::CmLweCiphertext;
+use omr_core::{KeyGen, OmrParameters, Payload, PayloadMatrix, PertinencyCiphertext};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rayon::prelude::*;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let params = OmrParameters::new();
+    let mut rng = rand::thread_rng();
+
+    let secret_key_pack = KeyGen::generate_secret_key(params.clone(), &mut rng);
+    let secret_key_pack2 = KeyGen::generate_secret_key(params.clone(), &mut rng);
+
+    let sender = secret_key_pack.generate_sender(&mut rng);
+    let sender2 = secret_key_pack2.generate_sender(&mut rng);
+    let detector = secret_key_pack.generate_detector(&mut rng);
+
+    let all_payloads_count: usize = 64;
+    let pertinent_count: usize = 50;
+
+    let mut pertinent = vec![false; all_payloads_count];
+    pertinent[0..pertinent_count]
+        .iter_mut()
+        .for_each(|v| *v = true);
+    pertinent.shuffle(&mut rng);
+
+    let clues_list: Vec<CmLweCiphertext<u16>> = pertinent
+        .par_iter()
+        .map_init(rand::thread_rng, |rng, &f| {
+            if f {
+                sender.gen_clues(rng)
+            } else {
+                sender2.gen_clues(rng)
+            }
+        })
+        .collect();
+
+    let payloads: Vec<Payload> = (0..all_payloads_count)
+        .into_par_iter()
+        .map_init(rand::thread_rng, |rng, _| Payload::random(rng))
+        .collect();
+
+    let pertinency_vector: Vec<PertinencyCiphertext> = clues_list
+        .par_iter()
+        .map(|clues| detector.detect(clues))
+        .collect();
+
+    let retriever = secret_key_pack
+        .generate_retriever(all_payloads_count, pertinent_count)
+        .unwrap();
+    let retrieval_params = retriever.params();
+
+    let encode_pertinent_indices: Vec<_> = (0..retrieval_params.max_encode_indices_cipher_count())
+        .into_par_iter()
+        .map(|_| detector.encode_pertinent_indices(retrieval_params, &pertinency_vector))
+        .collect();
+
+    let seed: [u8; 32] = rng.gen();
+    let combinations = detector.encode_pertinent_payloads(
+        &pertinency_vector,
+        &payloads,
+        retrieval_params.combination_count(),
+        retrieval_params.cmb_count_per_cipher(),
+        &mut StdRng::from_seed(seed),
+    );
+
+    let weights: Vec<u16> = (0..pertinent_count)
+        .map(|_| rng.gen_range(0..257))
+        .collect();
+    let modulus = <BarrettModulus<u16>>::new(257);
+    let pertinent_payloads = &payloads[..pertinent_count];
+    let payload_matrix = PayloadMatrix::from_payloads(pertinent_payloads);
+
+    c.bench_function("combine 50 payloads row-major", |b| {
+        b.iter(|| Payload::combine(black_box(pertinent_payloads), black_box(&weights), modulus))
+    });
+
+    c.bench_function("combine 50 payloads column-major", |b| {
+        b.iter(|| black_box(&payload_matrix).combine(black_box(&weights), modulus))
+    });
+
+    for num_threads in [1, 8, 64] {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .unwrap();
+
+        c.bench_function(
+            &format!("decode combined payloads with {num_threads} threads"),
+            |b| {
+                b.iter(|| {
+                    pool.install(|| retriever.decode_combined_payloads(black_box(&combinations)))
+                });
+            },
+        );
+
+        c.bench_function(&format!("decode digest with {num_threads} threads"), |b| {
+            b.iter_batched_ref(
+                || retriever.clone(),
+                |retriever| {
+                    pool.install(|| {
+                        retriever.decode_digest(
+                            black_box(&encode_pertinent_indices),
+                            black_box(&combinations),
+                            seed,
+                        )
+                    })
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
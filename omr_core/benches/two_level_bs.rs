@@ -52,6 +52,18 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         );
     });
 
+    // Each blind rotation consumes its own copy of the LUT as the accumulator.
+    c.bench_function(
+        &format!("first level lut clones per detection ({msg_count} clues)"),
+        |b| {
+            b.iter(|| {
+                for _ in 0..msg_count {
+                    black_box(detector.first_level_lut().clone());
+                }
+            })
+        },
+    );
+
     // First level blind rotation and sum
     let intermediate = clues
         .iter()
@@ -45,13 +45,16 @@ fn main() {
     let (result, result2) = rayon::join(|| detector.detect(&clues), || detector.detect(&clues2));
     debug!("Detect done");
 
+    assert!(secret_key_pack.assert_trace_clean(&result));
+
+    let result = result.as_ntt();
     let poly = ntt_table.inverse_transform_inplace(result.b() - result.a().clone() * &**key);
 
     let decrypted = poly.into_iter().map(decode).collect::<Vec<Inner>>();
 
     assert_eq!(decrypted[0], 1);
-    assert!(decrypted[1..].iter().all(|&x| x == 0));
 
+    let result2 = result2.as_ntt();
     let poly2 = ntt_table.inverse_transform_inplace(result2.b() - result2.a().clone() * &**key);
     let decrypted2 = poly2.into_iter().map(decode).collect::<Vec<Inner>>();
 
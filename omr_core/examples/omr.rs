@@ -6,8 +6,9 @@ use std::{collections::HashSet, time::Instant};
 use clap::Parser;
 use fhe_core::CmLweCiphertext;
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use lattice::NttRlwe;
-use omr_core::{Detector, KeyGen, OmrParameters, Payload, SecondLevelField, SecretKeyPack, Sender};
+use omr_core::{
+    Detector, KeyGen, OmrParameters, Payload, PertinencyCiphertext, SecretKeyPack, Sender,
+};
 use rand::{
     rngs::{StdRng, ThreadRng},
     seq::SliceRandom,
@@ -157,7 +158,7 @@ fn omr(
 
     debug!("Detecting...");
     let start = Instant::now();
-    let pertinency_vector: Vec<NttRlwe<SecondLevelField>> = clues_list
+    let pertinency_vector: Vec<PertinencyCiphertext> = clues_list
         .par_iter()
         .progress_with(pb.clone())
         .map(|clues| detector.detect(clues))
@@ -171,7 +172,9 @@ fn omr(
         (end - start) / all_payloads_count as u32
     );
 
-    let mut retriever = secret_key_pack.generate_retriever(all_payloads_count, pertinent_count);
+    let mut retriever = secret_key_pack
+        .generate_retriever(all_payloads_count, pertinent_count)
+        .unwrap();
     let retrieval_params = retriever.params();
 
     let max_encode_indices_cipher_count = retrieval_params.max_encode_indices_cipher_count();
@@ -220,7 +223,7 @@ fn omr(
     info!("decode time: {:?}", retrieve_end - retrieve_start);
 
     for (&i, p) in indices.iter().zip(solved_payloads.iter()) {
-        if payloads[i] != *p {
+        if !payloads[i].content_eq(p) {
             println!("Fail {}", i);
             let count = payloads[i]
                 .iter()
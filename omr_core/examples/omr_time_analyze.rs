@@ -12,8 +12,10 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use fhe_core::CmLweCiphertext;
-use lattice::NttRlwe;
-use omr_core::{Detector, KeyGen, OmrParameters, Payload, Retriever, SecondLevelField, Sender};
+use omr_core::{
+    Detector, KeyGen, OmrParameters, Payload, PertinencyCiphertext, Retriever, SecondLevelField,
+    Sender,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Record {
@@ -85,8 +87,9 @@ fn main() {
         let seed = rng.gen();
 
         for pool in pools.iter().rev() {
-            let mut retriever =
-                secret_key_pack.generate_retriever(all_payloads_count, pertinent_count);
+            let mut retriever = secret_key_pack
+                .generate_retriever(all_payloads_count, pertinent_count)
+                .unwrap();
 
             println!("Each Start time: {}", Utc::now().with_timezone(&OFFSET));
             let time = pool.install(|| {
@@ -182,7 +185,7 @@ fn omr(
 
     let time_0 = Instant::now();
 
-    let pertinency_vector: Vec<NttRlwe<SecondLevelField>> = clues_list
+    let pertinency_vector: Vec<PertinencyCiphertext> = clues_list
         .par_iter()
         .map(|clues| detector.detect(clues))
         .collect();
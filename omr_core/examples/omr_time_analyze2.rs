@@ -12,8 +12,10 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use fhe_core::CmLweCiphertext;
-use lattice::NttRlwe;
-use omr_core::{Detector, KeyGen, OmrParameters, Payload, Retriever, SecondLevelField, Sender};
+use omr_core::{
+    Detector, KeyGen, OmrParameters, Payload, PertinencyCiphertext, Retriever, SecondLevelField,
+    Sender,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Record {
@@ -79,7 +81,7 @@ fn main() {
         let payloads_list = generate_payloads(all_payloads_count);
 
         let time_0 = Instant::now();
-        let pertinency_vector: Vec<NttRlwe<SecondLevelField>> =
+        let pertinency_vector: Vec<PertinencyCiphertext> =
             generate_pertinency_vector(&detector, &clues_list);
         let time_1 = Instant::now();
         println!("Detect time: {:?}", time_1 - time_0);
@@ -87,8 +89,9 @@ fn main() {
         let seed = rng.gen();
 
         for pool in pools.iter().rev() {
-            let mut retriever =
-                secret_key_pack.generate_retriever(all_payloads_count, pertinent_count);
+            let mut retriever = secret_key_pack
+                .generate_retriever(all_payloads_count, pertinent_count)
+                .unwrap();
 
             println!("Each Start time: {}", Utc::now().with_timezone(&OFFSET));
 
@@ -174,7 +177,7 @@ fn generate_payloads(all_payloads_count: usize) -> Vec<Payload> {
 fn generate_pertinency_vector(
     detector: &Detector,
     clues_list: &[CmLweCiphertext<u16>],
-) -> Vec<NttRlwe<SecondLevelField>> {
+) -> Vec<PertinencyCiphertext> {
     clues_list
         .par_iter()
         .map(|clues| detector.detect(clues))
@@ -183,7 +186,7 @@ fn generate_pertinency_vector(
 
 fn compress_and_retrieve(
     detector: &Detector,
-    pertinency_vector: &[NttRlwe<SecondLevelField>],
+    pertinency_vector: &[PertinencyCiphertext],
     payloads_list: &[Payload],
     pertinent_set: &HashSet<usize>,
     retriever: &mut Retriever<SecondLevelField>,
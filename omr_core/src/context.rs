@@ -0,0 +1,282 @@
+//! A single randomness source threaded through key generation, clue generation and combining.
+
+use std::time::{Duration, Instant};
+
+use fhe_core::{CmLweCiphertext, NttRlweCiphertext};
+use rand::{rngs::StdRng, seq::SliceRandom, CryptoRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::{
+    ClueValue, Detector, KeyGen, OmrError, OmrParameters, Payload, PertinencyCiphertext,
+    RetrievalParams, SecondLevelField, SecretKeyPack, Sender,
+};
+
+/// A cryptographically secure RNG usable for every randomized OMR operation.
+///
+/// Implemented for every `Rng + CryptoRng`, so a specific DRBG only needs those two traits.
+pub trait OmrRng: Rng + CryptoRng {}
+
+impl<R: Rng + CryptoRng> OmrRng for R {}
+
+/// Owns the one RNG used for key generation, clue generation and combination seeding.
+pub struct OmrContext<R: OmrRng> {
+    rng: R,
+}
+
+impl<R: OmrRng> OmrContext<R> {
+    /// Creates a new [`OmrContext`] drawing all randomness from `rng`.
+    #[inline]
+    pub fn new(rng: R) -> Self {
+        Self { rng }
+    }
+
+    /// Returns a mutable reference to the rng of this [`OmrContext<R>`].
+    #[inline]
+    pub fn rng_mut(&mut self) -> &mut R {
+        &mut self.rng
+    }
+
+    /// Returns the rng of this [`OmrContext<R>`].
+    #[inline]
+    pub fn into_rng(self) -> R {
+        self.rng
+    }
+
+    /// Generates a [`SecretKeyPack`].
+    #[inline]
+    pub fn generate_secret_key(&mut self, params: OmrParameters) -> SecretKeyPack {
+        KeyGen::generate_secret_key(params, &mut self.rng)
+    }
+
+    /// Generates a [`Sender`] for `secret_key`.
+    #[inline]
+    pub fn generate_sender(&mut self, secret_key: &SecretKeyPack) -> Sender {
+        secret_key.generate_sender(&mut self.rng)
+    }
+
+    /// Generates a [`Detector`] for `secret_key`.
+    #[inline]
+    pub fn generate_detector(&mut self, secret_key: &SecretKeyPack) -> Detector {
+        secret_key.generate_detector(&mut self.rng)
+    }
+
+    /// Generates a clue bundle with `sender`.
+    #[inline]
+    pub fn gen_clues(&mut self, sender: &Sender) -> CmLweCiphertext<ClueValue> {
+        sender.gen_clues(&mut self.rng)
+    }
+
+    /// Draws a seed for the combination weights.
+    #[inline]
+    pub fn combination_seed(&mut self) -> [u8; 32] {
+        self.rng.gen()
+    }
+
+    /// Combines the pertinent payloads with weights from a fresh combination seed.
+    ///
+    /// Returns the seed, which the retriever needs to rebuild the weights.
+    pub fn encode_pertinent_payloads(
+        &mut self,
+        detector: &Detector,
+        retrieval_params: RetrievalParams<SecondLevelField>,
+        pertinency_vector: &[PertinencyCiphertext],
+        payloads: &[Payload],
+    ) -> ([u8; 32], Vec<NttRlweCiphertext<SecondLevelField>>) {
+        let seed = self.combination_seed();
+        let combinations = detector.encode_pertinent_payloads(
+            pertinency_vector,
+            payloads,
+            retrieval_params.combination_count(),
+            retrieval_params.cmb_count_per_cipher(),
+            &mut StdRng::from_seed(seed),
+        );
+        (seed, combinations)
+    }
+}
+
+/// Phase durations of one recipient's run, see [`OmrContext::benchmark_end_to_end`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EndToEndTiming {
+    /// Detecting every message on the board.
+    pub detect: Duration,
+    /// Encoding the pertinent indices.
+    pub compress: Duration,
+    /// Combining the pertinent payloads.
+    pub combine: Duration,
+    /// Decoding the digest into indices and payloads.
+    pub retrieve: Duration,
+    /// Wall time from the start of detection to the end of retrieval.
+    pub total: Duration,
+}
+
+impl EndToEndTiming {
+    /// Returns the sum of the phase durations, which equals `total` up to timer jitter.
+    #[inline]
+    pub fn phases_sum(&self) -> Duration {
+        self.detect + self.compress + self.combine + self.retrieve
+    }
+}
+
+impl OmrContext<StdRng> {
+    /// Runs detection, index compression, payload combination and retrieval for one
+    /// recipient on a random board of `board_size` messages, `pertinent_count` of them
+    /// pertinent, and returns how long each phase took.
+    ///
+    /// Keys, clues and payloads are generated before timing starts. All randomness is
+    /// drawn from `seed`, except what the detector samples internally.
+    ///
+    /// Returns [`OmrError::InvalidParameters`] if `pertinent_count > board_size`.
+    pub fn benchmark_end_to_end(
+        params: OmrParameters,
+        board_size: usize,
+        pertinent_count: usize,
+        seed: u64,
+    ) -> Result<EndToEndTiming, OmrError> {
+        // Checked before the costly key generation.
+        if pertinent_count > board_size {
+            return Err(OmrError::InvalidParameters {
+                reason: "pertinent count must not exceed the board size",
+            });
+        }
+
+        let mut context = Self::new(StdRng::seed_from_u64(seed));
+
+        let secret_key = context.generate_secret_key(params.clone());
+        let other_secret_key = context.generate_secret_key(params);
+        let sender = context.generate_sender(&secret_key);
+        let other_sender = context.generate_sender(&other_secret_key);
+        let detector = context.generate_detector(&secret_key);
+        let mut retriever = secret_key.generate_retriever(board_size, pertinent_count)?;
+        let retrieval_params = retriever.params();
+
+        let mut pertinent = vec![false; board_size];
+        pertinent[..pertinent_count].fill(true);
+        pertinent.shuffle(context.rng_mut());
+
+        let clues = pertinent
+            .iter()
+            .map(|&f| context.gen_clues(if f { &sender } else { &other_sender }))
+            .collect::<Vec<_>>();
+        let payloads = (0..board_size)
+            .map(|_| Payload::random(context.rng_mut()))
+            .collect::<Vec<_>>();
+
+        let mut timer = PhaseTimer::start();
+
+        let pertinency_vector = detector.detect_batch(&clues);
+        let detect = timer.lap();
+
+        let encoded_indices = (0..retrieval_params.max_encode_indices_cipher_count())
+            .into_par_iter()
+            .map(|_| detector.encode_pertinent_indices(retrieval_params, &pertinency_vector))
+            .collect::<Vec<_>>();
+        let compress = timer.lap();
+
+        let (seed, combinations) = context.encode_pertinent_payloads(
+            &detector,
+            retrieval_params,
+            &pertinency_vector,
+            &payloads,
+        );
+        let combine = timer.lap();
+
+        retriever.decode_digest(&encoded_indices, &combinations, seed)?;
+        let retrieve = timer.lap();
+
+        Ok(EndToEndTiming {
+            detect,
+            compress,
+            combine,
+            retrieve,
+            total: timer.total(),
+        })
+    }
+}
+
+/// Measures back-to-back phases, so the laps add up to the total.
+struct PhaseTimer {
+    start: Instant,
+    last: Instant,
+}
+
+impl PhaseTimer {
+    fn start() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Returns the time since the previous lap, or since the start for the first one.
+    fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now - self.last;
+        self.last = now;
+        elapsed
+    }
+
+    /// Returns the time from the start to the last lap.
+    fn total(&self) -> Duration {
+        self.last - self.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_rng_reproduces_keys_and_clues() {
+        let run = || {
+            let mut context = OmrContext::new(StdRng::seed_from_u64(42));
+            let secret_key = context.generate_secret_key(OmrParameters::new());
+            let sender = context.generate_sender(&secret_key);
+            let clues = context.gen_clues(&sender);
+            let seed = context.combination_seed();
+            (secret_key, clues, seed)
+        };
+
+        let (secret_key_a, clues_a, seed_a) = run();
+        let (secret_key_b, clues_b, seed_b) = run();
+
+        // Same keys: each pack decrypts the other run's clues to zeros.
+        assert!(secret_key_a
+            .decrypt_clue_bundle(&clues_b)
+            .iter()
+            .all(|&c| c == 0));
+        assert!(secret_key_b
+            .decrypt_clue_bundle(&clues_a)
+            .iter()
+            .all(|&c| c == 0));
+        assert_eq!(seed_a, seed_b);
+    }
+
+    #[test]
+    fn end_to_end_total_is_the_sum_of_phases() {
+        let mut timer = PhaseTimer::start();
+        let mut timing = EndToEndTiming::default();
+        for phase in [
+            &mut timing.detect,
+            &mut timing.compress,
+            &mut timing.combine,
+            &mut timing.retrieve,
+        ] {
+            std::thread::sleep(Duration::from_millis(2));
+            *phase = timer.lap();
+        }
+        timing.total = timer.total();
+
+        assert!(timing.detect >= Duration::from_millis(2));
+        assert!(timing.total.abs_diff(timing.phases_sum()) < Duration::from_millis(1));
+
+        let timing = OmrContext::benchmark_end_to_end(OmrParameters::new(), 8, 2, 7).unwrap();
+        assert!(!timing.detect.is_zero() && !timing.retrieve.is_zero());
+        assert_eq!(timing.total, timing.phases_sum());
+
+        assert!(matches!(
+            OmrContext::benchmark_end_to_end(OmrParameters::new(), 8, 9, 7),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+    }
+}
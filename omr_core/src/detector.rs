@@ -1,15 +1,15 @@
 //! Detector pipeline: two-layer TFHE bootstrapping + homomorphic trace.
 
 use std::{
+    iter::Sum,
     ops::Add,
     time::{Duration, Instant},
 };
 
-use bigdecimal::{BigDecimal, RoundingMode};
-use num_traits::{ConstOne, FromPrimitive, ToPrimitive, Zero};
+use num_traits::{ConstOne, Zero};
 use rand::prelude::*;
 use rand_distr::Uniform;
-use rayon::prelude::*;
+use rayon::{iter::FromParallelIterator, prelude::*};
 
 use algebra::{
     integer::{AsFrom, AsInto},
@@ -22,13 +22,15 @@ use algebra::{
 };
 use fhe_core::{
     lwe_modulus_switch, lwe_modulus_switch_assign, BlindRotationKey, CmLweCiphertext,
-    LweCiphertext, NonPowOf2LweKeySwitchingKey, NttRlweCiphertext, RlweCiphertext, TraceKey,
+    LweCiphertext, LwePublicKeyRlweMode, LweSecretKey, NonPowOf2LweKeySwitchingKey,
+    NttRlweCiphertext, RlweCiphertext, TraceKey,
 };
 use lattice::NttRlwe;
 
 use crate::{
-    payload::PayloadByteType, ClueValue, DetectionKey, FirstLevelField, InterLweValue, LookUpTable,
-    OmrParameters, Payload, RetrievalParams, SecondLevelField, PAYLOAD_LENGTH,
+    payload::PayloadByteType, ClueKey, ClueValue, DetectionKey, FirstLevelField, FixedClues,
+    InterLweValue, LookUpTable, OmrError, OmrParameters, OutputValue, Payload, RetrievalParams,
+    SecondLevelDetectionKey, SecondLevelField, SecretKeyPack, TaggedClues, PAYLOAD_LENGTH,
 };
 
 /// Server-side detector that turns clues into a digest via bootstrapping + RLWE encoding.
@@ -38,6 +40,125 @@ pub struct Detector {
     second_level_lut: FieldPolynomial<SecondLevelField>,
 }
 
+/// Detector which only holds the second level keys, detecting from intermediate
+/// ciphertexts cached by [`Detector::detect_intermediate`].
+pub struct SecondLevelDetector {
+    detection_key: SecondLevelDetectionKey,
+    second_level_lut: FieldPolynomial<SecondLevelField>,
+}
+
+impl SecondLevelDetector {
+    /// Returns a reference to the detection key of this [`SecondLevelDetector`].
+    #[inline]
+    pub fn detection_key(&self) -> &SecondLevelDetectionKey {
+        &self.detection_key
+    }
+
+    /// Returns the size of the detection key of this [`SecondLevelDetector`].
+    #[inline]
+    pub fn detect_key_size(&self) -> usize {
+        self.detection_key.size()
+    }
+
+    /// Finishes the detection of a cached intermediate ciphertext, with the second
+    /// level bootstrapping and the homomorphic trace.
+    pub fn detect_cached(
+        &self,
+        intermediate: &LweCiphertext<InterLweValue>,
+    ) -> PertinencyCiphertext {
+        let ciphertext = second_level_bootstrapping(
+            intermediate.clone(),
+            self.detection_key.second_level_blind_rotation_key(),
+            &self.second_level_lut,
+            self.detection_key.params(),
+        );
+
+        PertinencyCiphertext(hom_trace(
+            ciphertext,
+            self.detection_key.trace_key(),
+            self.detection_key.second_level_ring_dimension_inv(),
+            self.detection_key
+                .second_level_blind_rotation_key()
+                .ntt_table(),
+        ))
+    }
+}
+
+/// Statistics of one index ciphertext built by
+/// [`Detector::encode_pertinent_indices_with_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressStats {
+    /// The number of messages which landed in an already occupied bucket.
+    ///
+    /// The detector cannot tell which messages are pertinent, so this counts the
+    /// collisions of all messages and bounds the collisions of pertinent ones.
+    pub collisions: usize,
+}
+
+/// The encrypted pertinency indicator of one message, output by [`Detector::detect`].
+///
+/// It is kept in the NTT domain, ready for [`Detector::encode_pertinent_indices`]
+/// and [`Detector::encode_pertinent_payloads`]. Both multiply every pertinency
+/// ciphertext by plaintext polynomials, so a coefficient-domain output would cost
+/// one transform per message in each encoding, while detection transforms the trace
+/// output once. Use [`PertinencyCiphertext::to_coeff`] and
+/// [`PertinencyCiphertext::from_coeff`] where the coefficient domain is needed.
+#[derive(Clone)]
+pub struct PertinencyCiphertext(NttRlweCiphertext<SecondLevelField>);
+
+impl PertinencyCiphertext {
+    /// Wraps an NTT-domain ciphertext as a [`PertinencyCiphertext`].
+    #[inline]
+    pub fn from_ntt(ciphertext: NttRlweCiphertext<SecondLevelField>) -> Self {
+        Self(ciphertext)
+    }
+
+    /// Returns a reference to the NTT-domain ciphertext.
+    #[inline]
+    pub fn as_ntt(&self) -> &NttRlweCiphertext<SecondLevelField> {
+        &self.0
+    }
+
+    /// Returns the NTT-domain ciphertext.
+    #[inline]
+    pub fn into_ntt(self) -> NttRlweCiphertext<SecondLevelField> {
+        self.0
+    }
+
+    /// Converts a coefficient-domain ciphertext into a [`PertinencyCiphertext`].
+    #[inline]
+    pub fn from_coeff(
+        ciphertext: &RlweCiphertext<SecondLevelField>,
+        ntt_table: &<SecondLevelField as NttField>::Table,
+    ) -> Self {
+        Self(ciphertext.to_ntt_rlwe(ntt_table))
+    }
+
+    /// Returns the ciphertext in the coefficient domain, e.g. for decryption.
+    #[inline]
+    pub fn to_coeff(
+        &self,
+        ntt_table: &<SecondLevelField as NttField>::Table,
+    ) -> RlweCiphertext<SecondLevelField> {
+        self.0.to_rlwe(ntt_table)
+    }
+}
+
+/// A [`Detector`] view whose clue count `C` is part of its type.
+///
+/// It only accepts [`FixedClues<C>`] with the same `C`.
+pub struct FixedDetector<'a, const C: usize> {
+    detector: &'a Detector,
+}
+
+impl<const C: usize> FixedDetector<'_, C> {
+    /// Detects the message from the given clues.
+    #[inline]
+    pub fn detect(&self, clues: &FixedClues<C>) -> PertinencyCiphertext {
+        self.detector.detect(clues.as_clues())
+    }
+}
+
 /// Time information for detecting a message.
 #[derive(Debug, Clone, Copy, Default)]
 pub struct DetectTimeInfoPerMessage {
@@ -71,41 +192,73 @@ impl Add<DetectTimeInfoPerMessage> for DetectTimeInfo {
     }
 }
 
+impl Add for DetectTimeInfo {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            total_detect_time: self.total_detect_time + rhs.total_detect_time,
+            total_first_level_bootstrapping_time: self.total_first_level_bootstrapping_time
+                + rhs.total_first_level_bootstrapping_time,
+            total_second_level_bootstrapping_time: self.total_second_level_bootstrapping_time
+                + rhs.total_second_level_bootstrapping_time,
+            total_trace_time: self.total_trace_time + rhs.total_trace_time,
+        }
+    }
+}
+
+impl Sum<DetectTimeInfoPerMessage> for DetectTimeInfo {
+    fn sum<I: Iterator<Item = DetectTimeInfoPerMessage>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl FromParallelIterator<DetectTimeInfoPerMessage> for DetectTimeInfo {
+    fn from_par_iter<I>(par_iter: I) -> Self
+    where
+        I: IntoParallelIterator<Item = DetectTimeInfoPerMessage>,
+    {
+        par_iter
+            .into_par_iter()
+            .fold(Self::default, Add::add)
+            .reduce(Self::default, Add::add)
+    }
+}
+
+/// Detections faster than this are too close to the clock resolution
+/// of some platforms for their sub-stage timings to be trusted.
+const MIN_RELIABLE_DETECT_TIME: Duration = Duration::from_millis(1);
+
 impl DetectTimeInfoPerMessage {
     /// Creates a new [`DetectTimeInfoPerMessage`].
     #[inline]
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Returns whether the sub-stage timings of this [`DetectTimeInfoPerMessage`] look trustworthy.
+    ///
+    /// On platforms with a coarse [`Instant`], the stages of one detection may fall within
+    /// a single clock tick and read as zero. If this returns `false`, time many detections
+    /// together with [`Detector::time_batch`] instead.
+    pub fn is_reliable(&self) -> bool {
+        self.detect_time >= MIN_RELIABLE_DETECT_TIME
+            && !self.first_level_bootstrapping_time.is_zero()
+            && !self.second_level_bootstrapping_time.is_zero()
+            && !self.trace_time.is_zero()
+    }
 }
 
 impl Detector {
     /// Creates a new [`Detector`].
     #[inline]
     pub fn new(detection_key: DetectionKey) -> Self {
-        let params = detection_key.params();
-
-        let clue_count = params.clue_count();
-        let clue_plain_modulus_value = params.clue_plain_modulus_value().as_into();
-        let first_level_ring_dimension = params.first_level_ring_dimension();
-        let intermediate_lwe_plain_modulus =
-            params.intermediate_lwe_plain_modulus_value().as_into();
-        let second_level_ring_dimension = params.second_level_ring_dimension();
-        let output_plain_modulus_value = params.output_plain_modulus_value().as_into();
+        let (first_level_lut, second_level_lut) = detection_luts(detection_key.params());
 
         Self {
             detection_key,
-            first_level_lut: first_level_lut(
-                first_level_ring_dimension,
-                clue_plain_modulus_value,
-                intermediate_lwe_plain_modulus,
-            ),
-            second_level_lut: second_level_lut(
-                second_level_ring_dimension,
-                clue_count,
-                intermediate_lwe_plain_modulus,
-                output_plain_modulus_value,
-            ),
+            first_level_lut,
+            second_level_lut,
         }
     }
 
@@ -131,37 +284,234 @@ impl Detector {
         &self.second_level_lut
     }
 
+    /// Returns the inclusive range of clue sums that produce a positive indicator.
+    ///
+    /// A clue bundle whose clues all match sums to `clue_count`, and the window
+    /// reaches [`OmrParameters::mismatch_tolerance`] sums below that.
+    pub fn decision_window(&self) -> (usize, usize) {
+        decision_window(self.detection_key.params())
+    }
+
+    /// Returns a [`FixedDetector`] view of this [`Detector`] with clue count `C`.
+    #[inline]
+    pub fn fixed<const C: usize>(&self) -> Result<FixedDetector<'_, C>, OmrError> {
+        let clue_count = self.detection_key.params().clue_count();
+        if clue_count != C {
+            return Err(OmrError::ClueCountMismatch {
+                expected: C,
+                found: clue_count,
+            });
+        }
+        Ok(FixedDetector { detector: self })
+    }
+
+    /// Detects the message from the given tagged clues, rejecting clues generated
+    /// under different [`OmrParameters`] with [`OmrError::ParameterMismatch`].
+    #[inline]
+    pub fn try_detect(&self, clues: &TaggedClues) -> Result<PertinencyCiphertext, OmrError> {
+        let clues = clues.verify(self.detection_key.params())?;
+        Ok(self.detect(clues))
+    }
+
+    /// Detects the message from the given clues with a caller supplied second level LUT,
+    /// e.g. one mapping clue sums to a small category instead of a binary indicator.
+    ///
+    /// The LUT is indexed by the intermediate plaintext, which is the clue sum plus
+    /// `clue_count` and so lies in `0..=2 * clue_count`. A clue decrypting to
+    /// [`MATCH_CLUE_VALUE`](crate::MATCH_CLUE_VALUE) adds one to the sum, half the
+    /// clue plain modulus away subtracts one, and any other value adds nothing.
+    /// The LUT must be built like [`second_level_lut`], over the intermediate plain
+    /// modulus and negacyclic in the second level ring dimension, with values scaled
+    /// by [`second_level_delta`] so they decode under the output plain modulus.
+    pub fn detect_with_lut(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+        second_level_lut: &FieldPolynomial<SecondLevelField>,
+    ) -> PertinencyCiphertext {
+        detect_with_key(
+            &self.detection_key,
+            &self.first_level_lut,
+            second_level_lut,
+            clues,
+        )
+    }
+
     /// Detects the message from the given clues.
-    pub fn detect(
+    pub fn detect(&self, clues: &CmLweCiphertext<ClueValue>) -> PertinencyCiphertext {
+        detect_with_key(
+            &self.detection_key,
+            &self.first_level_lut,
+            &self.second_level_lut,
+            clues,
+        )
+    }
+
+    /// Detects the message from clues already extracted by [`prepare_clues`].
+    ///
+    /// The prepared clues only depend on the parameters, so a board scanned for several
+    /// recipients sharing them is prepared once and detected by every recipient.
+    ///
+    /// Returns [`OmrError::ClueCountMismatch`] if `clues` doesn't hold `clue_count`
+    /// clues, and [`OmrError::DimensionMismatch`] if they aren't of the clue dimension.
+    pub fn detect_from_lwes(
+        &self,
+        clues: &[LweCiphertext<ClueValue>],
+    ) -> Result<PertinencyCiphertext, OmrError> {
+        let params = self.detection_key.params();
+        if clues.len() != params.clue_count() {
+            return Err(OmrError::ClueCountMismatch {
+                expected: params.clue_count(),
+                found: clues.len(),
+            });
+        }
+        for clue in clues {
+            check_clue_dimension(clue.a().len(), params)?;
+        }
+
+        Ok(detect_lwes_with_key(
+            &self.detection_key,
+            &self.first_level_lut,
+            &self.second_level_lut,
+            clues,
+        ))
+    }
+
+    /// Detects a bundle of `clue_count` clues, which may differ from the parameter
+    /// default, so senders using different clue counts can share one detector.
+    ///
+    /// The `clue_count` offset added after the first level and the second level LUT,
+    /// which fires at `clue_count * 2`, are rebuilt for this count on every call.
+    /// That position must stay below the intermediate plain modulus, and each extra
+    /// clue adds its first level noise to the sum.
+    pub fn detect_with_clue_count(
         &self,
         clues: &CmLweCiphertext<ClueValue>,
-    ) -> NttRlweCiphertext<SecondLevelField> {
+        clue_count: usize,
+    ) -> Result<PertinencyCiphertext, OmrError> {
         let params = self.detection_key.params();
 
-        let clues = extract_clues_and_modulus_switch(clues, params);
+        if clues.msg_count() != clue_count {
+            return Err(OmrError::ClueCountMismatch {
+                expected: clue_count,
+                found: clues.msg_count(),
+            });
+        }
+        if clue_count == params.clue_count() {
+            return Ok(self.detect(clues));
+        }
 
-        let intermediate = first_level_bootstrapping(
+        let intermediate_plain_modulus = params.intermediate_lwe_plain_modulus_value() as usize;
+        if clue_count == 0 || clue_count * 2 >= intermediate_plain_modulus {
+            return Err(OmrError::InvalidParameters {
+                reason:
+                    "clue count must be positive and twice it below the intermediate plain modulus",
+            });
+        }
+
+        let clues = extract_any_clues_and_modulus_switch(clues, params)?;
+
+        let mut intermediate = first_level_bootstrapping_raw(
             &clues,
             self.detection_key.first_level_blind_rotation_key(),
             self.detection_key.first_level_key_switching_key(),
             &self.first_level_lut,
             params,
         );
-
+        add_clue_count(&mut intermediate, clue_count, params);
+
+        let lut = second_level_lut(
+            params.second_level_ring_dimension(),
+            clue_count,
+            clue_count,
+            params.mismatch_tolerance().min(clue_count - 1),
+            intermediate_plain_modulus,
+            params.output_plain_modulus_value() as usize,
+        );
         let ciphertext = second_level_bootstrapping(
             intermediate,
             self.detection_key.second_level_blind_rotation_key(),
-            &self.second_level_lut,
+            &lut,
             params,
         );
 
-        hom_trace(
+        Ok(PertinencyCiphertext(hom_trace(
             ciphertext,
             self.detection_key.trace_key(),
             self.detection_key.second_level_ring_dimension_inv(),
             self.detection_key
                 .second_level_blind_rotation_key()
                 .ntt_table(),
+        )))
+    }
+
+    /// Detects a fresh non-pertinent clue bundle, whose indicator should decode to 0.
+    ///
+    /// The bundle encrypts zeros under a throwaway clue key, so under the detection
+    /// key its clues decrypt to random values. Decrypting the output shows the noise
+    /// distribution of a non-match, for calibrating decode thresholds.
+    pub fn detect_baseline<R>(&self, rng: &mut R) -> PertinencyCiphertext
+    where
+        R: Rng + CryptoRng,
+    {
+        self.detect(&baseline_clues(self.detection_key.params(), rng))
+    }
+
+    /// Runs the first-level bootstrapping only and returns the intermediate LWE
+    /// ciphertext, ready for [`SecondLevelDetector::detect_cached`].
+    ///
+    /// Caching these for a board detected repeatedly lets the first level keys be
+    /// dropped with [`Detector::into_second_level_only`].
+    pub fn detect_intermediate(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+    ) -> LweCiphertext<InterLweValue> {
+        let params = self.detection_key.params();
+
+        let clues = extract_clues_and_modulus_switch(clues, params);
+
+        first_level_bootstrapping(
+            &clues,
+            self.detection_key.first_level_blind_rotation_key(),
+            self.detection_key.first_level_key_switching_key(),
+            &self.first_level_lut,
+            params,
+        )
+    }
+
+    /// Drops the first level keys and LUT, see [`DetectionKey::into_second_level_only`].
+    #[inline]
+    pub fn into_second_level_only(self) -> SecondLevelDetector {
+        SecondLevelDetector {
+            detection_key: self.detection_key.into_second_level_only(),
+            second_level_lut: self.second_level_lut,
+        }
+    }
+
+    /// Runs the first-level bootstrapping only and returns the intermediate LWE
+    /// ciphertext without adding `clue_count`, for debugging the intermediate encoding.
+    ///
+    /// Each clue decrypting to zero contributes one, so a matching bundle decrypts
+    /// to `clue_count` under the intermediate LWE key. [`Detector::detect`] adds a
+    /// further `clue_count` before the second-level bootstrapping, which moves a
+    /// match to `clue_count * 2`.
+    ///
+    /// The output is thus offset by `-clue_count` from [`Detector::detect_intermediate`]:
+    /// adding `clue_count` to its decryption, modulo the intermediate plain modulus,
+    /// gives the value the second-level LUT is indexed by.
+    pub fn detect_intermediate_raw(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+    ) -> LweCiphertext<InterLweValue> {
+        let params = self.detection_key.params();
+
+        let clues = extract_clues_and_modulus_switch(clues, params);
+
+        first_level_bootstrapping_raw(
+            &clues,
+            self.detection_key.first_level_blind_rotation_key(),
+            self.detection_key.first_level_key_switching_key(),
+            &self.first_level_lut,
+            params,
         )
     }
 
@@ -169,10 +519,7 @@ impl Detector {
     pub fn detect_with_time_info(
         &self,
         clues: &CmLweCiphertext<ClueValue>,
-    ) -> (
-        NttRlweCiphertext<SecondLevelField>,
-        DetectTimeInfoPerMessage,
-    ) {
+    ) -> (PertinencyCiphertext, DetectTimeInfoPerMessage) {
         let time_0 = Instant::now();
 
         let params = self.detection_key.params();
@@ -217,16 +564,131 @@ impl Detector {
             trace_time: time_4 - time_3,
         };
 
-        (result, time_info)
+        (PertinencyCiphertext(result), time_info)
+    }
+
+    /// Detects all `clues` and returns the results with the wall time of the whole batch,
+    /// measured by a single [`Instant`] pair.
+    ///
+    /// Prefer this over [`Self::detect_with_time_info`] on coarse-clock platforms.
+    pub fn time_batch(
+        &self,
+        clues: &[CmLweCiphertext<ClueValue>],
+    ) -> (Vec<PertinencyCiphertext>, Duration) {
+        timed(|| clues.iter().map(|clues| self.detect(clues)).collect())
+    }
+
+    /// Returns whether any of `clues` is pertinent to `key`, detecting and decrypting
+    /// in parallel and stopping the remaining work as soon as one indicator decodes to 1.
+    ///
+    /// This needs the recipient's secret key, so it is only meant to run on the
+    /// recipient's side: the early exit reveals that a match exists, and roughly
+    /// where, to anyone observing the running time.
+    pub fn any_match(&self, clues: &[CmLweCiphertext<ClueValue>], key: &SecretKeyPack) -> bool {
+        clues
+            .par_iter()
+            .any(|clues| is_pertinent(key, &self.detect(clues)))
+    }
+
+    /// Detects all `clues` in parallel.
+    pub fn detect_batch(&self, clues: &[CmLweCiphertext<ClueValue>]) -> Vec<PertinencyCiphertext> {
+        clues.par_iter().map(|clues| self.detect(clues)).collect()
+    }
+
+    /// Detects all `clues` in parallel into the preallocated `out`, so repeated rounds
+    /// reuse one pertinency vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `out.len() != clues.len()`.
+    pub fn detect_batch_into(
+        &self,
+        clues: &[CmLweCiphertext<ClueValue>],
+        out: &mut [PertinencyCiphertext],
+    ) {
+        par_map_into(clues, out, |clues| self.detect(clues));
+    }
+
+    /// Detects all `clues` in parallel on the caller-provided `pool`.
+    ///
+    /// All parallelism of this crate runs on the pool it is called from, so the
+    /// detection never touches the global rayon pool.
+    pub fn detect_batch_in_pool(
+        &self,
+        clues: &[CmLweCiphertext<ClueValue>],
+        pool: &rayon::ThreadPool,
+    ) -> Vec<PertinencyCiphertext> {
+        pool.install(|| clues.par_iter().map(|clues| self.detect(clues)).collect())
+    }
+
+    /// Runs [`Self::detect`] on `clues` `iterations` times and returns the
+    /// throughput in detections per second.
+    pub fn bench_detect(&self, clues: &CmLweCiphertext<ClueValue>, iterations: usize) -> f64 {
+        let ((), elapsed) = timed(|| {
+            for _ in 0..iterations {
+                std::hint::black_box(self.detect(clues));
+            }
+        });
+        ops_per_second(iterations, elapsed)
     }
 
+    /// Encodes the indices of the pertinent messages into index ciphertexts.
+    ///
+    /// `pertinency_vector` is in the NTT domain, as output by [`Self::detect`]; use
+    /// [`PertinencyCiphertext::from_coeff`] for coefficient-domain ciphertexts.
+    #[inline]
     pub fn encode_pertinent_indices(
         &self,
         retrieval_params: RetrievalParams<SecondLevelField>,
-        pertinency_vector: &[NttRlweCiphertext<SecondLevelField>],
+        pertinency_vector: &[PertinencyCiphertext],
     ) -> NttRlwe<SecondLevelField> {
+        self.encode_pertinent_indices_with_stats(retrieval_params, pertinency_vector)
+            .0
+    }
+
+    /// Like [`Self::encode_pertinent_indices`], also returning the [`CompressStats`]
+    /// of the sampled buckets, so the caller can decide to encode another ciphertext.
+    #[inline]
+    pub fn encode_pertinent_indices_with_stats(
+        &self,
+        retrieval_params: RetrievalParams<SecondLevelField>,
+        pertinency_vector: &[PertinencyCiphertext],
+    ) -> (NttRlwe<SecondLevelField>, CompressStats) {
+        self.encode_indices(retrieval_params, pertinency_vector, |_| rand::thread_rng())
+    }
+
+    /// Like [`Self::encode_pertinent_indices`], sampling the buckets from `seed`, so the
+    /// same seed and inputs give the same ciphertext.
+    ///
+    /// The retriever decodes the buckets without knowing where they were placed,
+    /// so the seed does not need to be shared with it.
+    #[inline]
+    pub fn encode_pertinent_indices_seeded(
+        &self,
+        retrieval_params: RetrievalParams<SecondLevelField>,
+        pertinency_vector: &[PertinencyCiphertext],
+        seed: [u8; 32],
+    ) -> NttRlwe<SecondLevelField> {
+        self.encode_indices(retrieval_params, pertinency_vector, |chunk_i| {
+            chunk_rng(seed, chunk_i)
+        })
+        .0
+    }
+
+    /// Encodes the indices, sampling the buckets of the `i`-th chunk of messages
+    /// from `chunk_rng(i)`.
+    fn encode_indices<R, G>(
+        &self,
+        retrieval_params: RetrievalParams<SecondLevelField>,
+        pertinency_vector: &[PertinencyCiphertext],
+        chunk_rng: G,
+    ) -> (NttRlwe<SecondLevelField>, CompressStats)
+    where
+        R: Rng,
+        G: Fn(usize) -> R + Sync,
+    {
         // Step 3c: RLWE-encode the indices of pertinent messages.
-        // Encode each index into slots using base-(index_modulus) digits or bit chunks.
+        // Encode each index into slots using base-(index_base) digits or bit chunks.
         const CHUNK_SIZE: usize = 2048;
         let ntt_table = self
             .detection_key
@@ -238,35 +700,38 @@ impl Detector {
         let slots_per_bucket = retrieval_params.slots_per_bucket();
         let slots_per_segment = retrieval_params.slots_per_segment();
         let bucket_distr = retrieval_params.bucket_distr();
+        let bucket_count = retrieval_params.bucket_count_per_segment();
+        let slot_count = polynomial_size / slots_per_segment * bucket_count;
 
         let index_slots_per_bucket = slots_per_bucket - 1;
-        let index_modulus = retrieval_params.index_modulus();
+        let index_base = retrieval_params.index_base();
 
-        let is_power_of_two = index_modulus.is_power_of_two();
+        let is_power_of_two = index_base.is_power_of_two();
 
-        let mask = index_modulus - 1;
-        let shift_bits = index_modulus.trailing_zeros();
+        let mask = index_base - 1;
+        let shift_bits = index_base.trailing_zeros();
 
-        let modulus = <BarrettModulus<u64>>::new(index_modulus);
+        let modulus = <BarrettModulus<u64>>::new(index_base);
 
         let q = <SecondLevelField as Field>::MODULUS_VALUE;
         let p = self.detection_key().params().output_plain_modulus_value();
         let half_p = (p + 1) >> 1;
 
-        let ciphertext = pertinency_vector
+        let (ciphertext, occupancy) = pertinency_vector
             .par_chunks(CHUNK_SIZE)
             .enumerate()
             .map_init(
                 || {
                     (
-                        rand::thread_rng(),
                         <FieldNttPolynomial<SecondLevelField>>::zero(polynomial_size),
                         <NttRlwe<SecondLevelField>>::zero(polynomial_size),
                     )
                 },
-                |(rng, poly, temp), (chunk_i, chunk)| {
+                |(poly, temp), (chunk_i, chunk)| {
+                    let mut rng = chunk_rng(chunk_i);
                     let mut chunk_result: NttRlwe<SecondLevelField> =
                         NttRlwe::zero(polynomial_size);
+                    let mut occupancy = vec![0usize; slot_count];
 
                     chunk.iter().enumerate().for_each(|(j, detect)| {
                         let i = CHUNK_SIZE * chunk_i + j;
@@ -275,12 +740,14 @@ impl Detector {
 
                         poly.as_mut_slice()
                             .chunks_exact_mut(slots_per_segment)
-                            .zip(bucket_distr.sample_iter(&mut *rng))
+                            .zip(bucket_distr.sample_iter(&mut rng))
+                            .enumerate()
                             .for_each(
-                                |(chunk, bucket_index): (
-                                    &mut [<SecondLevelField as Field>::ValueT],
+                                |(segment, (chunk, bucket_index)): (
                                     usize,
+                                    (&mut [<SecondLevelField as Field>::ValueT], usize),
                                 )| {
+                                    occupancy[segment * bucket_count + bucket_index] += 1;
                                     let mut i: <SecondLevelField as Field>::ValueT =
                                         AsFrom::as_from(i);
                                     let address = bucket_index * slots_per_bucket;
@@ -299,16 +766,13 @@ impl Detector {
                                         }
                                     } else {
                                         while !i.is_zero() {
-                                            let v = if i < index_modulus {
-                                                i
-                                            } else {
-                                                modulus.reduce(i)
-                                            };
+                                            let v =
+                                                if i < index_base { i } else { modulus.reduce(i) };
                                             unsafe {
                                                 *chunk.get_unchecked_mut(address + k) =
                                                     if v < half_p { v } else { q - p + v };
                                             }
-                                            i = (i - v) / index_modulus;
+                                            i = (i - v) / index_base;
                                             k += 1;
                                         }
                                     }
@@ -324,23 +788,40 @@ impl Detector {
 
                         ntt_table.transform_slice(poly.as_mut_slice());
 
-                        detect.mul_ntt_polynomial_inplace(poly, temp);
+                        detect.0.mul_ntt_polynomial_inplace(poly, temp);
                         chunk_result.add_assign_element_wise(temp);
                     });
-                    chunk_result
+                    (chunk_result, occupancy)
                 },
             )
             .reduce(
-                || <NttRlwe<SecondLevelField>>::zero(polynomial_size),
-                |a, b| a.add_element_wise(&b),
+                || {
+                    (
+                        <NttRlwe<SecondLevelField>>::zero(polynomial_size),
+                        vec![0usize; slot_count],
+                    )
+                },
+                |(a, mut occupancy), (b, other)| {
+                    occupancy.iter_mut().zip(other).for_each(|(x, y)| *x += y);
+                    (a.add_element_wise(&b), occupancy)
+                },
             );
 
-        ciphertext
+        let collisions = occupancy.iter().map(|&c| c.saturating_sub(1)).sum();
+
+        (ciphertext, CompressStats { collisions })
     }
 
+    /// Combines the pertinent payloads with random weights modulo the output plain
+    /// modulus `p`.
+    ///
+    /// The accumulation is RLWE addition, so the combinations are linear over `Z/p`:
+    /// `p = 256` pairs with `solve_matrix_mod_256` and `p = 257` with `solve_matrix_mod_257`.
+    /// GF(2^8) combinations are not reachable homomorphically: their addition is XOR,
+    /// which no plaintext modulus of an additively homomorphic RLWE encoding computes.
     pub fn encode_pertinent_payloads<R>(
         &self,
-        pertinency_vector: &[NttRlweCiphertext<SecondLevelField>],
+        pertinency_vector: &[PertinencyCiphertext],
         payloads: &[Payload],
         combination_count: usize,
         cmb_count_per_cipher: usize,
@@ -351,108 +832,441 @@ impl Detector {
     {
         // Step 3c: RLWE-encode payloads weighted by pertinency.
         // Sample random weights and pack weighted payloads into RLWE slots.
-        const CHUNK_SIZE: usize = 2048;
-
         let payloads_count = payloads.len();
-        let ring_dimension = self.detection_key.params().second_level_ring_dimension();
-        let cmb_cipher_count = combination_count.div_ceil(cmb_count_per_cipher);
-        let q = <SecondLevelField as Field>::MODULUS_VALUE;
-        let p = self.detection_key().params().output_plain_modulus_value();
-        let is_power_of_two = p.is_power_of_two();
-        let half_p = (p + 1) >> 1;
+        let all_weights = self.sample_combination_weights(
+            payloads_count,
+            combination_count,
+            cmb_count_per_cipher,
+            rng,
+        );
 
-        let powof2_modulus = if is_power_of_two {
-            <PowOf2Modulus<PayloadByteType>>::new(p as PayloadByteType)
-        } else {
-            <PowOf2Modulus<PayloadByteType>>::new(2)
-        };
-        let barrett_modulus = <BarrettModulus<PayloadByteType>>::new(p as PayloadByteType);
+        combine_payloads(
+            self.detection_key
+                .second_level_blind_rotation_key()
+                .ntt_table(),
+            self.detection_key().params().output_plain_modulus_value(),
+            pertinency_vector,
+            payloads,
+            &all_weights,
+            payloads_count,
+            cmb_count_per_cipher,
+            0,
+        )
+    }
 
-        let ntt_table = self
-            .detection_key
-            .second_level_blind_rotation_key()
-            .ntt_table();
+    /// Samples the combination weights used by [`Self::encode_pertinent_payloads`].
+    ///
+    /// The weights of combination ciphertext `c` for payload `i` in row `j` are at
+    /// `(c * cmb_count_per_cipher + j) * all_payloads_count + i`.
+    pub fn sample_combination_weights<R>(
+        &self,
+        all_payloads_count: usize,
+        combination_count: usize,
+        cmb_count_per_cipher: usize,
+        rng: &mut R,
+    ) -> Vec<PayloadByteType>
+    where
+        R: Rng + CryptoRng,
+    {
+        let cmb_cipher_count = combination_count.div_ceil(cmb_count_per_cipher);
+        let p = self.detection_key().params().output_plain_modulus_value();
 
         let mut all_weights: Vec<PayloadByteType> =
-            vec![0; cmb_cipher_count * cmb_count_per_cipher * payloads_count];
+            vec![0; cmb_cipher_count * cmb_count_per_cipher * all_payloads_count];
 
         let distr = Uniform::new(0, p as PayloadByteType);
 
         distr
             .sample_iter(rng)
             .zip(all_weights.iter_mut())
-            .take(combination_count * payloads_count)
+            .take(combination_count * all_payloads_count)
             .for_each(|(weight, w)| {
                 *w = weight;
             });
 
         // rng.fill_bytes(&mut all_weights[..combination_count * payloads_count]);
 
-        let combinations = all_weights
-            .par_chunks_exact(cmb_count_per_cipher * payloads_count)
-            .map(|weights_chunk| {
-                pertinency_vector
-                    .par_chunks(CHUNK_SIZE)
-                    .zip(payloads.par_chunks(CHUNK_SIZE))
-                    .enumerate()
-                    .map_init(
-                        || FieldNttPolynomial::<SecondLevelField>::zero(ring_dimension),
-                        |payload_ntt_poly, (chunk_i, (pv_chunk, payload_chunk))| {
-                            let mut temp_cmb =
-                                NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension);
-
-                            pv_chunk
-                                .iter()
-                                .zip(payload_chunk.iter())
-                                .enumerate()
-                                .for_each(|(chunk_j, (pv, payload))| {
-                                    let i = CHUNK_SIZE * chunk_i + chunk_j;
-                                    payload_ntt_poly.set_zero();
-
-                                    for (j, poly_chunk) in payload_ntt_poly
-                                        .as_mut_slice()
-                                        .chunks_exact_mut(PAYLOAD_LENGTH)
-                                        .take(cmb_count_per_cipher)
-                                        .enumerate()
-                                    {
-                                        let weight = unsafe {
-                                            *weights_chunk.get_unchecked(j * payloads_count + i)
-                                        };
-                                        let weighted_payload = if is_power_of_two {
-                                            payload.mul_scalar(weight, powof2_modulus)
-                                        } else {
-                                            payload.mul_scalar(weight, barrett_modulus)
-                                        };
-                                        poly_chunk
-                                            .iter_mut()
-                                            .zip(weighted_payload.0.iter())
-                                            .for_each(|(a, &b)| {
-                                                let b = b as <SecondLevelField as Field>::ValueT;
-                                                *a = if b < half_p { b } else { q - p + b };
-                                            });
-                                    }
+        all_weights
+    }
 
-                                    ntt_table.transform_slice(payload_ntt_poly.as_mut_slice());
+    /// Computes the partial combinations of one shard of the board.
+    ///
+    /// The shard holds the payloads `global_offset..global_offset + payloads_shard.len()`
+    /// of a board of `all_payloads_count` payloads, and `weights` are the weights of the
+    /// whole board from [`Self::sample_combination_weights`]. Summing the partial
+    /// combinations of all shards with `add_assign_element_wise` gives the combinations
+    /// of the whole board.
+    pub fn combine_shard(
+        &self,
+        pertinency_vector_shard: &[PertinencyCiphertext],
+        payloads_shard: &[Payload],
+        weights: &[PayloadByteType],
+        all_payloads_count: usize,
+        cmb_count_per_cipher: usize,
+        global_offset: usize,
+    ) -> Vec<NttRlweCiphertext<SecondLevelField>> {
+        combine_payloads(
+            self.detection_key
+                .second_level_blind_rotation_key()
+                .ntt_table(),
+            self.detection_key().params().output_plain_modulus_value(),
+            pertinency_vector_shard,
+            payloads_shard,
+            weights,
+            all_payloads_count,
+            cmb_count_per_cipher,
+            global_offset,
+        )
+    }
 
-                                    temp_cmb.add_ntt_rlwe_mul_ntt_polynomial_assign(
-                                        pv,
-                                        payload_ntt_poly,
-                                    );
-                                });
-                            temp_cmb
-                        },
-                    )
-                    .reduce(
-                        || NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension),
-                        |acc, x| acc.add_element_wise(&x),
-                    )
-            })
-            .collect();
+    /// Computes the partial combinations of one shard like [`Self::combine_shard`],
+    /// returned in the coefficient domain.
+    ///
+    /// The weighted sum itself is computed in the NTT domain, so each combination
+    /// ciphertext costs one inverse transform here. Coefficient-domain partials can
+    /// then be summed with `add_assign_element_wise` and converted once with
+    /// [`Self::to_ntt`], instead of round-tripping every partial.
+    pub fn combine_coeff_domain(
+        &self,
+        pertinency_vector_shard: &[PertinencyCiphertext],
+        payloads_shard: &[Payload],
+        weights: &[PayloadByteType],
+        all_payloads_count: usize,
+        cmb_count_per_cipher: usize,
+        global_offset: usize,
+    ) -> Vec<RlweCiphertext<SecondLevelField>> {
+        combinations_to_coeff(
+            self.detection_key
+                .second_level_blind_rotation_key()
+                .ntt_table(),
+            &self.combine_shard(
+                pertinency_vector_shard,
+                payloads_shard,
+                weights,
+                all_payloads_count,
+                cmb_count_per_cipher,
+                global_offset,
+            ),
+        )
+    }
+
+    /// Converts coefficient-domain combinations from [`Self::combine_coeff_domain`]
+    /// to the NTT domain expected by the retriever.
+    pub fn to_ntt(
+        &self,
+        combinations: &[RlweCiphertext<SecondLevelField>],
+    ) -> Vec<NttRlweCiphertext<SecondLevelField>> {
+        combinations_to_ntt(
+            self.detection_key
+                .second_level_blind_rotation_key()
+                .ntt_table(),
+            combinations,
+        )
+    }
+}
+
+#[cfg(feature = "async")]
+impl Detector {
+    /// Detects the message from the given clues on tokio's blocking thread pool.
+    ///
+    /// Detection is CPU-bound, so it must not run on the async worker threads.
+    /// The detector is shared through an [`Arc`](std::sync::Arc) since the blocking
+    /// task may outlive the caller's borrow.
+    ///
+    /// # Panics
+    ///
+    /// Must be called within a tokio runtime. A panic in [`Self::detect`] is
+    /// resumed when the future is awaited.
+    pub fn detect_async(
+        self: &std::sync::Arc<Self>,
+        clues: CmLweCiphertext<ClueValue>,
+    ) -> impl std::future::Future<Output = PertinencyCiphertext> {
+        let detector = std::sync::Arc::clone(self);
+        run_blocking(move || detector.detect(&clues))
+    }
+}
 
-        combinations
+/// Runs `f` on tokio's blocking thread pool.
+#[cfg(feature = "async")]
+async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
     }
 }
 
+fn combinations_to_coeff(
+    ntt_table: &<SecondLevelField as NttField>::Table,
+    combinations: &[NttRlweCiphertext<SecondLevelField>],
+) -> Vec<RlweCiphertext<SecondLevelField>> {
+    combinations
+        .par_iter()
+        .map(|c| c.to_rlwe(ntt_table))
+        .collect()
+}
+
+fn combinations_to_ntt(
+    ntt_table: &<SecondLevelField as NttField>::Table,
+    combinations: &[RlweCiphertext<SecondLevelField>],
+) -> Vec<NttRlweCiphertext<SecondLevelField>> {
+    combinations
+        .par_iter()
+        .map(|c| c.to_ntt_rlwe(ntt_table))
+        .collect()
+}
+
+/// Packs the weighted payloads into RLWE slots and sums them weighted by pertinency.
+///
+/// `payloads` are the payloads `global_offset..` of a board of `all_payloads_count`.
+#[allow(clippy::too_many_arguments)]
+fn combine_payloads(
+    ntt_table: &<SecondLevelField as NttField>::Table,
+    p: OutputValue,
+    pertinency_vector: &[PertinencyCiphertext],
+    payloads: &[Payload],
+    all_weights: &[PayloadByteType],
+    all_payloads_count: usize,
+    cmb_count_per_cipher: usize,
+    global_offset: usize,
+) -> Vec<NttRlweCiphertext<SecondLevelField>> {
+    const CHUNK_SIZE: usize = 2048;
+
+    assert!(global_offset + payloads.len() <= all_payloads_count);
+
+    let ring_dimension = ntt_table.dimension();
+    let q = <SecondLevelField as Field>::MODULUS_VALUE;
+    let is_power_of_two = p.is_power_of_two();
+    let half_p = (p + 1) >> 1;
+
+    let powof2_modulus = if is_power_of_two {
+        <PowOf2Modulus<PayloadByteType>>::new(p as PayloadByteType)
+    } else {
+        <PowOf2Modulus<PayloadByteType>>::new(2)
+    };
+    let barrett_modulus = <BarrettModulus<PayloadByteType>>::new(p as PayloadByteType);
+
+    let combinations = all_weights
+        .par_chunks_exact(cmb_count_per_cipher * all_payloads_count)
+        .map(|weights_chunk| {
+            pertinency_vector
+                .par_chunks(CHUNK_SIZE)
+                .zip(payloads.par_chunks(CHUNK_SIZE))
+                .enumerate()
+                .map_init(
+                    || FieldNttPolynomial::<SecondLevelField>::zero(ring_dimension),
+                    |payload_ntt_poly, (chunk_i, (pv_chunk, payload_chunk))| {
+                        let mut temp_cmb =
+                            NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension);
+
+                        pv_chunk
+                            .iter()
+                            .zip(payload_chunk.iter())
+                            .enumerate()
+                            .for_each(|(chunk_j, (pv, payload))| {
+                                let i = global_offset + CHUNK_SIZE * chunk_i + chunk_j;
+                                payload_ntt_poly.set_zero();
+
+                                for (j, poly_chunk) in payload_ntt_poly
+                                    .as_mut_slice()
+                                    .chunks_exact_mut(PAYLOAD_LENGTH)
+                                    .take(cmb_count_per_cipher)
+                                    .enumerate()
+                                {
+                                    let weight = unsafe {
+                                        *weights_chunk.get_unchecked(j * all_payloads_count + i)
+                                    };
+                                    let weighted_payload = if is_power_of_two {
+                                        payload.mul_scalar(weight, powof2_modulus)
+                                    } else {
+                                        payload.mul_scalar(weight, barrett_modulus)
+                                    };
+                                    poly_chunk
+                                        .iter_mut()
+                                        .zip(weighted_payload.0.iter())
+                                        .for_each(|(a, &b)| {
+                                            let b = b as <SecondLevelField as Field>::ValueT;
+                                            *a = if b < half_p { b } else { q - p + b };
+                                        });
+                                }
+
+                                ntt_table.transform_slice(payload_ntt_poly.as_mut_slice());
+
+                                temp_cmb.add_ntt_rlwe_mul_ntt_polynomial_assign(
+                                    &pv.0,
+                                    payload_ntt_poly,
+                                );
+                            });
+                        temp_cmb
+                    },
+                )
+                .reduce(
+                    || NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension),
+                    |acc, x| acc.add_element_wise(&x),
+                )
+        })
+        .collect();
+
+    combinations
+}
+
+/// The scaling `round(q_1 / output_plain_modulus)` used by [`first_level_lut`],
+/// where `output_plain_modulus` must be a power of two.
+pub fn first_level_delta(output_plain_modulus: usize) -> InterLweValue {
+    let q = <FirstLevelField as Field>::MODULUS_VALUE;
+    let log = output_plain_modulus.trailing_zeros() - 1;
+    ((q >> log) + 1) >> 1
+}
+
+/// The scaling `round(q_2 / output_plain_modulus)` used by [`second_level_lut`].
+pub fn second_level_delta(output_plain_modulus: usize) -> OutputValue {
+    round_delta(
+        <SecondLevelField as Field>::MODULUS_VALUE,
+        output_plain_modulus as u64,
+    )
+}
+
+/// Returns `round(q / p)` with ties rounded up.
+///
+/// This is the scale of the second level LUT and of the combined payload bytes,
+/// see [`RetrievalParams::payload_delta`], so both come from here.
+pub(crate) fn round_delta(q: u64, p: u64) -> u64 {
+    let (q, p) = (q as u128, p as u128);
+    ((2 * q + p) / (2 * p)) as u64
+}
+
+/// Checks that the LUT scales decode back with `round(c * p / q)`, for every symbol.
+///
+/// The first level LUT encodes the intermediate plaintexts with [`first_level_delta`]
+/// and the second level LUT the output plaintexts with [`second_level_delta`]. A scale
+/// off by a factor of two, e.g. from a wrong `log`, decodes systematically wrong.
+pub fn verify_lut_decode_consistency(params: &OmrParameters) -> Result<(), OmrError> {
+    let t = params.intermediate_lwe_plain_modulus_value() as u64;
+    let q = <FirstLevelField as Field>::MODULUS_VALUE as u64;
+    if !symbols_round_trip(t, first_level_delta(t as usize) as u64, q) {
+        return Err(OmrError::InvalidParameters {
+            reason: "first level LUT scale does not decode back",
+        });
+    }
+
+    let p = params.output_plain_modulus_value() as u64;
+    let q = <SecondLevelField as Field>::MODULUS_VALUE;
+    if !symbols_round_trip(p, second_level_delta(p as usize), q) {
+        return Err(OmrError::InvalidParameters {
+            reason: "second level LUT scale does not decode back",
+        });
+    }
+    Ok(())
+}
+
+/// Whether every `m < p` scaled by `delta` modulo `q` decodes back to `m`.
+fn symbols_round_trip(p: u64, delta: u64, q: u64) -> bool {
+    (0..p).all(|m| crate::retriever::scale_round(m * delta % q, p, q) == m)
+}
+
+/// Writes `f(input)` to the slot of `out` with the same index, in parallel.
+fn par_map_into<T: Sync, U: Send>(inputs: &[T], out: &mut [U], f: impl Fn(&T) -> U + Sync) {
+    assert_eq!(
+        inputs.len(),
+        out.len(),
+        "Output length must match input length."
+    );
+    out.par_iter_mut()
+        .zip(inputs.par_iter())
+        .for_each(|(slot, input)| *slot = f(input));
+}
+
+/// Builds the first and second level LUTs for `params`.
+pub(crate) fn detection_luts(
+    params: &OmrParameters,
+) -> (
+    FieldPolynomial<FirstLevelField>,
+    FieldPolynomial<SecondLevelField>,
+) {
+    let intermediate_lwe_plain_modulus = params.intermediate_lwe_plain_modulus_value().as_into();
+
+    (
+        first_level_lut(
+            params.first_level_ring_dimension(),
+            params.clue_plain_modulus_value().as_into(),
+            intermediate_lwe_plain_modulus,
+        ),
+        second_level_lut(
+            params.second_level_ring_dimension(),
+            params.clue_count(),
+            params.match_sum_value(),
+            params.mismatch_tolerance(),
+            intermediate_lwe_plain_modulus,
+            params.output_plain_modulus_value().as_into(),
+        ),
+    )
+}
+
+/// Detects `clues` with `detection_key` and the LUTs from [`detection_luts`].
+pub(crate) fn detect_with_key(
+    detection_key: &DetectionKey,
+    first_level_lut: &FieldPolynomial<FirstLevelField>,
+    second_level_lut: &FieldPolynomial<SecondLevelField>,
+    clues: &CmLweCiphertext<ClueValue>,
+) -> PertinencyCiphertext {
+    let clues = extract_clues_and_modulus_switch(clues, detection_key.params());
+
+    detect_lwes_with_key(detection_key, first_level_lut, second_level_lut, &clues)
+}
+
+/// Detects clues extracted by [`extract_clues_and_modulus_switch`] with `detection_key`.
+fn detect_lwes_with_key(
+    detection_key: &DetectionKey,
+    first_level_lut: &FieldPolynomial<FirstLevelField>,
+    second_level_lut: &FieldPolynomial<SecondLevelField>,
+    clues: &[LweCiphertext<ClueValue>],
+) -> PertinencyCiphertext {
+    let params = detection_key.params();
+
+    let intermediate = first_level_bootstrapping(
+        clues,
+        detection_key.first_level_blind_rotation_key(),
+        detection_key.first_level_key_switching_key(),
+        first_level_lut,
+        params,
+    );
+
+    let ciphertext = second_level_bootstrapping(
+        intermediate,
+        detection_key.second_level_blind_rotation_key(),
+        second_level_lut,
+        params,
+    );
+
+    PertinencyCiphertext(hom_trace(
+        ciphertext,
+        detection_key.trace_key(),
+        detection_key.second_level_ring_dimension_inv(),
+        detection_key.second_level_blind_rotation_key().ntt_table(),
+    ))
+}
+
+/// Checks whether `pertinency` decrypts to an indicator of 1 under `key`.
+fn is_pertinent(key: &SecretKeyPack, pertinency: &PertinencyCiphertext) -> bool {
+    key.decrypt_pertinency(pertinency)[0] == 1
+}
+
+/// Runs `f` and returns its result with the elapsed time.
+fn timed<R>(f: impl FnOnce() -> R) -> (R, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+/// Returns `ops / elapsed` in operations per second, treating a zero `elapsed`
+/// as one nanosecond so the rate stays finite.
+fn ops_per_second(ops: usize, elapsed: Duration) -> f64 {
+    ops as f64 / elapsed.max(Duration::from_nanos(1)).as_secs_f64()
+}
+
 /// LUT for first-layer functional bootstrapping (homomorphic decryption).
 pub fn first_level_lut(
     rlwe_dimension: usize,
@@ -460,8 +1274,7 @@ pub fn first_level_lut(
     output_plain_modulus: usize,
 ) -> FieldPolynomial<FirstLevelField> {
     let q = <FirstLevelField as Field>::MODULUS_VALUE;
-    let log = output_plain_modulus.trailing_zeros() - 1;
-    let scale_one = ((q >> log) + 1) >> 1;
+    let scale_one = first_level_delta(output_plain_modulus);
     let scale_minus_one = q - scale_one;
     let log_plain_modulus = input_plain_modulus.trailing_zeros();
 
@@ -476,58 +1289,165 @@ pub fn first_level_lut(
 }
 
 /// LUT for second-layer functional bootstrapping (homomorphic checking).
+///
+/// It fires when the clue sum is in `match_sum - tolerance..=match_sum`, see
+/// [`OmrParameters::match_sum_value`] and [`OmrParameters::mismatch_tolerance`].
 pub fn second_level_lut(
     rlwe_dimension: usize,
     clue_count: usize,
+    match_sum: usize,
+    tolerance: usize,
     input_plain_modulus: usize,
     output_plain_modulus: usize,
 ) -> FieldPolynomial<SecondLevelField> {
-    let q = <SecondLevelField as Field>::MODULUS_VALUE;
-    let scale_one = if output_plain_modulus.is_power_of_two() {
-        let log = output_plain_modulus.trailing_zeros() - 1;
-        ((q >> log) + 1) >> 1
-    } else {
-        let delta = BigDecimal::from_u64(q).unwrap() / (output_plain_modulus as u64);
-        delta
-            .with_scale_round(0, RoundingMode::HalfUp)
-            .to_u64()
-            .unwrap()
-    };
     let log_plain_modulus = input_plain_modulus.trailing_zeros();
 
+    second_level_lut_data(
+        clue_count,
+        match_sum,
+        tolerance,
+        input_plain_modulus,
+        second_level_delta(output_plain_modulus),
+    )
+    .as_slice()
+    .negacyclic_lut(rlwe_dimension, log_plain_modulus)
+}
+
+/// The values of the second level LUT on the intermediate plaintexts `0..input_plain_modulus`.
+///
+/// The intermediate plaintext is the clue sum plus `clue_count`, so the LUT fires from
+/// `match_sum - tolerance + clue_count` to `match_sum + clue_count`.
+fn second_level_lut_data(
+    clue_count: usize,
+    match_sum: usize,
+    tolerance: usize,
+    input_plain_modulus: usize,
+    scale_one: OutputValue,
+) -> Vec<OutputValue> {
     let mut data = vec![SecondLevelField::ZERO; input_plain_modulus];
-    data[clue_count * 2] = scale_one;
+    data[match_sum - tolerance + clue_count..=match_sum + clue_count].fill(scale_one);
+    data
+}
 
-    data.as_slice()
-        .negacyclic_lut(rlwe_dimension, log_plain_modulus)
+/// Returns the decision window of a detector built for `params`.
+///
+/// The window is read off the LUT data rather than recomputed, so it reports what
+/// the second level bootstrapping actually does. [`OmrParameters::validate`] keeps
+/// the tolerance below the clue count, so the window is never empty.
+fn decision_window(params: &OmrParameters) -> (usize, usize) {
+    let data = second_level_lut_data(
+        params.clue_count(),
+        params.match_sum_value(),
+        params.mismatch_tolerance(),
+        params.intermediate_lwe_plain_modulus_value() as usize,
+        second_level_delta(params.output_plain_modulus_value() as usize),
+    );
+    decision_window_of(&data, params.clue_count()).unwrap_or((
+        params.match_sum_value() - params.mismatch_tolerance(),
+        params.match_sum_value(),
+    ))
+}
+
+/// Returns the inclusive range of clue sums mapped to a nonzero value by the
+/// second level LUT `data`, or `None` if it never fires. The intermediate plaintext
+/// is the clue sum plus `clue_count`.
+fn decision_window_of(data: &[OutputValue], clue_count: usize) -> Option<(usize, usize)> {
+    let first = data.iter().position(|v| !v.is_zero())?;
+    let last = data.iter().rposition(|v| !v.is_zero())?;
+    Some((
+        first.checked_sub(clue_count)?,
+        last.checked_sub(clue_count)?,
+    ))
+}
+
+/// Generates a clue bundle of zeros under a throwaway clue key.
+fn baseline_clues<R>(params: &OmrParameters, rng: &mut R) -> CmLweCiphertext<ClueValue>
+where
+    R: Rng + CryptoRng,
+{
+    let clue_params = *params.clue_params();
+    let secret_key = LweSecretKey::generate(&clue_params, rng);
+    let public_key = LwePublicKeyRlweMode::new(&secret_key, &clue_params, rng);
+    ClueKey::new(public_key, clue_params).gen_clues(params.clue_count(), rng)
+}
+
+/// Extracts the clues of a bundle and switches them to the first level modulus, ready
+/// for [`Detector::detect_from_lwes`].
+///
+/// This needs no key, so the result can be shared by every recipient with `params`.
+///
+/// # Panics
+///
+/// Panics if the bundle doesn't hold `clue_count` clues of the clue dimension.
+#[inline]
+pub fn prepare_clues(
+    clues: &CmLweCiphertext<ClueValue>,
+    params: &OmrParameters,
+) -> Vec<LweCiphertext<ClueValue>> {
+    extract_clues_and_modulus_switch(clues, params)
 }
 
 fn extract_clues_and_modulus_switch(
     clues: &CmLweCiphertext<ClueValue>,
     params: &OmrParameters,
 ) -> Vec<LweCiphertext<ClueValue>> {
-    // Step 3a prep: extract LWE clues and switch modulus for first-layer bootstrapping.
     let clue_count = params.clue_count();
     assert_eq!(clue_count, clues.msg_count(), "Invalid clue count.");
 
-    // Extract clues
+    extract_any_clues_and_modulus_switch(clues, params).expect("Invalid clue dimension.")
+}
+
+/// [`extract_clues_and_modulus_switch`] for a bundle of any clue count, returning
+/// [`OmrError::DimensionMismatch`] for clues not of the clue dimension.
+fn extract_any_clues_and_modulus_switch(
+    clues: &CmLweCiphertext<ClueValue>,
+    params: &OmrParameters,
+) -> Result<Vec<LweCiphertext<ClueValue>>, OmrError> {
+    // Step 3a prep: extract LWE clues and switch modulus for first-layer bootstrapping.
+
+    // The clues of a bundle share one `a`, so checking it checks every clue.
+    check_clue_dimension(clues.a().len(), params)?;
+
+    // Extract clues. This allocation can't be skipped: the clues of a bundle share one
+    // `a`, and each extracted clue needs its own rotation of it.
     let mut clues: Vec<LweCiphertext<ClueValue>> = clues.extract_all(params.clue_cipher_modulus());
 
-    let clue_cipher_modulus_value = params.clue_cipher_modulus_value();
-    let first_level_ring_dimension = params.first_level_ring_dimension();
+    if clue_modulus_is_aligned(params) {
+        return Ok(clues);
+    }
 
     // Modulus switching to `2 * N_1`
-    let twice_first_level_ring_dimension = first_level_ring_dimension as ClueValue * 2;
-    if clue_cipher_modulus_value != ModulusValue::PowerOf2(twice_first_level_ring_dimension) {
-        clues.iter_mut().for_each(|clue| {
-            lwe_modulus_switch_assign(
-                clue,
-                clue_cipher_modulus_value,
-                twice_first_level_ring_dimension,
-            );
+    let clue_cipher_modulus_value = params.clue_cipher_modulus_value();
+    let twice_first_level_ring_dimension = params.first_level_ring_dimension() as ClueValue * 2;
+    clues.iter_mut().for_each(|clue| {
+        lwe_modulus_switch_assign(
+            clue,
+            clue_cipher_modulus_value,
+            twice_first_level_ring_dimension,
+        );
+    });
+    Ok(clues)
+}
+
+/// Checks that a clue has the clue dimension of `params`: the first level blind
+/// rotation key has one entry per clue secret key coefficient.
+fn check_clue_dimension(dimension: usize, params: &OmrParameters) -> Result<(), OmrError> {
+    if dimension != params.clue_dimension() {
+        return Err(OmrError::DimensionMismatch {
+            expected: params.clue_dimension(),
+            found: dimension,
         });
     }
-    clues
+    Ok(())
+}
+
+/// Checks whether the clue cipher modulus is already `2 * N_1`, the modulus the first
+/// level blind rotation reads clues in, so no modulus switch is needed.
+///
+/// This holds for [`OmrParameters::new`], with `q = 2048` and `N_1 = 1024`.
+fn clue_modulus_is_aligned(params: &OmrParameters) -> bool {
+    let twice_first_level_ring_dimension = params.first_level_ring_dimension() as ClueValue * 2;
+    params.clue_cipher_modulus_value() == ModulusValue::PowerOf2(twice_first_level_ring_dimension)
 }
 
 fn first_level_bootstrapping(
@@ -537,6 +1457,21 @@ fn first_level_bootstrapping(
     lut: &FieldPolynomial<FirstLevelField>,
     params: &OmrParameters,
 ) -> LweCiphertext<InterLweValue> {
+    let mut intermediate =
+        first_level_bootstrapping_raw(clues, blind_rotation_key, key_switching_key, lut, params);
+
+    add_clue_count(&mut intermediate, params.clue_count(), params);
+
+    intermediate
+}
+
+fn first_level_bootstrapping_raw(
+    clues: &[LweCiphertext<ClueValue>],
+    blind_rotation_key: &BlindRotationKey<FirstLevelField>,
+    key_switching_key: &NonPowOf2LweKeySwitchingKey<<FirstLevelField as Field>::ValueT>,
+    lut: &FieldPolynomial<FirstLevelField>,
+    params: &OmrParameters,
+) -> LweCiphertext<InterLweValue> {
     // Step 3a: first-layer functional bootstrapping (homomorphic decryption).
     // Aggregate clue ciphertexts and switch to the intermediate LWE key.
     let first_level_ring_dimension = params.first_level_ring_dimension();
@@ -550,11 +1485,15 @@ fn first_level_bootstrapping(
     //         || <RlweCiphertext<FirstLevelField>>::zero(first_level_ring_dimension),
     //         |acc, c| acc.add_element_wise(&c),
     //     );
-    let intermediate = clues
-        .iter()
-        .map(|c| blind_rotation_key.blind_rotate(lut.clone(), c))
-        .reduce(|acc, ele| acc.add_element_wise(&ele))
-        .unwrap_or_else(|| <RlweCiphertext<FirstLevelField>>::zero(first_level_ring_dimension));
+    // `blind_rotate` takes the LUT by value as its accumulator and rotates it in place,
+    // so each rotation needs its own copy; the clone is that copy, not an extra one.
+    let intermediate = blind_rotate_and_sum(
+        clues,
+        |x, y| x.a() == y.a() && x.b() == y.b(),
+        |c| blind_rotation_key.blind_rotate(lut.clone(), c),
+        |acc, ele| acc.add_element_wise(ele),
+    )
+    .unwrap_or_else(|| <RlweCiphertext<FirstLevelField>>::zero(first_level_ring_dimension));
 
     // Key switching
     let intermediate = key_switching_key.key_switch(
@@ -562,22 +1501,47 @@ fn first_level_bootstrapping(
         FirstLevelField::MODULUS,
     );
 
+    // Modulus switching
+    lwe_modulus_switch(
+        &intermediate,
+        params.first_level_blind_rotation_params().modulus,
+        params.intermediate_lwe_params().cipher_modulus_value,
+    )
+}
+
+/// Blind rotates every clue and sums the results.
+///
+/// Opportunistic fast path: when all clues are identical ciphertexts, as with a clue
+/// replicated `clue_count` times in tests and benchmarks, a single blind rotation is
+/// computed and added `clue_count` times. Real clue bundles never hit this path.
+fn blind_rotate_and_sum<C, T: Clone>(
+    clues: &[C],
+    is_same: impl Fn(&C, &C) -> bool,
+    rotate: impl Fn(&C) -> T,
+    add: impl Fn(T, &T) -> T,
+) -> Option<T> {
+    let (first, rest) = clues.split_first()?;
+    if rest.iter().all(|c| is_same(first, c)) {
+        let single = rotate(first);
+        Some(rest.iter().fold(single.clone(), |acc, _| add(acc, &single)))
+    } else {
+        clues.iter().map(rotate).reduce(|acc, ele| add(acc, &ele))
+    }
+}
+
+fn add_clue_count(
+    intermediate: &mut LweCiphertext<InterLweValue>,
+    clue_count: usize,
+    params: &OmrParameters,
+) {
     let intermediate_lwe_params = params.intermediate_lwe_params();
     let intermediate_cipher_modulus_value = intermediate_lwe_params.cipher_modulus_value;
     let intermediate_cipher_modulus = intermediate_lwe_params.cipher_modulus;
     let intermediate_plain_modulus_value = intermediate_lwe_params.plain_modulus_value;
 
-    // Modulus switching
-    let mut intermediate = lwe_modulus_switch(
-        &intermediate,
-        params.first_level_blind_rotation_params().modulus,
-        intermediate_cipher_modulus_value,
-    );
-
     let log_plain_modulus = intermediate_plain_modulus_value.trailing_zeros();
 
     // Add `clue count`
-    let clue_count = params.clue_count();
     let scale = (clue_count as InterLweValue) * {
         match intermediate_cipher_modulus_value {
             ModulusValue::Native => 1 << (InterLweValue::BITS - log_plain_modulus),
@@ -592,8 +1556,6 @@ fn first_level_bootstrapping(
         intermediate.b_mut(),
         intermediate_cipher_modulus.reduce(scale),
     );
-
-    intermediate
 }
 
 fn second_level_bootstrapping(
@@ -623,6 +1585,15 @@ fn second_level_bootstrapping(
     blind_rotation_key.blind_rotate(lut.clone(), &intermediate)
 }
 
+/// Derives the bucket sampler of the `chunk_i`-th chunk from `seed`.
+fn chunk_rng(mut seed: [u8; 32], chunk_i: usize) -> StdRng {
+    seed[24..]
+        .iter_mut()
+        .zip((chunk_i as u64).to_le_bytes())
+        .for_each(|(s, c)| *s ^= c);
+    StdRng::from_seed(seed)
+}
+
 fn hom_trace(
     mut ciphertext: RlweCiphertext<SecondLevelField>,
     trace_key: &TraceKey<SecondLevelField>,
@@ -637,3 +1608,681 @@ fn hom_trace(
     // Homomorphic Trace
     trace_key.trace(&ciphertext).to_ntt_rlwe(ntt_table)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestSetup;
+
+    #[test]
+    fn batch_timer_covers_the_whole_batch() {
+        let pause = Duration::from_millis(5);
+        let (results, elapsed) = timed(|| {
+            (0..3)
+                .map(|i| {
+                    std::thread::sleep(pause);
+                    i
+                })
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(results, [0, 1, 2]);
+        assert!(elapsed >= pause * 3);
+    }
+
+    #[test]
+    fn batch_apis_detect_like_single_detection() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let setup = TestSetup::shared();
+        let detector = &setup.detector;
+        let board = setup.gen_board(&[true, false], &mut rng);
+        let expected = [1, 0];
+
+        assert_eq!(setup.indicators(&detector.detect_batch(&board)), expected);
+
+        let mut out = vec![detector.detect(&board[1]); board.len()];
+        detector.detect_batch_into(&board, &mut out);
+        assert_eq!(setup.indicators(&out), expected);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let in_pool = detector.detect_batch_in_pool(&board, &pool);
+        assert_eq!(setup.indicators(&in_pool), expected);
+
+        // The batch duration lies within the wall time around the call.
+        let outer = Instant::now();
+        let (timed, elapsed) = detector.time_batch(&board);
+        assert!(!elapsed.is_zero() && elapsed <= outer.elapsed());
+        assert_eq!(setup.indicators(&timed), expected);
+
+        assert!(detector.any_match(&board, &setup.secret_key));
+        assert!(!detector.any_match(&board[1..], &setup.secret_key));
+
+        let rate = detector.bench_detect(&board[0], 2);
+        assert!(rate > 0.0 && rate.is_finite());
+    }
+
+    #[test]
+    fn shard_combinations_sum_to_full_combination() {
+        let mut rng = rand::thread_rng();
+        let ntt_table = SecondLevelField::generate_ntt_table(10).unwrap();
+        let ring_dimension = ntt_table.dimension();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let p = 257;
+
+        let all_payloads_count = 6;
+        let cmb_count_per_cipher = 1;
+        let combination_count = 3;
+
+        let pertinency_vector = (0..all_payloads_count)
+            .map(|_| {
+                let mut ct = NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension);
+                ct.a_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                ct.b_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                PertinencyCiphertext::from_ntt(ct)
+            })
+            .collect::<Vec<_>>();
+        let payloads = (0..all_payloads_count)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let weights = (0..combination_count * all_payloads_count)
+            .map(|_| rng.gen_range(0..p as PayloadByteType))
+            .collect::<Vec<_>>();
+
+        let combine = |range: std::ops::Range<usize>| {
+            combine_payloads(
+                &ntt_table,
+                p,
+                &pertinency_vector[range.clone()],
+                &payloads[range.clone()],
+                &weights,
+                all_payloads_count,
+                cmb_count_per_cipher,
+                range.start,
+            )
+        };
+
+        let full = combine(0..all_payloads_count);
+        let mut summed = combine(0..2);
+        for (acc, x) in summed.iter_mut().zip(combine(2..all_payloads_count)) {
+            acc.add_assign_element_wise(&x);
+        }
+
+        assert_eq!(full.len(), combination_count);
+        for (x, y) in full.iter().zip(summed.iter()) {
+            assert_eq!(x.a().as_slice(), y.a().as_slice());
+            assert_eq!(x.b().as_slice(), y.b().as_slice());
+        }
+    }
+
+    #[test]
+    fn coeff_domain_combinations_match_ntt_combine() {
+        let mut rng = rand::thread_rng();
+        let ntt_table = SecondLevelField::generate_ntt_table(10).unwrap();
+        let ring_dimension = ntt_table.dimension();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let p = 257;
+
+        let all_payloads_count = 6;
+        let combination_count = 2;
+
+        let pertinency_vector = (0..all_payloads_count)
+            .map(|_| {
+                let mut ct = NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension);
+                ct.a_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                ct.b_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                PertinencyCiphertext::from_ntt(ct)
+            })
+            .collect::<Vec<_>>();
+        let payloads = (0..all_payloads_count)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let weights = (0..combination_count * all_payloads_count)
+            .map(|_| rng.gen_range(0..p as PayloadByteType))
+            .collect::<Vec<_>>();
+
+        let combine = |range: std::ops::Range<usize>| {
+            combine_payloads(
+                &ntt_table,
+                p,
+                &pertinency_vector[range.clone()],
+                &payloads[range.clone()],
+                &weights,
+                all_payloads_count,
+                1,
+                range.start,
+            )
+        };
+
+        let direct = combine(0..all_payloads_count);
+
+        // Sum two shards in the coefficient domain, then transform once.
+        let mut summed = combinations_to_coeff(&ntt_table, &combine(0..3));
+        for (acc, x) in summed.iter_mut().zip(combinations_to_coeff(
+            &ntt_table,
+            &combine(3..all_payloads_count),
+        )) {
+            acc.add_assign_element_wise(&x);
+        }
+        let converted = combinations_to_ntt(&ntt_table, &summed);
+
+        assert_eq!(converted.len(), direct.len());
+        for (x, y) in converted.iter().zip(direct.iter()) {
+            assert_eq!(x.a().as_slice(), y.a().as_slice());
+            assert_eq!(x.b().as_slice(), y.b().as_slice());
+        }
+    }
+
+    #[test]
+    fn combine_runs_inside_a_custom_pool() {
+        let mut rng = rand::thread_rng();
+        let ntt_table = SecondLevelField::generate_ntt_table(10).unwrap();
+        let ring_dimension = ntt_table.dimension();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+
+        let pertinency_vector = (0..4)
+            .map(|_| {
+                let mut ct = NttRlweCiphertext::<SecondLevelField>::zero(ring_dimension);
+                ct.b_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                PertinencyCiphertext::from_ntt(ct)
+            })
+            .collect::<Vec<_>>();
+        let payloads = (0..4)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let weights = (0..8).map(|_| rng.gen_range(0..257)).collect::<Vec<_>>();
+        let combine = || {
+            combine_payloads(
+                &ntt_table,
+                257,
+                &pertinency_vector,
+                &payloads,
+                &weights,
+                4,
+                1,
+                0,
+            )
+        };
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let (threads, in_pool) = pool.install(|| (rayon::current_num_threads(), combine()));
+        let outside = combine();
+
+        assert_eq!(threads, 2);
+        for (x, y) in in_pool.iter().zip(outside.iter()) {
+            assert_eq!(x.b().as_slice(), y.b().as_slice());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn blocking_work_is_awaited() {
+        let caller = std::thread::current().id();
+        let (value, worker) = run_blocking(|| (6 * 7, std::thread::current().id())).await;
+
+        assert_eq!(value, 42);
+        assert_ne!(worker, caller);
+
+        let setup = TestSetup::shared();
+        let clues = setup.sender.gen_clues(&mut StdRng::seed_from_u64(37));
+
+        let pertinency = setup.detector.detect_async(clues).await;
+        assert_eq!(setup.indicators(&[pertinency]), [1]);
+    }
+
+    #[test]
+    fn baseline_clues_do_not_match() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let secret_key = &TestSetup::shared().secret_key;
+
+        let clues = baseline_clues(secret_key.parameters(), &mut rng);
+        assert_eq!(clues.msg_count(), secret_key.parameters().clue_count());
+        assert!(!secret_key.diagnose_clue_bundle(&clues).is_empty());
+    }
+
+    #[test]
+    fn pertinency_domains_round_trip() {
+        let mut rng = rand::thread_rng();
+        let ntt_table = SecondLevelField::generate_ntt_table(10).unwrap();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+
+        let mut ct = NttRlweCiphertext::<SecondLevelField>::zero(ntt_table.dimension());
+        ct.a_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+        ct.b_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+        let pertinency = PertinencyCiphertext::from_ntt(ct);
+
+        let coeff = pertinency.to_coeff(&ntt_table);
+        let back = PertinencyCiphertext::from_coeff(&coeff, &ntt_table);
+        assert_eq!(
+            back.as_ntt().a().as_slice(),
+            pertinency.as_ntt().a().as_slice()
+        );
+        assert_eq!(
+            back.as_ntt().b().as_slice(),
+            pertinency.as_ntt().b().as_slice()
+        );
+
+        let again = back.to_coeff(&ntt_table);
+        assert_eq!(again.a().as_slice(), coeff.a().as_slice());
+        assert_eq!(again.b().as_slice(), coeff.b().as_slice());
+    }
+
+    #[test]
+    fn tiny_bucket_count_reports_collisions() {
+        let setup = TestSetup::shared();
+        let detector = &setup.detector;
+        let ntt_table = setup.secret_key.second_level_ntt_table();
+        let polynomial_size = ntt_table.dimension();
+
+        let zero = RlweCiphertext::<SecondLevelField>::zero(polynomial_size);
+        let board = vec![PertinencyCiphertext::from_coeff(&zero, ntt_table); 16];
+
+        // Two buckets per segment cannot hold 16 messages without collisions.
+        let params = <RetrievalParams<SecondLevelField>>::new(257, polynomial_size, 16, 4, 2, 4, 1);
+        let (_, stats) = detector.encode_pertinent_indices_with_stats(params, &board);
+
+        let segments = polynomial_size / params.slots_per_segment();
+        assert!(stats.collisions >= segments * (16 - 2));
+    }
+
+    #[test]
+    fn seeded_index_encoding_is_reproducible() {
+        let mut rng = StdRng::seed_from_u64(21);
+        let setup = TestSetup::shared();
+        let detector = &setup.detector;
+        let ntt_table = setup.secret_key.second_level_ntt_table();
+        let polynomial_size = ntt_table.dimension();
+
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let board = (0..16)
+            .map(|_| {
+                let mut ct = NttRlweCiphertext::<SecondLevelField>::zero(polynomial_size);
+                ct.a_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                ct.b_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+                PertinencyCiphertext::from_ntt(ct)
+            })
+            .collect::<Vec<_>>();
+
+        let params = <RetrievalParams<SecondLevelField>>::new(257, polynomial_size, 16, 4, 8, 4, 1);
+        let seed = [7u8; 32];
+        let first = detector.encode_pertinent_indices_seeded(params, &board, seed);
+        let second = detector.encode_pertinent_indices_seeded(params, &board, seed);
+        let other = detector.encode_pertinent_indices_seeded(params, &board, [8u8; 32]);
+
+        assert_eq!(first.a(), second.a());
+        assert_eq!(first.b(), second.b());
+        assert_ne!(first.b(), other.b());
+    }
+
+    #[test]
+    fn lut_scales_decode_back() {
+        let params = OmrParameters::new();
+        assert!(verify_lut_decode_consistency(&params).is_ok());
+
+        // An off-by-one `log` doubles the scale.
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        assert!(symbols_round_trip(257, second_level_delta(257), q));
+        assert!(!symbols_round_trip(256, second_level_delta(128), q));
+    }
+
+    #[test]
+    fn cached_intermediates_detect_like_full_detection() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let setup = TestSetup::shared();
+        // Splitting the detector consumes it, so this test needs its own.
+        let detector = setup.secret_key.generate_detector(&mut rng);
+        let board = setup.gen_board(&[true, false], &mut rng);
+
+        let full = detector.detect_batch(&board);
+        let cached = board
+            .iter()
+            .map(|clues| detector.detect_intermediate(clues))
+            .collect::<Vec<_>>();
+
+        let full_size = detector.detect_key_size();
+        let detector = detector.into_second_level_only();
+        assert!(detector.detect_key_size() < full_size);
+
+        for (full, intermediate) in full.iter().zip(&cached) {
+            let ct = detector.detect_cached(intermediate);
+            assert_eq!(ct.as_ntt().a(), full.as_ntt().a());
+            assert_eq!(ct.as_ntt().b(), full.as_ntt().b());
+        }
+    }
+
+    #[test]
+    fn batch_into_matches_collected_batch() {
+        let inputs = (0..1000u64).collect::<Vec<_>>();
+        let f = |x: &u64| x * x + 1;
+
+        let collected = inputs.par_iter().map(f).collect::<Vec<_>>();
+        let mut out = vec![0; inputs.len()];
+        par_map_into(&inputs, &mut out, f);
+        assert_eq!(out, collected);
+
+        // The buffer is reused as is on the next round.
+        par_map_into(&inputs, &mut out, |x| x + 2);
+        assert_eq!(out[10], 12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn batch_into_rejects_wrong_length() {
+        let mut out = vec![0u64; 3];
+        par_map_into(&[1u64, 2], &mut out, |x| *x);
+    }
+
+    #[test]
+    fn identical_clues_rotate_once() {
+        use std::cell::Cell;
+
+        let rotations = Cell::new(0);
+        let rotate = |c: &u64| {
+            rotations.set(rotations.get() + 1);
+            c * 3 + 1
+        };
+        let add = |acc: u64, x: &u64| acc + x;
+        let is_same = |x: &u64, y: &u64| x == y;
+
+        let identical = [5u64; 7];
+        let fast = blind_rotate_and_sum(&identical, is_same, rotate, add).unwrap();
+        assert_eq!(rotations.get(), 1);
+        let naive = identical.iter().map(|c| c * 3 + 1).sum::<u64>();
+        assert_eq!(fast, naive);
+
+        rotations.set(0);
+        let distinct = [5u64, 5, 6, 5, 5, 5, 5];
+        let sum = blind_rotate_and_sum(&distinct, is_same, rotate, add).unwrap();
+        assert_eq!(rotations.get(), 7);
+        assert_eq!(sum, distinct.iter().map(|c| c * 3 + 1).sum::<u64>());
+
+        assert_eq!(blind_rotate_and_sum(&[], is_same, rotate, add), None);
+    }
+
+    #[test]
+    fn match_constants_drive_sender_and_detector() {
+        let setup = TestSetup::shared();
+        let params = setup.secret_key.parameters();
+
+        // Sender side: every clue decrypts to the match value.
+        let clues = setup.sender.gen_clues(&mut StdRng::seed_from_u64(17));
+        assert!(setup
+            .secret_key
+            .decrypt_clue_bundle(&clues)
+            .iter()
+            .all(|&c| c == params.match_clue_value()));
+
+        // Detector side: the first level LUT maps the match value to 1, and the second
+        // level LUT fires exactly when all clues do.
+        let info = params.encoding_info();
+        let first = first_level_lut(
+            params.first_level_ring_dimension(),
+            info.clue_plain_modulus as usize,
+            info.intermediate_plain_modulus as usize,
+        );
+        assert_eq!(
+            first.as_slice()[params.match_clue_value() as usize],
+            info.first_level_delta
+        );
+
+        let data = second_level_lut_data(
+            params.clue_count(),
+            params.match_sum_value(),
+            params.mismatch_tolerance(),
+            info.intermediate_plain_modulus as usize,
+            1,
+        );
+        assert_eq!(
+            decision_window_of(&data, params.clue_count()),
+            Some((params.match_sum_value(), params.match_sum_value()))
+        );
+    }
+
+    #[test]
+    fn category_lut_recovers_the_category() {
+        let mut rng = StdRng::seed_from_u64(24);
+        let TestSetup {
+            secret_key,
+            sender,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let params = secret_key.parameters();
+
+        let clue_count = params.clue_count();
+        let t = params.intermediate_lwe_plain_modulus_value() as usize;
+        let delta = second_level_delta(params.output_plain_modulus_value() as usize);
+
+        // Category `c` turns `c` clues into non-matching ones, so the clue sum is
+        // `clue_count - c`; the LUT outputs `c + 1`, keeping 0 for strangers.
+        let mut data = vec![SecondLevelField::ZERO; t];
+        for c in 0..4 {
+            data[clue_count - c + clue_count] = (c as OutputValue + 1) * delta;
+        }
+        let lut = data
+            .as_slice()
+            .negacyclic_lut(params.second_level_ring_dimension(), t.trailing_zeros());
+
+        let clue_modulus = params.clue_params().cipher_modulus;
+        let clue_delta = (params.modulus_summary().clue_cipher_modulus
+            / params.clue_plain_modulus_value() as u64) as ClueValue;
+        for c in 0..4 {
+            let mut clues = sender.gen_clues(&mut rng);
+            clues.b_mut()[..c]
+                .iter_mut()
+                .for_each(|b| clue_modulus.reduce_add_assign(b, clue_delta));
+
+            let category =
+                secret_key.decrypt_pertinency(&detector.detect_with_lut(&clues, &lut))[0];
+            assert_eq!(category, c as OutputValue + 1);
+        }
+    }
+
+    #[test]
+    fn raw_intermediate_skips_the_clue_count_offset() {
+        let TestSetup {
+            secret_key,
+            sender,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let params = secret_key.parameters();
+        let clues = sender.gen_clues(&mut StdRng::seed_from_u64(37));
+
+        let clue_count = params.clue_count() as InterLweValue;
+        let t = params.intermediate_lwe_plain_modulus_value();
+        let with_add = secret_key.decrypt_intermediate(&detector.detect_intermediate(&clues));
+        let raw = secret_key.decrypt_intermediate(&detector.detect_intermediate_raw(&clues));
+
+        // A match sums to `clue_count`, and the add moves it to `clue_count * 2`.
+        assert_eq!(raw, clue_count);
+        assert_eq!(with_add, clue_count * 2);
+        assert_eq!((with_add + t - raw) % t, clue_count);
+    }
+
+    #[test]
+    fn one_detector_serves_two_clue_counts() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let TestSetup {
+            secret_key,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let other_key = crate::KeyGen::generate_secret_key(OmrParameters::new(), &mut rng);
+        let clue_key = secret_key.generate_clue_key(&mut rng);
+        let other_clue_key = other_key.generate_clue_key(&mut rng);
+
+        for clue_count in [5, 7] {
+            let clues = clue_key.gen_clues(clue_count, &mut rng);
+            let pertinency = detector.detect_with_clue_count(&clues, clue_count).unwrap();
+            assert!(is_pertinent(secret_key, &pertinency));
+
+            let clues = other_clue_key.gen_clues(clue_count, &mut rng);
+            let pertinency = detector.detect_with_clue_count(&clues, clue_count).unwrap();
+            assert!(!is_pertinent(secret_key, &pertinency));
+        }
+
+        let clues = clue_key.gen_clues(5, &mut rng);
+        assert!(matches!(
+            detector.detect_with_clue_count(&clues, 6),
+            Err(OmrError::ClueCountMismatch {
+                expected: 6,
+                found: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn prepared_clues_are_shared_by_recipients() {
+        let mut rng = StdRng::seed_from_u64(31);
+        let setup = TestSetup::shared();
+        let params = setup.secret_key.parameters();
+
+        // Preparing needs no key, so the same prepared clues serve any recipient.
+        let board = setup.gen_board(&[true, false], &mut rng);
+        for (clues, expected) in board.iter().zip([1, 0]) {
+            let prepared = prepare_clues(clues, params);
+            let shared = setup.detector.detect_from_lwes(&prepared).unwrap();
+            let direct = setup.detector.detect(clues);
+            assert_eq!(shared.as_ntt().b(), direct.as_ntt().b());
+            assert_eq!(setup.indicators(&[shared]), [expected]);
+        }
+    }
+
+    #[test]
+    fn wider_clues_round_trip_one_detection() {
+        let mut rng = StdRng::seed_from_u64(53);
+        let params = OmrParameters::new().with_clue_dimension(600).unwrap();
+        let secret_key = crate::KeyGen::generate_secret_key(params.clone(), &mut rng);
+        let detector = secret_key.generate_detector(&mut rng);
+
+        let clues = secret_key.generate_sender(&mut rng).gen_clues(&mut rng);
+        assert_eq!(clues.a().len(), 600);
+        let pertinency = detector.detect_from_lwes(&prepare_clues(&clues, &params));
+        assert_eq!(secret_key.decrypt_pertinency(&pertinency.unwrap())[0], 1);
+
+        // Clues of the default dimension don't fit the wider key.
+        let narrow_key = &TestSetup::shared().secret_key;
+        let narrow_params = narrow_key.parameters();
+        let narrow = narrow_key.generate_sender(&mut rng).gen_clues(&mut rng);
+        assert!(matches!(
+            detector.detect_from_lwes(&prepare_clues(&narrow, narrow_params)),
+            Err(OmrError::DimensionMismatch {
+                expected: 600,
+                found: 512
+            })
+        ));
+        let narrow = narrow_key
+            .generate_clue_key(&mut rng)
+            .gen_clues(5, &mut rng);
+        assert!(matches!(
+            detector.detect_with_clue_count(&narrow, 5),
+            Err(OmrError::DimensionMismatch {
+                expected: 600,
+                found: 512
+            })
+        ));
+    }
+
+    #[test]
+    fn default_clues_skip_the_modulus_switch() {
+        let setup = TestSetup::shared();
+        let params = setup.secret_key.parameters();
+        assert!(clue_modulus_is_aligned(params));
+
+        let clues = setup.sender.gen_clues(&mut StdRng::seed_from_u64(29));
+
+        let extracted = extract_clues_and_modulus_switch(&clues, params);
+        let raw = clues.extract_all(params.clue_cipher_modulus());
+        assert_eq!(extracted.len(), raw.len());
+        for (x, y) in extracted.iter().zip(raw.iter()) {
+            assert_eq!(x.a(), y.a());
+            assert_eq!(x.b(), y.b());
+        }
+    }
+
+    #[test]
+    fn decision_window_follows_tolerance() {
+        let TestSetup { detector, .. } = TestSetup::shared();
+        let params = OmrParameters::new();
+        let clue_count = params.clue_count();
+
+        assert_eq!(detector.decision_window(), (clue_count, clue_count));
+        assert_eq!(decision_window(&params), (clue_count, clue_count));
+
+        // Accepting one mismatching clue widens the window by one.
+        let tolerant = params.clone().with_mismatch_tolerance(1).unwrap();
+        assert_eq!(decision_window(&tolerant), (clue_count - 1, clue_count));
+
+        assert!(matches!(
+            params.with_mismatch_tolerance(clue_count),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+        assert_eq!(decision_window_of(&[0; 32], clue_count), None);
+    }
+
+    #[test]
+    fn ops_per_second_is_positive_and_finite() {
+        for elapsed in [
+            Duration::ZERO,
+            Duration::from_millis(3),
+            Duration::from_secs(2),
+        ] {
+            let rate = ops_per_second(10, elapsed);
+            assert!(rate > 0.0 && rate.is_finite());
+        }
+        assert_eq!(ops_per_second(10, Duration::from_secs(2)), 5.0);
+    }
+
+    #[test]
+    fn time_infos_sum_like_the_manual_fold() {
+        let infos = (1..=10u64)
+            .map(|i| DetectTimeInfoPerMessage {
+                detect_time: Duration::from_millis(10 * i),
+                first_level_bootstrapping_time: Duration::from_millis(5 * i),
+                second_level_bootstrapping_time: Duration::from_millis(4 * i),
+                trace_time: Duration::from_millis(i),
+            })
+            .collect::<Vec<_>>();
+
+        let folded = infos
+            .iter()
+            .fold(DetectTimeInfo::default(), |acc, &info| acc + info);
+        let summed: DetectTimeInfo = infos.iter().copied().sum();
+        let collected: DetectTimeInfo = infos.par_iter().copied().collect();
+
+        for total in [summed, collected] {
+            assert_eq!(total.total_detect_time, folded.total_detect_time);
+            assert_eq!(
+                total.total_first_level_bootstrapping_time,
+                folded.total_first_level_bootstrapping_time
+            );
+            assert_eq!(
+                total.total_second_level_bootstrapping_time,
+                folded.total_second_level_bootstrapping_time
+            );
+            assert_eq!(total.total_trace_time, folded.total_trace_time);
+        }
+    }
+
+    #[test]
+    fn zero_stage_timing_is_unreliable() {
+        let coarse = DetectTimeInfoPerMessage {
+            detect_time: Duration::from_millis(15),
+            ..Default::default()
+        };
+        assert!(!coarse.is_reliable());
+
+        let fine = DetectTimeInfoPerMessage {
+            detect_time: Duration::from_millis(15),
+            first_level_bootstrapping_time: Duration::from_millis(8),
+            second_level_bootstrapping_time: Duration::from_millis(6),
+            trace_time: Duration::from_millis(1),
+        };
+        assert!(fine.is_reliable());
+    }
+}
@@ -3,6 +3,43 @@
 /// Errors returned by OMR core helpers.
 #[derive(thiserror::Error, Debug)]
 pub enum OmrError {
-    #[error("Matrix is not invertible")]
-    InvertibleMatrix,
-}
+    #[error("Matrix is not invertible")]
+    InvertibleMatrix,
+    #[error("Not enough combinations to solve: fed {fed}, needed {needed}")]
+    InsufficientCombinations { fed: usize, needed: usize },
+    #[error("Clue count mismatch: expected {expected}, found {found}")]
+    ClueCountMismatch { expected: usize, found: usize },
+    #[error("Parameter mismatch: expected fingerprint {expected:#x}, found {found:#x}")]
+    ParameterMismatch { expected: u64, found: u64 },
+    #[error("Modulus mismatch: expected {expected}, found {found}")]
+    ModulusMismatch { expected: u64, found: u64 },
+    #[error("Dimension mismatch: expected {expected}, found {found}")]
+    DimensionMismatch { expected: usize, found: usize },
+    #[error("Invalid length: expected {expected} bytes, found {found}")]
+    InvalidLength { expected: usize, found: usize },
+    #[error("Index out of range: index {index}, bound {bound}")]
+    IndexOutOfRange { index: usize, bound: usize },
+    #[error("Clues do not fit parameter generation {generation}")]
+    UnknownGeneration { generation: u8 },
+    #[error("Cannot pick a parameter generation: {candidates} candidates fit the clues")]
+    AmbiguousGeneration { candidates: usize },
+    #[error("No parameter generation fits the clues")]
+    NoMatchingGeneration,
+    #[error("Too many indices: digest decodes to more than {bound}")]
+    TooManyIndices { bound: usize },
+    #[error("Matrix has no rows")]
+    EmptyMatrix,
+    #[error("Decoded value overflows 64 bits")]
+    DecodeOverflow,
+    #[error("Decoded value {value} is neither 0 nor 1")]
+    DecodeOutOfRange { value: u64 },
+    #[error("Value {value} is not reduced modulo {modulus}")]
+    UnreducedValue { value: u64, modulus: u64 },
+    #[error("Invalid parameters: {reason}")]
+    InvalidParameters { reason: &'static str },
+    #[error("Polynomial size {polynomial_size} is smaller than one segment of {slots_per_segment} slots")]
+    InsufficientPolynomialSize {
+        polynomial_size: usize,
+        slots_per_segment: usize,
+    },
+}
@@ -1,12 +1,16 @@
 //! Clue key for sender-side clue encryption.
 
-use algebra::utils::Size;
+use algebra::{reduce::ReduceAddAssign, utils::Size};
 use fhe_core::{CmLweCiphertext, LweParameters, LwePublicKeyRlweMode};
 use rand::{CryptoRng, Rng};
 
-use crate::{ClueModulus, ClueValue};
+use crate::{ClueModulus, ClueValue, OmrError, MATCH_CLUE_VALUE};
 
 /// RLWE public key used to encrypt the clue string.
+///
+/// Clue bundles have no seed-compressed form, so a detector always receives `a` in
+/// full: `a` follows from the encryption randomness, and a seed reproducing it would
+/// let anyone recompute the mask of `b` and tell whether the clues match this key.
 pub struct ClueKey {
     key: LwePublicKeyRlweMode<ClueValue>,
     params: LweParameters<ClueValue, ClueModulus>,
@@ -22,16 +26,79 @@ impl ClueKey {
         Self { key, params }
     }
 
-    /// Generates a clue which contains `count` 0.
+    /// Generates a clue which contains `count` times [`MATCH_CLUE_VALUE`].
+    ///
+    /// A board needs one bundle per message, generated by calling this in a loop.
+    /// There is no bulk variant: every bundle needs fresh encryption randomness, and
+    /// [`LwePublicKeyRlweMode`] exposes no per-key precomputation to share across them.
     #[inline]
     pub fn gen_clues<R>(&self, count: usize, rng: &mut R) -> CmLweCiphertext<ClueValue>
     where
         R: Rng + CryptoRng,
     {
-        let messages = vec![0; count];
+        let messages = vec![MATCH_CLUE_VALUE; count];
         self.key
             .encrypt_multi_messages(&messages, &self.params, rng)
     }
+
+    /// Checks that `clues` has the shape this [`ClueKey`] encrypts to and holds
+    /// `count` clues.
+    ///
+    /// A public key that does not match its parameters, e.g. a corrupted or
+    /// truncated one, produces bundles that fail this check.
+    pub fn check_clues(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+        count: usize,
+    ) -> Result<(), OmrError> {
+        if clues.a().len() != self.params.dimension {
+            return Err(OmrError::DimensionMismatch {
+                expected: self.params.dimension,
+                found: clues.a().len(),
+            });
+        }
+        if clues.msg_count() != count {
+            return Err(OmrError::ClueCountMismatch {
+                expected: count,
+                found: clues.msg_count(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Re-randomizes a clue bundle by homomorphically adding a fresh encryption of zeros.
+    ///
+    /// Two bundles of the same sender for the same recipient are otherwise linkable by
+    /// whoever relays them. After re-randomization a bundle is indistinguishable from an
+    /// independent encryption under the public key, while still decrypting to the same
+    /// values, so detection is unaffected. The noise is the sum of two fresh noises,
+    /// which stays well inside the first level LUT's decision window.
+    pub fn rerandomize_clues<R>(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+        rng: &mut R,
+    ) -> CmLweCiphertext<ClueValue>
+    where
+        R: Rng + CryptoRng,
+    {
+        let modulus = self.params.cipher_modulus;
+        // Zeros rather than `gen_clues`, which encrypts `MATCH_CLUE_VALUE`.
+        let zeros = vec![0; clues.msg_count()];
+        let mut rerandomized = self.key.encrypt_multi_messages(&zeros, &self.params, rng);
+
+        rerandomized
+            .a_mut()
+            .iter_mut()
+            .zip(clues.a().iter())
+            .for_each(|(x, &y)| modulus.reduce_add_assign(x, y));
+        rerandomized
+            .b_mut()
+            .iter_mut()
+            .zip(clues.b().iter())
+            .for_each(|(x, &y)| modulus.reduce_add_assign(x, y));
+
+        rerandomized
+    }
 }
 
 impl Size for ClueKey {
@@ -40,3 +107,69 @@ impl Size for ClueKey {
         self.key.size()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fhe_core::LweParameters;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::test_utils::TestSetup;
+
+    #[test]
+    fn rerandomized_clues_still_decrypt_to_zero() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let TestSetup {
+            secret_key,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let clue_key = secret_key.generate_clue_key(&mut rng);
+
+        let clues = clue_key.gen_clues(7, &mut rng);
+        let rerandomized = clue_key.rerandomize_clues(&clues, &mut rng);
+
+        assert_eq!(rerandomized.msg_count(), clues.msg_count());
+        assert!(secret_key.diagnose_clue_bundle(&rerandomized).is_empty());
+        assert_ne!(rerandomized.a(), clues.a());
+        assert_ne!(rerandomized.b(), clues.b());
+        assert_eq!(
+            secret_key.decrypt_clue_bundle(&rerandomized),
+            secret_key.decrypt_clue_bundle(&clues)
+        );
+
+        let pertinency = detector.detect(&rerandomized);
+        assert_eq!(secret_key.decrypt_pertinency(&pertinency)[0], 1);
+    }
+
+    #[test]
+    fn clue_key_with_mismatched_dimension_fails_check() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let secret_key = &TestSetup::shared().secret_key;
+        let clue_key = secret_key.generate_clue_key(&mut rng);
+
+        let clues = clue_key.gen_clues(7, &mut rng);
+        assert!(clue_key.check_clues(&clues, 7).is_ok());
+        assert!(matches!(
+            clue_key.check_clues(&clues, 5),
+            Err(OmrError::ClueCountMismatch {
+                expected: 5,
+                found: 7
+            })
+        ));
+
+        let params = clue_key.params;
+        let truncated = ClueKey::new(
+            clue_key.key,
+            LweParameters {
+                dimension: params.dimension / 2,
+                ..params
+            },
+        );
+        let sender = crate::Sender::new(truncated, 7);
+        assert!(matches!(
+            sender.gen_clues_checked(&mut rng),
+            Err(OmrError::DimensionMismatch { .. })
+        ));
+    }
+}
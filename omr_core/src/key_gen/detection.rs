@@ -1,9 +1,12 @@
 //! Detection key material for the detector (two bootstrapping layers + trace).
 
 use algebra::{modulus::ShoupFactor, utils::Size, Field};
-use fhe_core::{BlindRotationKey, NonPowOf2LweKeySwitchingKey, TraceKey};
+use fhe_core::{BlindRotationKey, CmLweCiphertext, NonPowOf2LweKeySwitchingKey, TraceKey};
 
-use crate::{ClueModulus, FirstLevelField, OmrParameters, SecondLevelField};
+use crate::{
+    detector::{detect_with_key, detection_luts},
+    ClueModulus, ClueValue, FirstLevelField, OmrParameters, SecondLevelField, SecretKeyPack,
+};
 
 /// Contains BSK1, KSK (z1 -> s2), BSK2, and TraceKey for retrieval.
 pub struct DetectionKey {
@@ -70,6 +73,33 @@ impl DetectionKey {
         &self.params
     }
 
+    /// Checks that this [`DetectionKey`] and `other` detect `clues` to the same
+    /// decrypted output under `key`.
+    ///
+    /// Two keys generated from the same pack carry different noise, so comparing
+    /// their bytes says nothing about whether they detect alike.
+    pub fn functionally_equivalent(
+        &self,
+        other: &Self,
+        clues: &CmLweCiphertext<ClueValue>,
+        key: &SecretKeyPack,
+    ) -> bool {
+        if self.params.fingerprint() != other.params.fingerprint() {
+            return false;
+        }
+
+        let (first_level_lut, second_level_lut) = detection_luts(&self.params);
+        let detect = |detection_key: &Self| {
+            key.decrypt_pertinency(&detect_with_key(
+                detection_key,
+                &first_level_lut,
+                &second_level_lut,
+                clues,
+            ))
+        };
+        detect(self) == detect(other)
+    }
+
     /// Returns the second level ring dimension inv of this [`DetectionKey`].
     pub fn second_level_ring_dimension_inv(
         &self,
@@ -78,6 +108,62 @@ impl DetectionKey {
     }
 }
 
+impl DetectionKey {
+    /// Drops the first level keys, keeping what detection needs after the first level
+    /// bootstrapping, see [`SecondLevelDetector`](crate::SecondLevelDetector).
+    ///
+    /// The first level blind rotation key and key switching key are usually the bulk
+    /// of a [`DetectionKey`], the saving is their [`Size::size`].
+    pub fn into_second_level_only(self) -> SecondLevelDetectionKey {
+        SecondLevelDetectionKey {
+            second_level_blind_rotation_key: self.second_level_blind_rotation_key,
+            second_level_ring_dimension_inv: self.second_level_ring_dimension_inv,
+            trace_key: self.trace_key,
+            params: self.params,
+        }
+    }
+}
+
+/// Contains BSK2 and TraceKey, the part of a [`DetectionKey`] which detects from
+/// cached intermediate ciphertexts.
+pub struct SecondLevelDetectionKey {
+    second_level_blind_rotation_key: BlindRotationKey<SecondLevelField>,
+    second_level_ring_dimension_inv: ShoupFactor<<SecondLevelField as Field>::ValueT>,
+    trace_key: TraceKey<SecondLevelField>,
+    params: OmrParameters,
+}
+
+impl SecondLevelDetectionKey {
+    /// Returns a reference to the second level blind rotation key of this [`SecondLevelDetectionKey`].
+    pub fn second_level_blind_rotation_key(&self) -> &BlindRotationKey<SecondLevelField> {
+        &self.second_level_blind_rotation_key
+    }
+
+    /// Returns the second level ring dimension inv of this [`SecondLevelDetectionKey`].
+    pub fn second_level_ring_dimension_inv(
+        &self,
+    ) -> ShoupFactor<<SecondLevelField as Field>::ValueT> {
+        self.second_level_ring_dimension_inv
+    }
+
+    /// Returns a reference to the trace key of this [`SecondLevelDetectionKey`].
+    pub fn trace_key(&self) -> &TraceKey<SecondLevelField> {
+        &self.trace_key
+    }
+
+    /// Returns a reference to the params of this [`SecondLevelDetectionKey`].
+    pub fn params(&self) -> &OmrParameters {
+        &self.params
+    }
+}
+
+impl Size for SecondLevelDetectionKey {
+    #[inline]
+    fn size(&self) -> usize {
+        self.second_level_blind_rotation_key.size() + self.trace_key.size()
+    }
+}
+
 impl Size for DetectionKey {
     #[inline]
     fn size(&self) -> usize {
@@ -87,3 +173,24 @@ impl Size for DetectionKey {
             + self.trace_key.size()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::test_utils::TestSetup;
+
+    #[test]
+    fn detection_keys_from_one_pack_are_equivalent() {
+        let mut rng = StdRng::seed_from_u64(21);
+        let secret_key = &TestSetup::shared().secret_key;
+        let clues = secret_key.generate_sender(&mut rng).gen_clues(&mut rng);
+
+        let key = |seed| secret_key.generate_detection_key(&mut StdRng::seed_from_u64(seed));
+        let (a, a_again, b) = (key(1), key(1), key(2));
+
+        assert!(a.functionally_equivalent(&a_again, &clues, secret_key));
+        // Different noise, same decrypted detect output.
+        assert!(a.functionally_equivalent(&b, &clues, secret_key));
+    }
+}
@@ -1,16 +1,21 @@
 //! Key generation wrappers for OMR keys.
 
-use crate::OmrParameters;
+use crate::{OmrParameters, Sender};
 
 mod clue;
 mod detection;
+mod retriever;
 mod secret;
+#[cfg(feature = "zeroize")]
+mod wipe;
 
-use rand::{CryptoRng, Rng};
+use rand::{rngs::StdRng, CryptoRng, Rng, SeedableRng};
+use rayon::prelude::*;
 
 pub use clue::ClueKey;
-pub use detection::DetectionKey;
-pub use secret::SecretKeyPack;
+pub use detection::{DetectionKey, SecondLevelDetectionKey};
+pub use retriever::RetrieverKey;
+pub use secret::{KeyGenTimeInfo, SecretKeyPack};
 
 /// Key generation entry point.
 pub struct KeyGen;
@@ -24,4 +29,47 @@ impl KeyGen {
     {
         SecretKeyPack::new(params, rng)
     }
+
+    /// Generates one [`Sender`] per secret key pack in parallel.
+    ///
+    /// A seed is drawn from `rng` for each pack in order, and the pack's sender is
+    /// generated from a [`StdRng`] with that seed.
+    pub fn generate_senders<R>(packs: &[SecretKeyPack], rng: &mut R) -> Vec<Sender>
+    where
+        R: Rng + CryptoRng,
+    {
+        let seeds: Vec<[u8; 32]> = packs.iter().map(|_| rng.gen()).collect();
+        packs
+            .par_iter()
+            .zip(seeds.par_iter())
+            .map(|(pack, &seed)| pack.generate_sender(&mut StdRng::from_seed(seed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_senders_match_individual_senders() {
+        let mut rng = StdRng::seed_from_u64(21);
+        let packs = (0..3)
+            .map(|_| KeyGen::generate_secret_key(OmrParameters::new(), &mut rng))
+            .collect::<Vec<_>>();
+
+        let senders = KeyGen::generate_senders(&packs, &mut StdRng::seed_from_u64(9));
+
+        let mut seed_rng = StdRng::seed_from_u64(9);
+        for (pack, sender) in packs.iter().zip(senders.iter()) {
+            let seed: [u8; 32] = seed_rng.gen();
+            let expected = pack.generate_sender(&mut StdRng::from_seed(seed));
+
+            let clues = sender.gen_clues(&mut StdRng::seed_from_u64(0));
+            let expected_clues = expected.gen_clues(&mut StdRng::seed_from_u64(0));
+            assert_eq!(clues.a(), expected_clues.a());
+            assert_eq!(clues.b(), expected_clues.b());
+            assert!(pack.diagnose_clue_bundle(&clues).is_empty());
+        }
+    }
 }
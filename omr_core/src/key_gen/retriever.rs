@@ -0,0 +1,224 @@
+//! Retriever key holding only the secret material needed to decode digests.
+
+use std::sync::Arc;
+
+use algebra::{polynomial::FieldNttPolynomial, utils::Size, Field, NttField};
+use fhe_core::NttRlweSecretKey;
+
+use crate::{OmrError, OutputValue, RetrievalParams, Retriever, SecondLevelField};
+
+const VALUE_BYTES: usize = std::mem::size_of::<OutputValue>();
+
+/// Second-level NTT secret key exported from a [`SecretKeyPack`](crate::SecretKeyPack),
+/// together with the fingerprint of the NTT table it lives in.
+#[derive(Clone)]
+pub struct RetrieverKey {
+    key: NttRlweSecretKey<SecondLevelField>,
+    modulus: u64,
+    ring_dimension: usize,
+}
+
+impl RetrieverKey {
+    /// Creates a new [`RetrieverKey`].
+    #[inline]
+    pub fn new(key: NttRlweSecretKey<SecondLevelField>) -> Self {
+        let ring_dimension = key.as_slice().len();
+        Self {
+            key,
+            modulus: <SecondLevelField as Field>::MODULUS_VALUE,
+            ring_dimension,
+        }
+    }
+
+    /// Returns a reference to the key of this [`RetrieverKey`].
+    #[inline]
+    pub fn key(&self) -> &NttRlweSecretKey<SecondLevelField> {
+        &self.key
+    }
+
+    /// Returns the ring dimension of this [`RetrieverKey`].
+    #[inline]
+    pub fn ring_dimension(&self) -> usize {
+        self.ring_dimension
+    }
+
+    /// Serializes the table fingerprint (modulus, ring dimension) followed by the
+    /// key coefficients, as little-endian `u64`s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity((2 + self.ring_dimension) * VALUE_BYTES);
+        bytes.extend_from_slice(&self.modulus.to_le_bytes());
+        bytes.extend_from_slice(&(self.ring_dimension as u64).to_le_bytes());
+        for value in self.key.iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a key written by [`RetrieverKey::to_bytes`].
+    ///
+    /// The ring dimension must be a power of two, and every coefficient must be
+    /// reduced modulo the second level modulus.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OmrError> {
+        let header = 2 * VALUE_BYTES;
+        if bytes.len() < header {
+            return Err(OmrError::InvalidLength {
+                expected: header,
+                found: bytes.len(),
+            });
+        }
+
+        let (modulus_bytes, rest) = bytes.split_at(VALUE_BYTES);
+        let (dimension_bytes, key_bytes) = rest.split_at(VALUE_BYTES);
+        let modulus = u64::from_le_bytes(modulus_bytes.try_into().unwrap());
+        let ring_dimension = u64::from_le_bytes(dimension_bytes.try_into().unwrap()) as usize;
+
+        if modulus != <SecondLevelField as Field>::MODULUS_VALUE {
+            return Err(OmrError::ModulusMismatch {
+                expected: <SecondLevelField as Field>::MODULUS_VALUE,
+                found: modulus,
+            });
+        }
+        if !ring_dimension.is_power_of_two() {
+            return Err(OmrError::InvalidParameters {
+                reason: "ring dimension must be a power of two",
+            });
+        }
+        if ring_dimension
+            .checked_mul(VALUE_BYTES)
+            .is_none_or(|len| len != key_bytes.len())
+        {
+            return Err(OmrError::InvalidLength {
+                expected: ring_dimension
+                    .saturating_mul(VALUE_BYTES)
+                    .saturating_add(header),
+                found: bytes.len(),
+            });
+        }
+
+        let mut key = <FieldNttPolynomial<SecondLevelField>>::zero(ring_dimension);
+        for (value, chunk) in key.iter_mut().zip(key_bytes.chunks_exact(VALUE_BYTES)) {
+            *value = OutputValue::from_le_bytes(chunk.try_into().unwrap());
+            if *value >= modulus {
+                return Err(OmrError::UnreducedValue {
+                    value: *value,
+                    modulus,
+                });
+            }
+        }
+
+        Ok(Self {
+            key: NttRlweSecretKey::new(key),
+            modulus,
+            ring_dimension,
+        })
+    }
+}
+
+impl Size for RetrieverKey {
+    #[inline]
+    fn size(&self) -> usize {
+        self.ring_dimension * VALUE_BYTES
+    }
+}
+
+impl Retriever<SecondLevelField> {
+    /// Creates a new [`Retriever`] from an exported [`RetrieverKey`], rebuilding the
+    /// second-level NTT table from the key's fingerprint.
+    pub fn from_exported_key(
+        key: RetrieverKey,
+        params: RetrievalParams<SecondLevelField>,
+    ) -> Result<Self, OmrError> {
+        if key.ring_dimension != params.polynomial_size() {
+            return Err(OmrError::DimensionMismatch {
+                expected: params.polynomial_size(),
+                found: key.ring_dimension,
+            });
+        }
+
+        let ntt_table = SecondLevelField::generate_ntt_table(key.ring_dimension.trailing_zeros())
+            .map_err(|_| OmrError::InvalidParameters {
+            reason: "the second level field has no NTT table for this ring dimension",
+        })?;
+
+        Ok(Retriever::new(params, Arc::new(ntt_table), key.key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::test_utils::TestSetup;
+
+    #[test]
+    fn exported_key_decodes_like_full_pack() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let secret_key = &TestSetup::shared().secret_key;
+        let full = secret_key.generate_retriever(1000, 50).unwrap();
+
+        let bytes = secret_key.export_retriever_key().to_bytes();
+        let key = RetrieverKey::from_bytes(&bytes).unwrap();
+        let exported = Retriever::from_exported_key(key, full.params()).unwrap();
+
+        let n = full.params().polynomial_size();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let mut cipher = <lattice::NttRlwe<SecondLevelField>>::zero(n);
+        cipher
+            .a_mut()
+            .iter_mut()
+            .for_each(|v| *v = rng.gen_range(0..q));
+        cipher
+            .b_mut()
+            .iter_mut()
+            .for_each(|v| *v = rng.gen_range(0..q));
+
+        assert_eq!(
+            exported.decode_index_coefficients(&cipher),
+            full.decode_index_coefficients(&cipher)
+        );
+        assert!(matches!(
+            RetrieverKey::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(OmrError::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn malformed_key_bytes_are_rejected() {
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let header = |modulus: u64, dimension: u64| {
+            let mut bytes = modulus.to_le_bytes().to_vec();
+            bytes.extend_from_slice(&dimension.to_le_bytes());
+            bytes
+        };
+
+        let mut wrong_modulus = header(q - 2, 4);
+        wrong_modulus.resize(wrong_modulus.len() + 4 * VALUE_BYTES, 0);
+        assert!(matches!(
+            RetrieverKey::from_bytes(&wrong_modulus),
+            Err(OmrError::ModulusMismatch { found, .. }) if found == q - 2
+        ));
+
+        let mut odd_dimension = header(q, 3);
+        odd_dimension.resize(odd_dimension.len() + 3 * VALUE_BYTES, 0);
+        assert!(matches!(
+            RetrieverKey::from_bytes(&odd_dimension),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+
+        // `2^63 * 8` overflows the expected length.
+        assert!(matches!(
+            RetrieverKey::from_bytes(&header(q, 1 << 63)),
+            Err(OmrError::InvalidLength { .. })
+        ));
+
+        let mut unreduced = header(q, 4);
+        for value in [0, 1, q, 2] {
+            unreduced.extend_from_slice(&value.to_le_bytes());
+        }
+        assert!(matches!(
+            RetrieverKey::from_bytes(&unreduced),
+            Err(OmrError::UnreducedValue { value, .. }) if value == q
+        ));
+    }
+}
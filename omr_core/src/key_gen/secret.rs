@@ -1,20 +1,53 @@
 //! Secret-key pack and key derivation helpers for two-layer InstantOMR.
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use algebra::{modulus::ShoupFactor, ntt::NttTable, utils::Size, Field, NttField};
+use algebra::{
+    modulus::ShoupFactor,
+    ntt::{NttTable, NumberTheoryTransform},
+    utils::Size,
+    Field, NttField,
+};
 use fhe_core::{
-    BlindRotationKey, LweCiphertext, LwePublicKeyRlweMode, LweSecretKey,
-    NonPowOf2LweKeySwitchingKey, NttRlweSecretKey, RlweSecretKey, TraceKey,
+    BlindRotationKey, CmLweCiphertext, LweCiphertext, LwePublicKeyRlweMode, LweSecretKey,
+    NonPowOf2LweKeySwitchingKey, NttRlweSecretKey, RlweCiphertext, RlweSecretKey, TraceKey,
 };
 use rand::{CryptoRng, Rng};
 
 use crate::{
-    ClueValue, Detector, FirstLevelField, InterLweValue, OmrParameters, RetrievalParams, Retriever,
-    SecondLevelField, Sender,
+    retriever::round_coefficients, ClueValue, Detector, FirstLevelField, InterLweValue, OmrError,
+    OmrParameters, OutputValue, PertinencyCiphertext, RetrievalParams, Retriever, SecondLevelField,
+    Sender, MATCH_CLUE_VALUE,
 };
 
-use super::{ClueKey, DetectionKey};
+use super::{ClueKey, DetectionKey, RetrieverKey};
+
+/// The index recovery failure probability targeted by [`SecretKeyPack::generate_retriever`].
+const INDEX_FAILURE_PROBABILITY: f64 = 1e-9;
+
+/// Time information for generating a [`DetectionKey`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyGenTimeInfo {
+    /// Generating the first level blind rotation key.
+    pub first_level_brk: Duration,
+    /// Generating the first level key switching key.
+    pub key_switching: Duration,
+    /// Generating the second level blind rotation key.
+    pub second_level_brk: Duration,
+    /// Generating the homomorphic trace key.
+    pub trace_key: Duration,
+}
+
+impl KeyGenTimeInfo {
+    /// Returns the total key generation time.
+    #[inline]
+    pub fn total(&self) -> Duration {
+        self.first_level_brk + self.key_switching + self.second_level_brk + self.trace_key
+    }
+}
 
 /// Bundles two-layer LWE/RLWE secrets (s1,s2,z1,z2) and derived helpers.
 ///
@@ -112,15 +145,27 @@ impl SecretKeyPack {
         R: Rng + CryptoRng,
     {
         Sender::new(self.generate_clue_key(rng), self.parameters.clue_count())
+            .with_fingerprint(self.parameters.fingerprint())
     }
 
     /// Generates a [`DetectionKey`].
+    #[inline]
     pub fn generate_detection_key<R>(&self, rng: &mut R) -> DetectionKey
+    where
+        R: Rng + CryptoRng,
+    {
+        self.generate_detection_key_with_time(rng).0
+    }
+
+    /// Generates a [`DetectionKey`] and reports the time spent on each of its keys.
+    pub fn generate_detection_key_with_time<R>(&self, rng: &mut R) -> (DetectionKey, KeyGenTimeInfo)
     where
         R: Rng + CryptoRng,
     {
         let parameters = self.parameters();
 
+        let time_0 = Instant::now();
+
         let first_level_blind_rotation_key = BlindRotationKey::generate(
             self.clue_secret_key(),
             self.first_level_ntt_rlwe_secret_key(),
@@ -130,12 +175,17 @@ impl SecretKeyPack {
             rng,
         );
 
+        let time_1 = Instant::now();
+
         let key_switching_key = {
             let s_in = self.first_level_rlwe_secret_key();
             let s_in = LweSecretKey::<<FirstLevelField as Field>::ValueT>::from_rlwe_secret_key(
                 s_in,
                 <FirstLevelField as Field>::MODULUS_VALUE - 1,
             );
+            // `s_in` is a copy of the first level secret only needed for this key.
+            #[cfg(feature = "zeroize")]
+            let s_in = super::wipe::WipeOnDrop::new(s_in);
             let s_out = self.intermediate_lwe_secret_key();
             NonPowOf2LweKeySwitchingKey::<<FirstLevelField as Field>::ValueT>::generate(
                 &s_in,
@@ -146,6 +196,8 @@ impl SecretKeyPack {
             )
         };
 
+        let time_2 = Instant::now();
+
         let second_level_blind_rotation_key = BlindRotationKey::generate(
             self.intermediate_lwe_secret_key(),
             self.second_level_ntt_rlwe_secret_key(),
@@ -155,6 +207,8 @@ impl SecretKeyPack {
             rng,
         );
 
+        let time_3 = Instant::now();
+
         let trace_key = TraceKey::new(
             self.second_level_rlwe_secret_key(),
             self.second_level_ntt_rlwe_secret_key(),
@@ -164,17 +218,28 @@ impl SecretKeyPack {
             rng,
         );
 
+        let time_4 = Instant::now();
+
         let n = self.second_level_ntt_table().dimension();
         let inv_n = SecondLevelField::inv(n as <SecondLevelField as Field>::ValueT);
 
-        DetectionKey::new(
+        let detection_key = DetectionKey::new(
             first_level_blind_rotation_key,
             key_switching_key,
             second_level_blind_rotation_key,
             ShoupFactor::new(inv_n, SecondLevelField::MODULUS_VALUE),
             trace_key,
             self.parameters.clone(),
-        )
+        );
+
+        let time_info = KeyGenTimeInfo {
+            first_level_brk: time_1 - time_0,
+            key_switching: time_2 - time_1,
+            second_level_brk: time_3 - time_2,
+            trace_key: time_4 - time_3,
+        };
+
+        (detection_key, time_info)
     }
 
     /// Generates a [`Detector`].
@@ -186,26 +251,66 @@ impl SecretKeyPack {
         Detector::new(self.generate_detection_key(rng))
     }
 
+    /// Generates a [`Retriever`] whose digest layout is sized for the board with
+    /// [`RetrievalParams::recommended_layout`].
+    ///
+    /// Fails like [`SecretKeyPack::generate_retriever_with_layout`].
     pub fn generate_retriever(
         &self,
         all_payloads_count: usize,
         pertinent_count: usize,
-    ) -> Retriever<SecondLevelField> {
+    ) -> Result<Retriever<SecondLevelField>, OmrError> {
         let params = self.parameters();
-        let retrieval_params: RetrievalParams<SecondLevelField> = RetrievalParams::new(
+        let (bucket_count_per_segment, segment_count) =
+            RetrievalParams::<SecondLevelField>::recommended_layout(
+                params.output_plain_modulus_value(),
+                params.second_level_ring_dimension(),
+                all_payloads_count,
+                pertinent_count,
+                INDEX_FAILURE_PROBABILITY,
+            );
+        self.generate_retriever_with_layout(
+            all_payloads_count,
+            pertinent_count,
+            bucket_count_per_segment,
+            segment_count,
+        )
+    }
+
+    /// Generates a [`Retriever`] with a manually chosen digest layout.
+    ///
+    /// The layout is checked with [`RetrievalParams::try_new`], so e.g. a segment
+    /// larger than the second level ring is reported as
+    /// [`OmrError::InsufficientPolynomialSize`].
+    pub fn generate_retriever_with_layout(
+        &self,
+        all_payloads_count: usize,
+        pertinent_count: usize,
+        bucket_count_per_segment: usize,
+        segment_count: usize,
+    ) -> Result<Retriever<SecondLevelField>, OmrError> {
+        let params = self.parameters();
+        let retrieval_params: RetrievalParams<SecondLevelField> = RetrievalParams::try_new(
             params.output_plain_modulus_value(),
             params.second_level_ring_dimension(),
             all_payloads_count,
             pertinent_count,
-            130,
-            25,
+            bucket_count_per_segment,
+            segment_count,
             2,
-        );
-        Retriever::new(
+        )?;
+        Ok(Retriever::new(
             retrieval_params,
             Arc::clone(self.second_level_ntt_table()),
             self.second_level_ntt_rlwe_secret_key().clone(),
-        )
+        ))
+    }
+
+    /// Exports only the secret material a [`Retriever`] needs: the second-level NTT
+    /// secret key. Pair it with [`Retriever::from_exported_key`] on the retrieving node.
+    #[inline]
+    pub fn export_retriever_key(&self) -> RetrieverKey {
+        RetrieverKey::new(self.second_level_ntt_rlwe_secret_key.clone())
     }
 
     /// Returns a reference to the parameters.
@@ -269,6 +374,77 @@ impl SecretKeyPack {
             .decrypt::<ClueValue, _>(clue, self.parameters.clue_params())
     }
 
+    /// Decrypts an intermediate LWE ciphertext, such as the output of
+    /// [`Detector::detect_intermediate_raw`].
+    #[inline]
+    pub fn decrypt_intermediate(&self, ciphertext: &LweCiphertext<InterLweValue>) -> InterLweValue {
+        self.intermediate_lwe_secret_key
+            .decrypt::<InterLweValue, _>(ciphertext, self.parameters.intermediate_lwe_params())
+    }
+
+    /// Decrypts every clue of a clue bundle.
+    #[inline]
+    pub fn decrypt_clue_bundle(&self, clues: &CmLweCiphertext<ClueValue>) -> Vec<ClueValue> {
+        clues
+            .extract_all(self.parameters.clue_cipher_modulus())
+            .iter()
+            .map(|clue| self.decrypt_clue(clue))
+            .collect()
+    }
+
+    /// Decrypts a detection output and rounds every coefficient to the output plain modulus.
+    pub fn decrypt_pertinency(&self, ciphertext: &PertinencyCiphertext) -> Vec<OutputValue> {
+        let ct = ciphertext.as_ntt();
+        let poly = self.second_level_ntt_table.inverse_transform_inplace(
+            ct.b() - ct.a().clone() * &*self.second_level_ntt_rlwe_secret_key,
+        );
+        round_coefficients::<SecondLevelField>(
+            poly.into_iter(),
+            self.parameters.output_plain_modulus_value(),
+        )
+    }
+
+    /// Decrypts the indicator in coefficient 0 of a coefficient-domain detection output,
+    /// returning whether the message is pertinent.
+    ///
+    /// Fails with [`OmrError::DecodeOutOfRange`] if the indicator rounds to anything
+    /// but 0 or 1, which points at a noise blowup or a key mismatch.
+    pub fn verify_indicator(
+        &self,
+        ciphertext: &RlweCiphertext<SecondLevelField>,
+    ) -> Result<bool, OmrError> {
+        let ciphertext = PertinencyCiphertext::from_coeff(ciphertext, &self.second_level_ntt_table);
+        match self.decrypt_pertinency(&ciphertext)[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(OmrError::DecodeOutOfRange { value }),
+        }
+    }
+
+    /// Checks that the homomorphic trace left the indicator in coefficient 0 only,
+    /// i.e. that every other coefficient decrypts to 0 within the rounding tolerance.
+    pub fn assert_trace_clean(&self, ciphertext: &PertinencyCiphertext) -> bool {
+        self.decrypt_pertinency(ciphertext)[1..]
+            .iter()
+            .all(|&x| x == 0)
+    }
+
+    /// Returns the index and decrypted value of every clue in the bundle
+    /// which doesn't decrypt to [`MATCH_CLUE_VALUE`].
+    ///
+    /// A bundle generated for this key decrypts to all matches, so any entry
+    /// here points at a corrupted clue or a key mismatch.
+    pub fn diagnose_clue_bundle(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+    ) -> Vec<(usize, ClueValue)> {
+        self.decrypt_clue_bundle(clues)
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, value)| value != MATCH_CLUE_VALUE)
+            .collect()
+    }
+
     /// z2 key size
     #[inline]
     pub fn z2_size(&self) -> usize {
@@ -287,3 +463,186 @@ impl Size for SecretKeyPack {
         // + self.second_level_ntt_rlwe_secret_key.size()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use algebra::polynomial::FieldPolynomial;
+    use fhe_core::NttRlweCiphertext;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{detector::second_level_delta, test_utils::TestSetup};
+
+    /// Encrypts `message * delta` under the second level NTT key.
+    fn encrypt(
+        pack: &SecretKeyPack,
+        message: &[OutputValue],
+        rng: &mut StdRng,
+    ) -> PertinencyCiphertext {
+        let table = pack.second_level_ntt_table();
+        let n = table.dimension();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let delta = second_level_delta(pack.parameters().output_plain_modulus_value() as usize);
+
+        let mut m = FieldPolynomial::<SecondLevelField>::zero(n);
+        m.iter_mut()
+            .zip(message.iter())
+            .for_each(|(c, &v)| *c = v * delta % q);
+
+        let mut ct = NttRlweCiphertext::<SecondLevelField>::zero(n);
+        ct.a_mut().iter_mut().for_each(|v| *v = rng.gen_range(0..q));
+        *ct.b_mut() =
+            ct.a().clone() * &*pack.second_level_ntt_rlwe_secret_key + table.transform_inplace(m);
+        PertinencyCiphertext::from_ntt(ct)
+    }
+
+    #[test]
+    fn diagnosis_points_at_the_corrupted_clue() {
+        use algebra::reduce::ReduceAddAssign;
+
+        let mut rng = StdRng::seed_from_u64(19);
+        let params = OmrParameters::new();
+        let pack = SecretKeyPack::new(params.clone(), &mut rng);
+        let mut clues = pack.generate_sender(&mut rng).gen_clues(&mut rng);
+        assert!(pack.diagnose_clue_bundle(&clues).is_empty());
+
+        // Shift clue 3 by one plaintext step.
+        let clue_delta = (params.modulus_summary().clue_cipher_modulus
+            / params.clue_plain_modulus_value() as u64) as ClueValue;
+        params
+            .clue_params()
+            .cipher_modulus
+            .reduce_add_assign(&mut clues.b_mut()[3], clue_delta);
+
+        let diagnosis = pack.diagnose_clue_bundle(&clues);
+        assert_eq!(diagnosis.len(), 1);
+        assert_eq!(diagnosis[0].0, 3);
+        assert_ne!(diagnosis[0].1, MATCH_CLUE_VALUE);
+    }
+
+    #[test]
+    fn auto_sized_retrievers_recover_the_pertinent_payloads() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let TestSetup {
+            secret_key: pack,
+            detector,
+            ..
+        } = TestSetup::shared();
+
+        // Encrypted indicators stand in for detection, the digest runs as is.
+        for (board_size, pertinent_count) in [(64, 4), (1000, 10), (5000, 20)] {
+            let mut retriever = pack
+                .generate_retriever(board_size, pertinent_count)
+                .unwrap();
+            let retrieval_params = retriever.params();
+
+            let stride = board_size / pertinent_count;
+            let pertinent = (0..pertinent_count)
+                .map(|i| i * stride + 1)
+                .collect::<Vec<_>>();
+            let pertinency_vector = (0..board_size)
+                .map(|i| encrypt(pack, &[pertinent.contains(&i) as OutputValue], &mut rng))
+                .collect::<Vec<_>>();
+            let payloads = (0..board_size)
+                .map(|_| crate::Payload::random(&mut rng))
+                .collect::<Vec<_>>();
+
+            let indices = (0..retrieval_params.max_encode_indices_cipher_count())
+                .map(|_| detector.encode_pertinent_indices(retrieval_params, &pertinency_vector))
+                .collect::<Vec<_>>();
+            let seed = [board_size as u8; 32];
+            let combinations = detector.encode_pertinent_payloads(
+                &pertinency_vector,
+                &payloads,
+                retrieval_params.combination_count(),
+                retrieval_params.cmb_count_per_cipher(),
+                &mut StdRng::from_seed(seed),
+            );
+
+            let (decoded, solved) = retriever
+                .decode_digest(&indices, &combinations, seed)
+                .unwrap();
+            assert_eq!(decoded, pertinent);
+            assert_eq!(
+                solved,
+                pertinent.iter().map(|&i| payloads[i]).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn key_gen_time_is_the_sum_of_its_parts() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let pack = SecretKeyPack::new(OmrParameters::new(), &mut rng);
+        let (_, time_info) = pack.generate_detection_key_with_time(&mut rng);
+
+        let parts = [
+            time_info.first_level_brk,
+            time_info.key_switching,
+            time_info.second_level_brk,
+            time_info.trace_key,
+        ];
+        assert!(parts.iter().all(|part| !part.is_zero()));
+        assert_eq!(time_info.total(), parts.iter().sum());
+    }
+
+    #[test]
+    fn oversized_layout_is_reported() {
+        let pack = SecretKeyPack::new(OmrParameters::new(), &mut StdRng::seed_from_u64(41));
+
+        // 1000 payloads take 3 slots per bucket: 1000 buckets need 3000 > 2048 slots.
+        assert!(matches!(
+            pack.generate_retriever_with_layout(1000, 10, 1000, 25),
+            Err(OmrError::InsufficientPolynomialSize {
+                polynomial_size: 2048,
+                slots_per_segment: 3000,
+            })
+        ));
+        assert!(pack
+            .generate_retriever_with_layout(1000, 10, 130, 25)
+            .is_ok());
+        assert!(matches!(
+            pack.generate_retriever(10, 11),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn indicator_outside_zero_and_one_is_rejected() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let pack = SecretKeyPack::new(OmrParameters::new(), &mut rng);
+        let table = pack.second_level_ntt_table();
+
+        let matching = encrypt(&pack, &[1], &mut rng).to_coeff(table);
+        let non_matching = encrypt(&pack, &[0], &mut rng).to_coeff(table);
+        assert!(pack.verify_indicator(&matching).unwrap());
+        assert!(!pack.verify_indicator(&non_matching).unwrap());
+
+        // Half the modulus on the indicator moves it to the middle of the plaintext space.
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let mut corrupted = matching;
+        corrupted.b_mut()[0] = (corrupted.b()[0] + q / 2) % q;
+        assert!(matches!(
+            pack.verify_indicator(&corrupted),
+            Err(OmrError::DecodeOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn trace_clean_accepts_only_constant_outputs() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let pack = SecretKeyPack::new(OmrParameters::new(), &mut rng);
+
+        // A match leaves 1 in coefficient 0, a non-match nothing at all.
+        let matching = encrypt(&pack, &[1], &mut rng);
+        let non_matching = encrypt(&pack, &[0], &mut rng);
+        assert!(pack.assert_trace_clean(&matching));
+        assert!(pack.assert_trace_clean(&non_matching));
+        assert_eq!(pack.decrypt_pertinency(&matching)[0], 1);
+        assert_eq!(pack.decrypt_pertinency(&non_matching)[0], 0);
+
+        // A leftover in another coefficient means the trace failed.
+        let dirty = encrypt(&pack, &[1, 0, 0, 5], &mut rng);
+        assert!(!pack.assert_trace_clean(&dirty));
+    }
+}
@@ -0,0 +1,99 @@
+//! Wiping of temporary secret keys.
+
+use std::ops::Deref;
+
+/// Owns a temporary secret and overwrites its buffer with zeros when dropped.
+pub(crate) struct WipeOnDrop<T: AsMut<[V]>, V: Copy + Default>(T, std::marker::PhantomData<V>);
+
+impl<T: AsMut<[V]>, V: Copy + Default> WipeOnDrop<T, V> {
+    /// Wraps `secret` so that it is wiped when dropped.
+    #[inline]
+    pub(crate) fn new(secret: T) -> Self {
+        Self(secret, std::marker::PhantomData)
+    }
+}
+
+impl<T: AsMut<[V]>, V: Copy + Default> Deref for WipeOnDrop<T, V> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: AsMut<[V]>, V: Copy + Default> Drop for WipeOnDrop<T, V> {
+    fn drop(&mut self) {
+        wipe(self.0.as_mut());
+    }
+}
+
+/// Overwrites `buffer` with zeros, with volatile writes the compiler can't elide.
+fn wipe<V: Copy + Default>(buffer: &mut [V]) {
+    for v in buffer.iter_mut() {
+        // SAFETY: `v` is a valid, aligned and exclusive reference.
+        unsafe { std::ptr::write_volatile(v, V::default()) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+
+    #[cfg(test)]
+    tests::WIPED_VALUES.with(|count| count.set(count.get() + buffer.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{OmrParameters, SecretKeyPack};
+
+    thread_local! {
+        /// The number of values wiped on this thread.
+        pub(super) static WIPED_VALUES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// A secret that reports its contents when dropped, which happens after the
+    /// [`WipeOnDrop`] owning it has wiped it.
+    struct Probe {
+        values: [u32; 4],
+        seen_on_drop: Rc<Cell<Option<[u32; 4]>>>,
+    }
+
+    impl AsMut<[u32]> for Probe {
+        fn as_mut(&mut self) -> &mut [u32] {
+            &mut self.values
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            self.seen_on_drop.set(Some(self.values));
+        }
+    }
+
+    #[test]
+    fn buffer_is_cleared_on_drop() {
+        let seen_on_drop = Rc::new(Cell::new(None));
+        let secret = WipeOnDrop::new(Probe {
+            values: [1, 2, 3, 4],
+            seen_on_drop: Rc::clone(&seen_on_drop),
+        });
+        assert_eq!(secret.values, [1, 2, 3, 4]);
+
+        drop(secret);
+        assert_eq!(seen_on_drop.get(), Some([0; 4]));
+    }
+
+    #[test]
+    fn detection_key_generation_wipes_the_key_switching_secret() {
+        let params = OmrParameters::new();
+        let dimension = params.first_level_ring_dimension();
+        let pack = SecretKeyPack::new(params, &mut StdRng::seed_from_u64(3));
+
+        let before = WIPED_VALUES.with(Cell::get);
+        pack.generate_detection_key(&mut StdRng::seed_from_u64(4));
+        assert_eq!(WIPED_VALUES.with(Cell::get) - before, dimension);
+    }
+}
@@ -4,8 +4,55 @@
 //! `Detector` performs two-layer TFHE functional bootstrapping plus homomorphic
 //! trace to mark pertinent messages, then uses RLWE encoding to build a digest.
 //! The `Retriever` decodes that digest into indices and payloads.
+//!
+//! The ciphertext types of the public API are re-exported, so a full round trip
+//! needs no other FHE crate:
+//!
+//! ```
+//! use omr_core::{
+//!     ClueValue, CmLweCiphertext, KeyGen, NttRlwe, NttRlweCiphertext, OmrParameters, Payload,
+//!     SecondLevelField,
+//! };
+//! use rand::{rngs::StdRng, SeedableRng};
+//!
+//! let mut rng = StdRng::seed_from_u64(0);
+//! let secret_key = KeyGen::generate_secret_key(OmrParameters::new(), &mut rng);
+//! let stranger = KeyGen::generate_secret_key(OmrParameters::new(), &mut rng);
+//! let sender = secret_key.generate_sender(&mut rng);
+//! let other = stranger.generate_sender(&mut rng);
+//! let detector = secret_key.generate_detector(&mut rng);
+//!
+//! let board: Vec<CmLweCiphertext<ClueValue>> = vec![
+//!     other.gen_clues(&mut rng),
+//!     sender.gen_clues(&mut rng),
+//!     other.gen_clues(&mut rng),
+//! ];
+//! let payloads: Vec<Payload> = (0..3).map(|_| Payload::random(&mut rng)).collect();
+//!
+//! let mut retriever = secret_key.generate_retriever(board.len(), 1)?;
+//! let params = retriever.params();
+//! let pertinency_vector = detector.detect_batch(&board);
+//! let indices: Vec<NttRlwe<SecondLevelField>> = (0..params.max_encode_indices_cipher_count())
+//!     .map(|_| detector.encode_pertinent_indices(params, &pertinency_vector))
+//!     .collect();
+//! let seed = [1u8; 32];
+//! let combinations: Vec<NttRlweCiphertext<SecondLevelField>> = detector
+//!     .encode_pertinent_payloads(
+//!         &pertinency_vector,
+//!         &payloads,
+//!         params.combination_count(),
+//!         params.cmb_count_per_cipher(),
+//!         &mut StdRng::from_seed(seed),
+//!     );
+//!
+//! let (retrieved, retrieved_payloads) = retriever.decode_digest(&indices, &combinations, seed)?;
+//! assert_eq!(retrieved, [1]);
+//! assert_eq!(retrieved_payloads, [payloads[1]]);
+//! # Ok::<(), omr_core::OmrError>(())
+//! ```
 
 mod error;
+mod log;
 
 mod parameters;
 mod payload;
@@ -13,19 +60,42 @@ mod payload;
 mod lut;
 pub(crate) mod matrix;
 
+mod context;
 mod detector;
 mod key_gen;
+mod multi_detector;
 pub mod retriever;
 mod sender;
+mod serialize;
+mod streaming_detector;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 
 pub use error::OmrError;
 
+pub use fhe_core::{CmLweCiphertext, LweCiphertext, NttRlweCiphertext, RlweCiphertext};
+pub use lattice::NttRlwe;
+
 pub use parameters::*;
-pub use payload::{Payload, PAYLOAD_LENGTH};
+pub use payload::{
+    Payload, PayloadMatrix, PAYLOAD_CHECKSUM_CONTENT_CAPACITY, PAYLOAD_CONTENT_CAPACITY,
+    PAYLOAD_LENGTH,
+};
 
 pub use lut::LookUpTable;
+pub use matrix::{solve_matrix_mod_256_partial, solve_matrix_mod_256_traced, RowOp, SolveTrace};
 
-pub use detector::{DetectTimeInfo, DetectTimeInfoPerMessage, Detector};
-pub use key_gen::{ClueKey, DetectionKey, KeyGen, SecretKeyPack};
-pub use retriever::Retriever;
-pub use sender::Sender;
+pub use context::{EndToEndTiming, OmrContext, OmrRng};
+pub use detector::{
+    prepare_clues, verify_lut_decode_consistency, CompressStats, DetectTimeInfo,
+    DetectTimeInfoPerMessage, Detector, FixedDetector, PertinencyCiphertext, SecondLevelDetector,
+};
+pub use key_gen::{
+    ClueKey, DetectionKey, KeyGen, KeyGenTimeInfo, RetrieverKey, SecondLevelDetectionKey,
+    SecretKeyPack,
+};
+pub use multi_detector::MultiGenDetector;
+pub use retriever::{retrieve_many, Digest, Retriever};
+pub use sender::{pad_board_to_pow2, FixedClues, FixedSender, Sender, TaggedClues};
+pub use serialize::{deserialize_combinations, serialize_combinations};
+pub use streaming_detector::StreamingDetector;
@@ -0,0 +1,34 @@
+//! Internal logging, forwarded to `tracing` only with the `tracing` feature.
+//!
+//! Without the feature the macros expand to a dead branch: the arguments still
+//! type-check, but nothing is formatted and `tracing` is not a dependency.
+
+/// Logs at debug level, see [`tracing::debug!`].
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        ::tracing::debug!($($arg)*)
+    };
+}
+
+/// Logs at debug level, a no-op without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use debug;
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn debug_compiles_with_and_without_tracing() {
+        let count = 3;
+        super::debug!("decoded {} indices", count);
+        super::debug!("decoded {count} indices");
+    }
+}
@@ -74,16 +74,117 @@ fn arr_get_mut(payloads: &mut [Payload], index: usize) -> &mut Payload {
     unsafe { payloads.get_unchecked_mut(index) }
 }
 
+/// Checks that `matrix * x = payloads` is a well-formed system with at least as many
+/// rows as columns, returning `(num_rows, num_cols)`.
+///
+/// The solvers index without bounds checks, so this must pass before they start.
+fn check_system(
+    matrix: &[Vec<PayloadByteType>],
+    payloads: &[Payload],
+) -> Result<(usize, usize), OmrError> {
+    let num_rows = matrix.len();
+    let num_cols = matrix.first().ok_or(OmrError::EmptyMatrix)?.len();
+
+    if let Some(row) = matrix.iter().find(|row| row.len() != num_cols) {
+        return Err(OmrError::DimensionMismatch {
+            expected: num_cols,
+            found: row.len(),
+        });
+    }
+    if payloads.len() != num_rows {
+        return Err(OmrError::DimensionMismatch {
+            expected: num_rows,
+            found: payloads.len(),
+        });
+    }
+    if num_rows < num_cols {
+        return Err(OmrError::InsufficientCombinations {
+            fed: num_rows,
+            needed: num_cols,
+        });
+    }
+
+    Ok((num_rows, num_cols))
+}
+
 /// Solves a matrix modulo 256.
 pub fn solve_matrix_mod_256(
     matrix: &mut [Vec<PayloadByteType>],
     payloads: &mut [Payload],
 ) -> Result<Vec<Payload>, OmrError> {
-    // Gaussian elimination (forward) + back substitution (mod 256).
-    let num_rows = matrix.len();
-    let num_cols = matrix[0].len();
+    solve_matrix_mod_256_with(matrix, payloads, |_| {})
+}
+
+/// A row operation applied to the payloads while solving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowOp {
+    /// Swaps two rows.
+    Swap(usize, usize),
+    /// `payloads[row] *= scalar`.
+    Scale { row: usize, scalar: PayloadByteType },
+    /// `payloads[target] -= payloads[source] * scalar`.
+    Eliminate {
+        target: usize,
+        source: usize,
+        scalar: PayloadByteType,
+    },
+}
 
-    assert!(num_rows >= num_cols);
+/// The row operations of a mod-256 solve, in the order they were applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SolveTrace {
+    ops: Vec<RowOp>,
+}
+
+impl SolveTrace {
+    /// Returns the recorded row operations.
+    #[inline]
+    pub fn ops(&self) -> &[RowOp] {
+        &self.ops
+    }
+
+    /// Replays the row operations on `payloads` and returns the first `num_cols`
+    /// rows, which is the solution if `payloads` are the right-hand side of the
+    /// traced solve.
+    pub fn replay(&self, payloads: &mut [Payload], num_cols: usize) -> Vec<Payload> {
+        for &op in &self.ops {
+            match op {
+                RowOp::Swap(a, b) => payloads.swap(a, b),
+                RowOp::Scale { row, scalar } => {
+                    payloads[row].mul_scalar_assign(scalar, MODULUS256);
+                }
+                RowOp::Eliminate {
+                    target,
+                    source,
+                    scalar,
+                } => {
+                    let source = payloads[source];
+                    payloads[target].sub_scaled_assign(&source, scalar, MODULUS256);
+                }
+            }
+        }
+        payloads.iter().copied().take(num_cols).collect()
+    }
+}
+
+/// Solves a matrix modulo 256 like [`solve_matrix_mod_256`], and records
+/// the row operations performed on the payloads.
+pub fn solve_matrix_mod_256_traced(
+    matrix: &mut [Vec<PayloadByteType>],
+    payloads: &mut [Payload],
+) -> Result<(Vec<Payload>, SolveTrace), OmrError> {
+    let mut trace = SolveTrace::default();
+    let solution = solve_matrix_mod_256_with(matrix, payloads, |op| trace.ops.push(op))?;
+    Ok((solution, trace))
+}
+
+fn solve_matrix_mod_256_with(
+    matrix: &mut [Vec<PayloadByteType>],
+    payloads: &mut [Payload],
+    mut record: impl FnMut(RowOp),
+) -> Result<Vec<Payload>, OmrError> {
+    // Gaussian elimination (forward) + back substitution (mod 256).
+    let (num_rows, num_cols) = check_system(matrix, payloads)?;
 
     // Gaussian elimination
     for i in 0..num_cols {
@@ -97,15 +198,13 @@ pub fn solve_matrix_mod_256(
         }
 
         // If no such row exists, the matrix is not invertible
-        if odd_index.is_none() {
-            return Err(OmrError::InvertibleMatrix);
-        }
+        let odd_index = odd_index.ok_or(OmrError::InvertibleMatrix)?;
 
         // Swap the rows
-        let odd_index = odd_index.unwrap();
         if i != odd_index {
             matrix.swap(i, odd_index);
             payloads.swap(i, odd_index);
+            record(RowOp::Swap(i, odd_index));
         }
 
         // Normalize the i-th row, so that the (i, i)-th element is 1
@@ -120,6 +219,10 @@ pub fn solve_matrix_mod_256(
                 });
 
             arr_get_mut(payloads, i).mul_scalar_assign(inv, MODULUS256);
+            record(RowOp::Scale {
+                row: i,
+                scalar: inv,
+            });
         }
 
         // If the i-th column is the last column, we are done
@@ -128,6 +231,7 @@ pub fn solve_matrix_mod_256(
         }
 
         // Eliminate the rows after the i-th row and update the payloads
+        let pivot = arr_get(payloads, i);
         for i_rows in i + 1..num_rows {
             let c = mat_get(matrix, i_rows, i);
             if c != 0 {
@@ -136,20 +240,29 @@ pub fn solve_matrix_mod_256(
                     MODULUS256.reduce_sub_assign(mat_get_mut(matrix, i_rows, i_cols), temp);
                 }
 
-                let temp = arr_get(payloads, i).mul_scalar(c, MODULUS256);
-                arr_get_mut(payloads, i_rows).sub_assign(&temp, MODULUS256);
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, MODULUS256);
+                record(RowOp::Eliminate {
+                    target: i_rows,
+                    source: i,
+                    scalar: c,
+                });
             }
         }
     }
 
     // Backward substitution
     for i_cols in (1..num_cols).rev() {
+        let pivot = arr_get(payloads, i_cols);
         for i_rows in 0..i_cols {
             let c = mat_get(matrix, i_rows, i_cols);
             if c != 0 {
                 // payloads[i_rows] -= payloads[i_cols] * c;
-                let temp = arr_get(payloads, i_cols).mul_scalar(c, MODULUS256);
-                arr_get_mut(payloads, i_rows).sub_assign(&temp, MODULUS256);
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, MODULUS256);
+                record(RowOp::Eliminate {
+                    target: i_rows,
+                    source: i_cols,
+                    scalar: c,
+                });
                 mat_set(matrix, i_rows, i_cols, 0);
             }
         }
@@ -158,6 +271,77 @@ pub fn solve_matrix_mod_256(
     Ok(payloads.iter().copied().take(num_cols).collect())
 }
 
+/// Solves as much of a matrix modulo 256 as it determines.
+///
+/// Unlike [`solve_matrix_mod_256`], a column without an odd pivot doesn't fail the
+/// solve: it is skipped and the elimination goes on with the next column. A payload
+/// is returned as `Some` when its pivot row no longer depends on a skipped column,
+/// and `None` otherwise. Also returns the rank, the number of columns with a pivot.
+///
+/// A retriever can feed more combinations and solve again to resolve the rest.
+/// The system is checked like in the other solvers, so it needs at least as many
+/// rows as columns.
+pub fn solve_matrix_mod_256_partial(
+    matrix: &mut [Vec<PayloadByteType>],
+    payloads: &mut [Payload],
+) -> Result<(Vec<Option<Payload>>, usize), OmrError> {
+    // Gauss-Jordan elimination (mod 256), skipping columns without an odd pivot.
+    let (num_rows, num_cols) = check_system(matrix, payloads)?;
+
+    let mut pivot_rows = Vec::with_capacity(num_cols);
+    let mut rank = 0;
+    for i in 0..num_cols {
+        let Some(odd_index) = (rank..num_rows).find(|&j| mat_get(matrix, j, i) % 2 == 1) else {
+            pivot_rows.push(None);
+            continue;
+        };
+        matrix.swap(rank, odd_index);
+        payloads.swap(rank, odd_index);
+
+        // Normalize the pivot row, so that the (rank, i)-th element is 1
+        let inv = INV_MOD_256[mat_get(matrix, rank, i) as usize];
+        mat_row_get_mut(matrix, rank)
+            .iter_mut()
+            .for_each(|w| MODULUS256.reduce_mul_assign(w, inv));
+        arr_get_mut(payloads, rank).mul_scalar_assign(inv, MODULUS256);
+
+        // Eliminate the i-th column from every other row
+        let pivot_row = matrix[rank].clone();
+        let pivot = arr_get(payloads, rank);
+        for i_rows in (0..num_rows).filter(|&j| j != rank) {
+            let c = mat_get(matrix, i_rows, i);
+            if c != 0 {
+                mat_row_get_mut(matrix, i_rows)
+                    .iter_mut()
+                    .zip(pivot_row.iter())
+                    .for_each(|(w, &v)| {
+                        MODULUS256.reduce_sub_assign(w, MODULUS256.reduce_mul(v, c))
+                    });
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, MODULUS256);
+            }
+        }
+
+        pivot_rows.push(Some(rank));
+        rank += 1;
+    }
+
+    let skipped = pivot_rows
+        .iter()
+        .enumerate()
+        .filter(|(_, row)| row.is_none())
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+    let solved = pivot_rows
+        .iter()
+        .map(|row| {
+            row.filter(|&j| skipped.iter().all(|&i| mat_get(matrix, j, i) == 0))
+                .map(|j| arr_get(payloads, j))
+        })
+        .collect();
+
+    Ok((solved, rank))
+}
+
 const MODULUS_257: BarrettModulus<PayloadByteType> = <BarrettModulus<PayloadByteType>>::new(257);
 
 /// Solves a matrix modulo 257.
@@ -166,10 +350,7 @@ pub fn solve_matrix_mod_257(
     payloads: &mut [Payload],
 ) -> Result<Vec<Payload>, OmrError> {
     // Gaussian elimination (forward) + back substitution (mod 257).
-    let num_rows = matrix.len();
-    let num_cols = matrix[0].len();
-
-    assert!(num_rows >= num_cols);
+    let (num_rows, num_cols) = check_system(matrix, payloads)?;
 
     // Gaussian elimination
     for i in 0..num_cols {
@@ -183,12 +364,9 @@ pub fn solve_matrix_mod_257(
         }
 
         // If no such row exists, the matrix is not invertible
-        if pick_index.is_none() {
-            return Err(OmrError::InvertibleMatrix);
-        }
+        let pick_index = pick_index.ok_or(OmrError::InvertibleMatrix)?;
 
         // Swap the rows
-        let pick_index = pick_index.unwrap();
         if i != pick_index {
             matrix.swap(i, pick_index);
             payloads.swap(i, pick_index);
@@ -215,6 +393,7 @@ pub fn solve_matrix_mod_257(
         }
 
         // Eliminate the rows after the i-th row and update the payloads
+        let pivot = arr_get(payloads, i);
         for i_rows in i + 1..num_rows {
             let c = mat_get(matrix, i_rows, i);
 
@@ -224,20 +403,19 @@ pub fn solve_matrix_mod_257(
                     MODULUS_257.reduce_sub_assign(mat_get_mut(matrix, i_rows, i_cols), temp);
                 }
 
-                let temp = arr_get(payloads, i).mul_scalar(c, MODULUS_257);
-                arr_get_mut(payloads, i_rows).sub_assign(&temp, MODULUS_257);
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, MODULUS_257);
             }
         }
     }
 
     // Backward substitution
     for i_cols in (1..num_cols).rev() {
+        let pivot = arr_get(payloads, i_cols);
         for i_rows in 0..i_cols {
             let c = mat_get(matrix, i_rows, i_cols);
 
             if c != 0 {
-                let temp = arr_get(payloads, i_cols).mul_scalar(c, MODULUS_257);
-                arr_get_mut(payloads, i_rows).sub_assign(&temp, MODULUS_257);
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, MODULUS_257);
                 mat_set(matrix, i_rows, i_cols, 0);
             }
         }
@@ -254,10 +432,7 @@ pub fn solve_matrix<M: RingReduce<PayloadByteType>>(
     modulus_value: PayloadByteType,
 ) -> Result<Vec<Payload>, OmrError> {
     // Gaussian elimination (forward) + back substitution (mod p).
-    let num_rows = matrix.len();
-    let num_cols = matrix[0].len();
-
-    assert!(num_rows >= num_cols);
+    let (num_rows, num_cols) = check_system(matrix, payloads)?;
 
     // Gaussian elimination
     for i in 0..num_cols {
@@ -271,12 +446,9 @@ pub fn solve_matrix<M: RingReduce<PayloadByteType>>(
         }
 
         // If no such row exists, the matrix is not invertible
-        if pick_index.is_none() {
-            return Err(OmrError::InvertibleMatrix);
-        }
+        let pick_index = pick_index.ok_or(OmrError::InvertibleMatrix)?;
 
         // Swap the rows
-        let pick_index = pick_index.unwrap();
         if i != pick_index {
             matrix.swap(i, pick_index);
             payloads.swap(i, pick_index);
@@ -286,7 +458,9 @@ pub fn solve_matrix<M: RingReduce<PayloadByteType>>(
         let value = mat_get(matrix, i, i);
         if value != 1 {
             let (inv, gcd) = Xgcd::gcdinv(value, modulus_value);
-            assert_eq!(gcd, 1, "value: {}", value);
+            if gcd != 1 {
+                return Err(OmrError::InvertibleMatrix);
+            }
 
             mat_set(matrix, i, i, 1);
             mat_row_get_mut(matrix, i)[i + 1..]
@@ -304,6 +478,7 @@ pub fn solve_matrix<M: RingReduce<PayloadByteType>>(
         }
 
         // Eliminate the rows after the i-th row and update the payloads
+        let pivot = arr_get(payloads, i);
         for i_rows in i + 1..num_rows {
             let c = mat_get(matrix, i_rows, i);
 
@@ -313,20 +488,19 @@ pub fn solve_matrix<M: RingReduce<PayloadByteType>>(
                     modulus.reduce_sub_assign(mat_get_mut(matrix, i_rows, i_cols), temp);
                 }
 
-                let temp = arr_get(payloads, i).mul_scalar(c, modulus);
-                arr_get_mut(payloads, i_rows).sub_assign(&temp, modulus);
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, modulus);
             }
         }
     }
 
     // Backward substitution
     for i_cols in (1..num_cols).rev() {
+        let pivot = arr_get(payloads, i_cols);
         for i_rows in 0..i_cols {
             let c = mat_get(matrix, i_rows, i_cols);
 
             if c != 0 {
-                let temp = arr_get(payloads, i_cols).mul_scalar(c, modulus);
-                arr_get_mut(payloads, i_rows).sub_assign(&temp, modulus);
+                arr_get_mut(payloads, i_rows).sub_scaled_assign(&pivot, c, modulus);
                 mat_set(matrix, i_rows, i_cols, 0);
             }
         }
@@ -351,3 +525,134 @@ fn feature() {
 
     println!("{:?}", inv);
 }
+
+#[test]
+fn partial_solve_recovers_the_determined_payloads() {
+    let mut rng = rand::thread_rng();
+
+    // The last column is all zero, the first three are invertible mod 2.
+    let matrix: Vec<Vec<PayloadByteType>> = vec![
+        vec![1, 2, 4, 0],
+        vec![3, 1, 0, 0],
+        vec![0, 5, 1, 0],
+        vec![2, 2, 2, 0],
+        vec![7, 0, 1, 0],
+    ];
+    let solution: Vec<Payload> = (0..4).map(|_| Payload::random(&mut rng)).collect();
+    let combined: Vec<Payload> = matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(solution.iter())
+                .fold(Payload::new(), |mut acc, (&w, x)| {
+                    acc.add_scaled_assign(x, w, MODULUS256);
+                    acc
+                })
+        })
+        .collect();
+
+    assert!(solve_matrix_mod_256(&mut matrix.clone(), &mut combined.clone()).is_err());
+
+    let (solved, rank) =
+        solve_matrix_mod_256_partial(&mut matrix.clone(), &mut combined.clone()).unwrap();
+    assert_eq!(rank, 3);
+    assert_eq!(
+        solved,
+        [
+            Some(solution[0]),
+            Some(solution[1]),
+            Some(solution[2]),
+            None
+        ]
+    );
+
+    // Malformed systems are rejected before any unchecked indexing.
+    let mut ragged = matrix.clone();
+    ragged[2].pop();
+    assert!(matches!(
+        solve_matrix_mod_256_partial(&mut ragged, &mut combined.clone()),
+        Err(OmrError::DimensionMismatch { .. })
+    ));
+    assert!(matches!(
+        solve_matrix_mod_256_partial(&mut matrix.clone(), &mut combined[..4].to_vec()),
+        Err(OmrError::DimensionMismatch { .. })
+    ));
+    assert!(matches!(
+        solve_matrix_mod_256_partial(&mut [], &mut []),
+        Err(OmrError::EmptyMatrix)
+    ));
+}
+
+#[test]
+fn traced_solve_replays() {
+    let mut rng = rand::thread_rng();
+
+    // det = -1, invertible mod 256; the zero pivot in row 0 forces a swap.
+    let matrix: Vec<Vec<PayloadByteType>> = vec![vec![0, 1, 4], vec![1, 2, 3], vec![5, 6, 0]];
+    let solution: Vec<Payload> = (0..3).map(|_| Payload::random(&mut rng)).collect();
+    let combined: Vec<Payload> = matrix
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(solution.iter())
+                .fold(Payload::new(), |mut acc, (&w, x)| {
+                    acc.add_scaled_assign(x, w, MODULUS256);
+                    acc
+                })
+        })
+        .collect();
+
+    let (solved, trace) =
+        solve_matrix_mod_256_traced(&mut matrix.clone(), &mut combined.clone()).unwrap();
+    assert_eq!(solved, solution);
+    assert!(matches!(trace.ops()[0], RowOp::Swap(0, 1)));
+
+    let replayed = trace.replay(&mut combined.clone(), 3);
+    assert_eq!(replayed, solution);
+}
+
+#[test]
+fn malformed_systems_are_rejected() {
+    let payloads = vec![Payload::new(); 3];
+
+    assert!(matches!(
+        solve_matrix_mod_256(&mut [], &mut []),
+        Err(OmrError::EmptyMatrix)
+    ));
+
+    let mut ragged = vec![vec![1, 0], vec![0, 1, 0], vec![1, 1]];
+    assert!(matches!(
+        solve_matrix_mod_257(&mut ragged, &mut payloads.clone()),
+        Err(OmrError::DimensionMismatch {
+            expected: 2,
+            found: 3
+        })
+    ));
+
+    let mut square = vec![vec![1, 0, 0], vec![0, 1, 0], vec![0, 0, 1]];
+    assert!(matches!(
+        solve_matrix_mod_256(&mut square, &mut payloads[..2].to_vec()),
+        Err(OmrError::DimensionMismatch {
+            expected: 3,
+            found: 2
+        })
+    ));
+
+    let mut wide = vec![vec![1, 0, 0], vec![0, 1, 0]];
+    assert!(matches!(
+        solve_matrix_mod_256(&mut wide, &mut payloads[..2].to_vec()),
+        Err(OmrError::InsufficientCombinations { fed: 2, needed: 3 })
+    ));
+
+    // 2 has no inverse modulo 4.
+    let mut singular = vec![vec![2, 1], vec![0, 1]];
+    assert!(matches!(
+        solve_matrix(
+            &mut singular,
+            &mut payloads[..2].to_vec(),
+            <PowOf2Modulus<PayloadByteType>>::new(4),
+            4
+        ),
+        Err(OmrError::InvertibleMatrix)
+    ));
+}
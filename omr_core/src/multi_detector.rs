@@ -0,0 +1,188 @@
+//! Detection across several parameter generations during a migration window.
+
+use fhe_core::CmLweCiphertext;
+
+use crate::{ClueValue, Detector, OmrError, OmrParameters, PertinencyCiphertext, TaggedClues};
+
+/// Holds one [`Detector`] per parameter generation and routes each clue bundle
+/// to the generation it was produced for.
+pub struct MultiGenDetector {
+    generations: Vec<(u8, Detector)>,
+    /// The [`OmrParameters::fingerprint`] of each generation, in the same order.
+    fingerprints: Vec<u64>,
+}
+
+impl MultiGenDetector {
+    /// Creates a new [`MultiGenDetector`] from `(generation, detector)` pairs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a generation appears twice.
+    pub fn new(generations: Vec<(u8, Detector)>) -> Self {
+        for (i, (generation, _)) in generations.iter().enumerate() {
+            assert!(
+                generations[..i].iter().all(|(g, _)| g != generation),
+                "Duplicate generation {generation}."
+            );
+        }
+        let fingerprints = generations
+            .iter()
+            .map(|(_, detector)| detector.detection_key().params().fingerprint())
+            .collect();
+        Self {
+            generations,
+            fingerprints,
+        }
+    }
+
+    /// Returns the detector of `generation`, if any.
+    pub fn detector(&self, generation: u8) -> Option<&Detector> {
+        self.generations
+            .iter()
+            .find(|(g, _)| *g == generation)
+            .map(|(_, detector)| detector)
+    }
+
+    /// Detects `clues` with the detector of the generation whose parameter
+    /// fingerprint they are tagged with.
+    ///
+    /// With `gen_hint`, that generation is used after checking that it has the
+    /// fingerprint of the bundle. Generations usually differ in noise or moduli
+    /// only, so the fingerprint tells them apart where the bundle shape can't.
+    pub fn detect_any(
+        &self,
+        clues: &TaggedClues,
+        gen_hint: Option<u8>,
+    ) -> Result<(u8, PertinencyCiphertext), OmrError> {
+        let candidates = self
+            .generations
+            .iter()
+            .zip(&self.fingerprints)
+            .filter(|(_, &fingerprint)| fingerprint == clues.fingerprint())
+            .map(|((generation, _), _)| *generation)
+            .collect::<Vec<u8>>();
+
+        self.detect_routed(clues.as_clues(), &candidates, gen_hint)
+    }
+
+    /// Detects an untagged clue bundle by trying every generation.
+    ///
+    /// With `gen_hint`, that generation is used after checking that the bundle fits
+    /// its parameters. Otherwise the generation is picked by the bundle shape
+    /// (clue count and clue dimension), which must match exactly one generation.
+    /// Prefer [`MultiGenDetector::detect_any`] whenever the bundle is tagged.
+    pub fn detect_untagged(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+        gen_hint: Option<u8>,
+    ) -> Result<(u8, PertinencyCiphertext), OmrError> {
+        let candidates = self
+            .generations
+            .iter()
+            .filter(|(_, detector)| fits(clues, detector.detection_key().params()))
+            .map(|(generation, _)| *generation)
+            .collect::<Vec<u8>>();
+
+        self.detect_routed(clues, &candidates, gen_hint)
+    }
+
+    /// Detects `clues` with the generation [`route_generation`] picks.
+    fn detect_routed(
+        &self,
+        clues: &CmLweCiphertext<ClueValue>,
+        candidates: &[u8],
+        gen_hint: Option<u8>,
+    ) -> Result<(u8, PertinencyCiphertext), OmrError> {
+        let generation = route_generation(candidates, gen_hint)?;
+        let detector = self.detector(generation).unwrap();
+
+        Ok((generation, detector.detect(clues)))
+    }
+}
+
+/// Checks that the clue bundle has the clue count and dimension of `params`.
+fn fits(clues: &CmLweCiphertext<ClueValue>, params: &OmrParameters) -> bool {
+    clues.msg_count() == params.clue_count() && clues.a().len() == params.clue_dimension()
+}
+
+/// Picks the generation among the `candidates` whose parameters fit a bundle.
+fn route_generation(candidates: &[u8], gen_hint: Option<u8>) -> Result<u8, OmrError> {
+    match (gen_hint, candidates) {
+        (Some(hint), _) if candidates.contains(&hint) => Ok(hint),
+        (Some(hint), _) => Err(OmrError::UnknownGeneration { generation: hint }),
+        (None, []) => Err(OmrError::NoMatchingGeneration),
+        (None, [generation]) => Ok(*generation),
+        (None, _) => Err(OmrError::AmbiguousGeneration {
+            candidates: candidates.len(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::KeyGen;
+
+    #[test]
+    fn tagged_clues_are_detected_by_their_generation() {
+        let mut rng = StdRng::seed_from_u64(41);
+        // Same bundle shape, different fingerprints.
+        let params_a = OmrParameters::new();
+        let params_b = OmrParameters::new().with_clue_noise_standard_deviation(3.0);
+        assert_ne!(params_a.fingerprint(), params_b.fingerprint());
+
+        let key_a = KeyGen::generate_secret_key(params_a, &mut rng);
+        let key_b = KeyGen::generate_secret_key(params_b, &mut rng);
+        let detector = MultiGenDetector::new(vec![
+            (0xA, key_a.generate_detector(&mut rng)),
+            (0xB, key_b.generate_detector(&mut rng)),
+        ]);
+
+        let clues_a = key_a.generate_sender(&mut rng).gen_tagged_clues(&mut rng);
+        let clues_b = key_b.generate_sender(&mut rng).gen_tagged_clues(&mut rng);
+
+        let (generation, pertinency) = detector.detect_any(&clues_a, None).unwrap();
+        assert_eq!(generation, 0xA);
+        assert_eq!(key_a.decrypt_pertinency(&pertinency)[0], 1);
+
+        let (generation, pertinency) = detector.detect_any(&clues_b, None).unwrap();
+        assert_eq!(generation, 0xB);
+        assert_eq!(key_b.decrypt_pertinency(&pertinency)[0], 1);
+
+        // The shape alone can't pick a generation, the fingerprint can.
+        assert!(matches!(
+            detector.detect_untagged(clues_a.as_clues(), None),
+            Err(OmrError::AmbiguousGeneration { candidates: 2 })
+        ));
+        assert!(matches!(
+            detector.detect_any(&clues_a, Some(0xB)),
+            Err(OmrError::UnknownGeneration { generation: 0xB })
+        ));
+    }
+
+    #[test]
+    fn clues_are_routed_to_their_generation() {
+        // Bundles of different shapes: only one generation is a candidate.
+        assert_eq!(route_generation(&[0xA], None).unwrap(), 0xA);
+        assert_eq!(route_generation(&[0xB], None).unwrap(), 0xB);
+
+        // Same shape in both generations: the hint decides.
+        assert_eq!(route_generation(&[0xA, 0xB], Some(0xB)).unwrap(), 0xB);
+        assert!(matches!(
+            route_generation(&[0xA, 0xB], None),
+            Err(OmrError::AmbiguousGeneration { candidates: 2 })
+        ));
+
+        // A hint for a generation the bundle does not fit is rejected.
+        assert!(matches!(
+            route_generation(&[0xA], Some(0xB)),
+            Err(OmrError::UnknownGeneration { generation: 0xB })
+        ));
+        assert!(matches!(
+            route_generation(&[], None),
+            Err(OmrError::NoMatchingGeneration)
+        ));
+    }
+}
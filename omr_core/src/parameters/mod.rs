@@ -1,17 +1,28 @@
 //! Parameter types and defaults for two-layer InstantOMR.
 
+use std::fmt::Debug;
+
 use algebra::{
-    decompose::NonPowOf2ApproxSignedBasis, modulus::PowOf2Modulus, random::DiscreteGaussian,
-    reduce::ModulusValue, Field, NttField, U32FieldEval, U64FieldEval,
+    decompose::NonPowOf2ApproxSignedBasis, integer::UnsignedInteger, modulus::PowOf2Modulus,
+    random::DiscreteGaussian, reduce::ModulusValue, Field, NttField, U32FieldEval, U64FieldEval,
 };
 use fhe_core::{
     GadgetRlweParameters, KeySwitchingParameters, LweParameters, LweSecretKeyType,
     RingSecretKeyType,
 };
 
+use crate::{
+    detector::{first_level_delta, second_level_delta},
+    OmrError,
+};
+
+mod noise;
 mod retrieval_params;
 
-pub use retrieval_params::RetrievalParams;
+pub use noise::NoiseBudget;
+pub use retrieval_params::{
+    DigestShape, RetrievalParams, RetrievalParamsFingerprint, RETRIEVAL_FINGERPRINT_BYTES,
+};
 
 pub type ClueValue = u16;
 pub type ClueModulus = PowOf2Modulus<ClueValue>;
@@ -21,11 +32,53 @@ pub type InterLweModulus = PowOf2Modulus<InterLweValue>;
 pub type SecondLevelField = U64FieldEval<1125899906826241>;
 pub type OutputValue = <SecondLevelField as Field>::ValueT;
 
+/// The plaintext every clue of a bundle encrypts, see [`OmrParameters::match_clue_value`].
+pub const MATCH_CLUE_VALUE: ClueValue = 0;
+
+/// 64-bit FNV-1a hash, used for parameter fingerprints and payload checksums.
+pub(crate) fn fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Plaintext moduli and LUT scalings of the two bootstrapping levels,
+/// for encoding and verifying values at each stage of the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingInfo {
+    /// The plain modulus of the clues.
+    pub clue_plain_modulus: ClueValue,
+    /// The plain modulus of the intermediate LWE ciphertexts.
+    pub intermediate_plain_modulus: InterLweValue,
+    /// The plain modulus of the detection output.
+    pub output_plain_modulus: OutputValue,
+    /// The scaling of `1` in the first level LUT.
+    pub first_level_delta: InterLweValue,
+    /// The scaling of `1` in the second level LUT.
+    pub second_level_delta: OutputValue,
+}
+
+/// Every modulus of the detection pipeline, for status reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModulusSummary {
+    /// The cipher modulus of the clues.
+    pub clue_cipher_modulus: u64,
+    /// The modulus of the first level field.
+    pub first_level_modulus: u64,
+    /// The cipher modulus of the intermediate LWE ciphertexts.
+    pub intermediate_cipher_modulus: u64,
+    /// The modulus of the second level field.
+    pub second_level_modulus: u64,
+    /// The plain modulus of the detection output.
+    pub output_plain_modulus: u64,
+}
+
 /// Cryptographic parameters for two-layer bootstrapping and RLWE encoding.
 #[derive(Clone)]
 pub struct OmrParameters {
     clue_params: LweParameters<ClueValue, ClueModulus>,
     clue_count: usize,
+    mismatch_tolerance: usize,
     first_level_blind_rotation_params: GadgetRlweParameters<FirstLevelField>,
     first_level_key_switching_params: KeySwitchingParameters,
     intermediate_lwe_params: LweParameters<InterLweValue, InterLweModulus>,
@@ -95,6 +148,7 @@ impl OmrParameters {
         Self {
             clue_params,
             clue_count,
+            mismatch_tolerance: 0,
             first_level_blind_rotation_params,
             first_level_key_switching_params,
             intermediate_lwe_params,
@@ -110,6 +164,114 @@ impl OmrParameters {
         self.clue_count
     }
 
+    /// Returns the plaintext a clue encrypts, [`MATCH_CLUE_VALUE`].
+    ///
+    /// This is one half of the encoding contract between sender and detector: a clue
+    /// decrypted under the recipient's key gives this value, which the first level LUT
+    /// maps to 1, and anything else maps to 0 or -1.
+    #[inline]
+    pub fn match_clue_value(&self) -> ClueValue {
+        MATCH_CLUE_VALUE
+    }
+
+    /// Returns the sum of first level LUT outputs of a matching bundle, `clue_count`.
+    ///
+    /// This is the other half of the contract: the second level LUT fires only at
+    /// this sum, i.e. when every clue decrypts to [`OmrParameters::match_clue_value`].
+    #[inline]
+    pub fn match_sum_value(&self) -> usize {
+        self.clue_count
+    }
+
+    /// Returns the number of clues of a bundle that may fail to match while the
+    /// detector still reports it as pertinent, 0 by default.
+    ///
+    /// With tolerance `t` the second level LUT fires on the clue sums
+    /// `match_sum - t..=match_sum`, so a bundle is pertinent once `clue_count - t`
+    /// of its clues match.
+    #[inline]
+    pub fn mismatch_tolerance(&self) -> usize {
+        self.mismatch_tolerance
+    }
+
+    /// Returns these parameters with a detector accepting up to `tolerance`
+    /// mismatching clues per bundle, see [`OmrParameters::mismatch_tolerance`].
+    ///
+    /// The tolerance only shapes the detector's second level LUT, senders and
+    /// retrievers are unaffected, so it is not part of [`OmrParameters::fingerprint`].
+    pub fn with_mismatch_tolerance(mut self, tolerance: usize) -> Result<Self, OmrError> {
+        self.mismatch_tolerance = tolerance;
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Returns this [`OmrParameters`] with the clue LWE dimension set to `clue_dimension`.
+    ///
+    /// The first level blind rotation key is generated from the clue secret key,
+    /// so it follows this dimension. A smaller dimension gives shorter clues
+    /// at the cost of security.
+    pub fn with_clue_dimension(mut self, clue_dimension: usize) -> Result<Self, OmrError> {
+        self.clue_params = <LweParameters<ClueValue, ClueModulus>>::new(
+            clue_dimension,
+            self.clue_params.plain_modulus_value,
+            self.clue_params.cipher_modulus,
+            self.clue_params.secret_key_type,
+            self.clue_params.noise_standard_deviation,
+        );
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Returns these parameters with another clue noise, a change that keeps the
+    /// clue bundle shape but not the fingerprint.
+    #[cfg(test)]
+    pub(crate) fn with_clue_noise_standard_deviation(mut self, standard_deviation: f64) -> Self {
+        self.clue_params.noise_standard_deviation = standard_deviation;
+        self
+    }
+
+    /// Returns these parameters with `clue_count` clues per bundle.
+    pub fn with_clue_count(mut self, clue_count: usize) -> Result<Self, OmrError> {
+        self.clue_count = clue_count;
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Checks that these parameters describe a usable detection pipeline.
+    ///
+    /// A bundle without clues sums to the zero ciphertext in the first level
+    /// bootstrapping, so detection would be meaningless. The same holds for clues
+    /// of dimension zero, which carry no mask.
+    pub fn validate(&self) -> Result<(), OmrError> {
+        if self.clue_count == 0 {
+            return Err(OmrError::InvalidParameters {
+                reason: "clue count must be positive",
+            });
+        }
+        if self.mismatch_tolerance >= self.clue_count {
+            return Err(OmrError::InvalidParameters {
+                reason: "mismatch tolerance must be below the clue count",
+            });
+        }
+        if self.clue_dimension() == 0 {
+            return Err(OmrError::InvalidParameters {
+                reason: "clue dimension must be positive",
+            });
+        }
+        if self.payloads_per_combination_cipher() == 0 {
+            return Err(OmrError::InvalidParameters {
+                reason: "a combination ciphertext must hold at least one payload",
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the clue LWE dimension of this [`OmrParameters`].
+    #[inline]
+    pub fn clue_dimension(&self) -> usize {
+        self.clue_params.dimension
+    }
+
     /// Returns a reference to the clue params of this [`OmrParameters`].
     #[inline]
     pub fn clue_params(&self) -> &LweParameters<ClueValue, ClueModulus> {
@@ -210,6 +372,13 @@ impl OmrParameters {
         self.second_level_blind_rotation_params.dimension
     }
 
+    /// Returns how many whole payloads fit in one combination ciphertext of the second
+    /// level ring, like [`RetrievalParams::payload_slots_per_cipher`].
+    #[inline]
+    pub fn payloads_per_combination_cipher(&self) -> usize {
+        retrieval_params::payload_slots_per_cipher(self.second_level_ring_dimension())
+    }
+
     /// Returns the second level ring secret key type of this [`OmrParameters`].
     #[inline]
     pub fn second_level_ring_secret_key_type(&self) -> RingSecretKeyType {
@@ -255,4 +424,415 @@ impl OmrParameters {
     pub fn output_plain_modulus_value(&self) -> <SecondLevelField as Field>::ValueT {
         self.output_plain_modulus_value
     }
+
+    /// Lists human-readable differences between this [`OmrParameters`] and `other`,
+    /// one entry per differing field. An empty list means the two sets are identical.
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        self.fields()
+            .into_iter()
+            .zip(other.fields())
+            .filter(|((_, a), (_, b))| a != b)
+            .map(|((name, a), (_, b))| format!("{name}: {a} != {b}"))
+            .collect()
+    }
+
+    /// Returns a fingerprint of every field of this [`OmrParameters`].
+    ///
+    /// Two parameter sets with a different [`OmrParameters::diff`] get different
+    /// fingerprints with overwhelming probability.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(self.fields().iter().flat_map(|(name, value)| {
+            name.bytes()
+                .chain([b'='])
+                .chain(value.bytes())
+                .chain([b';'])
+        }))
+    }
+
+    /// Lists every field as `(name, value)`, in a fixed order.
+    fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+
+        push_field(&mut fields, "clue_count", self.clue_count);
+        push_lwe_params(&mut fields, "clue_params", &self.clue_params);
+        push_gadget_rlwe_params(
+            &mut fields,
+            "first_level_blind_rotation_params",
+            &self.first_level_blind_rotation_params,
+        );
+
+        let ks = &self.first_level_key_switching_params;
+        let name = "first_level_key_switching_params";
+        push_field(
+            &mut fields,
+            &format!("{name}.input_cipher_dimension"),
+            ks.input_cipher_dimension,
+        );
+        push_field(
+            &mut fields,
+            &format!("{name}.output_cipher_dimension"),
+            ks.output_cipher_dimension,
+        );
+        push_field(&mut fields, &format!("{name}.log_modulus"), ks.log_modulus);
+        push_field(&mut fields, &format!("{name}.log_basis"), ks.log_basis);
+        push_field(
+            &mut fields,
+            &format!("{name}.reverse_length"),
+            ks.reverse_length,
+        );
+        push_field(
+            &mut fields,
+            &format!("{name}.noise_standard_deviation"),
+            ks.noise_standard_deviation,
+        );
+
+        push_lwe_params(
+            &mut fields,
+            "intermediate_lwe_params",
+            &self.intermediate_lwe_params,
+        );
+        push_gadget_rlwe_params(
+            &mut fields,
+            "second_level_blind_rotation_params",
+            &self.second_level_blind_rotation_params,
+        );
+        push_gadget_rlwe_params(&mut fields, "hom_trace_params", &self.hom_trace_params);
+        push_field(
+            &mut fields,
+            "output_plain_modulus_value",
+            self.output_plain_modulus_value,
+        );
+
+        fields
+    }
+
+    /// Returns the fields, named as in [`OmrParameters::diff`], that change the size
+    /// of keys or ciphertexts.
+    ///
+    /// All the other fields (noise standard deviations, secret key types and plain
+    /// moduli) only affect security and correctness, so they can be tuned without
+    /// changing any wire size.
+    pub fn size_sensitive_fields() -> &'static [&'static str] {
+        &[
+            "clue_count",
+            "clue_params.dimension",
+            "clue_params.cipher_modulus_value",
+            "first_level_blind_rotation_params.dimension",
+            "first_level_blind_rotation_params.modulus",
+            "first_level_blind_rotation_params.basis",
+            "first_level_key_switching_params.input_cipher_dimension",
+            "first_level_key_switching_params.output_cipher_dimension",
+            "first_level_key_switching_params.log_modulus",
+            "first_level_key_switching_params.log_basis",
+            "first_level_key_switching_params.reverse_length",
+            "intermediate_lwe_params.dimension",
+            "intermediate_lwe_params.cipher_modulus_value",
+            "second_level_blind_rotation_params.dimension",
+            "second_level_blind_rotation_params.modulus",
+            "second_level_blind_rotation_params.basis",
+            "hom_trace_params.dimension",
+            "hom_trace_params.modulus",
+            "hom_trace_params.basis",
+        ]
+    }
+
+    /// Returns the in-memory size in bytes of one clue bundle: the shared `a` of
+    /// `clue_dimension` values and one `b` per clue.
+    pub fn estimated_clue_size(&self) -> usize {
+        (self.clue_dimension() + self.clue_count) * std::mem::size_of::<ClueValue>()
+    }
+
+    /// Returns the in-memory size in bytes of the detection output of one message,
+    /// an NTT RLWE ciphertext of the second level ring.
+    pub fn estimated_detection_output_size(&self) -> usize {
+        2 * self.second_level_ring_dimension() * std::mem::size_of::<OutputValue>()
+    }
+
+    /// Returns the in-memory size in bytes of the pertinency vector of a board of
+    /// `board_size` messages, i.e. `board_size` detection outputs.
+    ///
+    /// Compare it to the available memory to choose between
+    /// [`Detector::detect_batch`](crate::Detector::detect_batch), which holds the
+    /// whole vector, and a [`StreamingDetector`](crate::StreamingDetector).
+    pub fn pertinency_vector_bytes(&self, board_size: usize) -> usize {
+        board_size * self.pertinency_ciphertext_bytes()
+    }
+
+    /// Returns the in-memory size in bytes of one
+    /// [`PertinencyCiphertext`](crate::PertinencyCiphertext), the same as
+    /// [`OmrParameters::estimated_detection_output_size`].
+    #[inline]
+    pub fn pertinency_ciphertext_bytes(&self) -> usize {
+        self.estimated_detection_output_size()
+    }
+
+    /// Returns the [`ModulusSummary`] of this [`OmrParameters`].
+    pub fn modulus_summary(&self) -> ModulusSummary {
+        ModulusSummary {
+            clue_cipher_modulus: modulus_value_to_u64(self.clue_params.cipher_modulus_value),
+            first_level_modulus: self.first_level_blind_rotation_params.modulus as u64,
+            intermediate_cipher_modulus: modulus_value_to_u64(
+                self.intermediate_lwe_params.cipher_modulus_value,
+            ),
+            second_level_modulus: self.second_level_blind_rotation_params.modulus,
+            output_plain_modulus: self.output_plain_modulus_value,
+        }
+    }
+
+    /// Returns the [`EncodingInfo`] of this [`OmrParameters`].
+    pub fn encoding_info(&self) -> EncodingInfo {
+        let intermediate_plain_modulus = self.intermediate_lwe_plain_modulus_value();
+        EncodingInfo {
+            clue_plain_modulus: self.clue_plain_modulus_value(),
+            intermediate_plain_modulus,
+            output_plain_modulus: self.output_plain_modulus_value,
+            first_level_delta: first_level_delta(intermediate_plain_modulus as usize),
+            second_level_delta: second_level_delta(self.output_plain_modulus_value as usize),
+        }
+    }
+}
+
+fn modulus_value_to_u64<T: UnsignedInteger + Into<u64>>(value: ModulusValue<T>) -> u64 {
+    match value {
+        ModulusValue::Native => 1 << (std::mem::size_of::<T>() * 8),
+        ModulusValue::PowerOf2(q) | ModulusValue::Prime(q) | ModulusValue::Others(q) => q.into(),
+    }
+}
+
+fn push_field<T: Debug>(fields: &mut Vec<(String, String)>, name: &str, value: T) {
+    fields.push((name.to_owned(), format!("{value:?}")));
+}
+
+fn push_lwe_params<C, M>(
+    fields: &mut Vec<(String, String)>,
+    name: &str,
+    params: &LweParameters<C, M>,
+) where
+    C: UnsignedInteger,
+{
+    push_field(fields, &format!("{name}.dimension"), params.dimension);
+    push_field(
+        fields,
+        &format!("{name}.plain_modulus_value"),
+        params.plain_modulus_value,
+    );
+    push_field(
+        fields,
+        &format!("{name}.cipher_modulus_value"),
+        params.cipher_modulus_value,
+    );
+    push_field(
+        fields,
+        &format!("{name}.secret_key_type"),
+        params.secret_key_type,
+    );
+    push_field(
+        fields,
+        &format!("{name}.noise_standard_deviation"),
+        params.noise_standard_deviation,
+    );
+}
+
+fn push_gadget_rlwe_params<F: NttField>(
+    fields: &mut Vec<(String, String)>,
+    name: &str,
+    params: &GadgetRlweParameters<F>,
+) {
+    push_field(fields, &format!("{name}.dimension"), params.dimension);
+    push_field(fields, &format!("{name}.modulus"), params.modulus);
+    push_field(
+        fields,
+        &format!("{name}.secret_key_type"),
+        params.secret_key_type,
+    );
+    push_field(
+        fields,
+        &format!("{name}.noise_standard_deviation"),
+        params.noise_standard_deviation,
+    );
+    push_field(fields, &format!("{name}.basis"), &params.basis);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        detector::{first_level_lut, second_level_lut},
+        PAYLOAD_LENGTH,
+    };
+
+    use super::*;
+
+    #[test]
+    fn noise_only_changes_keep_sizes() {
+        let params = OmrParameters::new();
+        let mut other = params.clone();
+        other.clue_params.noise_standard_deviation *= 2.0;
+        other
+            .second_level_blind_rotation_params
+            .noise_standard_deviation *= 2.0;
+
+        assert_eq!(params.estimated_clue_size(), other.estimated_clue_size());
+        assert_eq!(
+            params.estimated_detection_output_size(),
+            other.estimated_detection_output_size()
+        );
+        for diff in params.diff(&other) {
+            assert!(OmrParameters::size_sensitive_fields()
+                .iter()
+                .all(|field| !diff.starts_with(&format!("{field}:"))));
+        }
+
+        assert_eq!(params.estimated_clue_size(), (512 + 7) * 2);
+        assert_eq!(params.estimated_detection_output_size(), 2 * 2048 * 8);
+    }
+
+    #[test]
+    fn pertinency_vector_bytes_match_allocation() {
+        use fhe_core::NttRlweCiphertext;
+
+        use crate::PertinencyCiphertext;
+
+        let params = OmrParameters::new();
+        let ct = NttRlweCiphertext::<SecondLevelField>::zero(params.second_level_ring_dimension());
+        let board = vec![PertinencyCiphertext::from_ntt(ct); 10];
+        let coefficient_bytes = |ct: &PertinencyCiphertext| {
+            std::mem::size_of_val(ct.as_ntt().a().as_slice())
+                + std::mem::size_of_val(ct.as_ntt().b().as_slice())
+        };
+
+        assert_eq!(
+            params.pertinency_ciphertext_bytes(),
+            coefficient_bytes(&board[0])
+        );
+        assert_eq!(
+            params.pertinency_vector_bytes(board.len()),
+            board.iter().map(coefficient_bytes).sum::<usize>()
+        );
+        assert_eq!(params.pertinency_vector_bytes(0), 0);
+    }
+
+    #[test]
+    fn zero_clue_count_is_rejected() {
+        assert!(OmrParameters::new().validate().is_ok());
+        assert!(matches!(
+            OmrParameters::new().with_clue_count(0),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+        assert_eq!(
+            OmrParameters::new()
+                .with_clue_count(5)
+                .unwrap()
+                .clue_count(),
+            5
+        );
+    }
+
+    #[test]
+    fn encoding_info_matches_luts() {
+        let params = OmrParameters::new();
+        let info = params.encoding_info();
+
+        let first = first_level_lut(
+            params.first_level_ring_dimension(),
+            info.clue_plain_modulus as usize,
+            info.intermediate_plain_modulus as usize,
+        );
+        assert!(first.as_slice().contains(&info.first_level_delta));
+
+        let second = second_level_lut(
+            params.second_level_ring_dimension(),
+            params.clue_count(),
+            params.match_sum_value(),
+            params.mismatch_tolerance(),
+            info.intermediate_plain_modulus as usize,
+            info.output_plain_modulus as usize,
+        );
+        assert!(second.as_slice().contains(&info.second_level_delta));
+    }
+
+    #[test]
+    fn modulus_summary_matches_defaults() {
+        assert_eq!(
+            OmrParameters::new().modulus_summary(),
+            ModulusSummary {
+                clue_cipher_modulus: 2048,
+                first_level_modulus: 134215681,
+                intermediate_cipher_modulus: 4096,
+                second_level_modulus: 1125899906826241,
+                output_plain_modulus: 257,
+            }
+        );
+    }
+
+    #[test]
+    fn diff_reports_changed_fields() {
+        let params = OmrParameters::new();
+        assert!(params.diff(&params.clone()).is_empty());
+
+        let mut other = params.clone();
+        other.clue_count = 5;
+        other
+            .second_level_blind_rotation_params
+            .noise_standard_deviation = 3.2;
+        other.output_plain_modulus_value = 1 << 8;
+
+        let diffs = params.diff(&other);
+        assert_eq!(diffs.len(), 3);
+        assert!(diffs[0].starts_with("clue_count"));
+        assert!(diffs[1].starts_with("second_level_blind_rotation_params.noise_standard_deviation"));
+        assert!(diffs[2].starts_with("output_plain_modulus_value"));
+    }
+
+    #[test]
+    fn clue_dimension_is_configurable() {
+        let params = OmrParameters::new();
+        assert_eq!(params.clue_dimension(), 512);
+
+        let lighter = params.clone().with_clue_dimension(600).unwrap();
+        assert_eq!(lighter.clue_dimension(), 600);
+        assert_eq!(
+            lighter.clue_plain_modulus_value(),
+            params.clue_plain_modulus_value()
+        );
+        assert_eq!(params.diff(&lighter), ["clue_params.dimension: 512 != 600"]);
+        assert!(matches!(
+            params.with_clue_dimension(0),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn default_combination_cipher_holds_three_payloads() {
+        let params = OmrParameters::new();
+        let n = params.second_level_ring_dimension();
+        let capacity = params.payloads_per_combination_cipher();
+        assert_eq!(capacity, 3);
+        let retrieval_params =
+            RetrievalParams::<crate::SecondLevelField>::new(257, n, 1000, 50, 130, 25, 2);
+        assert_eq!(retrieval_params.payload_slots_per_cipher(), capacity);
+        assert_eq!(n % PAYLOAD_LENGTH, 212);
+        assert!(params.validate().is_ok());
+
+        // Pack like the detector and decode like the retriever.
+        let payloads = (0..capacity)
+            .map(|i| {
+                (0..PAYLOAD_LENGTH)
+                    .map(|j| ((i + j) % 256) as u8)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut slots = vec![0u8; n];
+        slots
+            .chunks_exact_mut(PAYLOAD_LENGTH)
+            .zip(&payloads)
+            .for_each(|(slot, payload)| slot.copy_from_slice(payload));
+
+        let decoded = slots.chunks_exact(PAYLOAD_LENGTH).collect::<Vec<_>>();
+        assert_eq!(decoded, payloads);
+        assert!(slots
+            .chunks_exact(PAYLOAD_LENGTH)
+            .remainder()
+            .iter()
+            .all(|&b| b == 0));
+    }
 }
@@ -0,0 +1,216 @@
+//! Analytic noise estimates for the detection pipeline.
+
+use algebra::{
+    decompose::NonPowOf2ApproxSignedBasis,
+    integer::{AsInto, UnsignedInteger},
+    NttField,
+};
+use fhe_core::{GadgetRlweParameters, LweSecretKeyType, RingSecretKeyType};
+
+use super::{modulus_value_to_u64, OmrParameters};
+
+/// Per-stage noise variances of the detection pipeline, estimated from the
+/// parameters alone, see [`OmrParameters::noise_budget`].
+///
+/// The first level terms are in units of the first level field, the intermediate
+/// and output terms in units of their own cipher moduli. They treat every noise term
+/// as independent, so they are good for an order of magnitude, not as a bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseBudget {
+    /// One first level blind rotation.
+    pub first_level_blind_rotation: f64,
+    /// The first level key switching.
+    pub key_switching: f64,
+    /// The intermediate LWE ciphertext after the modulus switch, which includes the
+    /// blind rotation of every clue and the key switching.
+    pub intermediate: f64,
+    /// The second level blind rotation.
+    pub second_level_blind_rotation: f64,
+    /// The automorphism key switchings of the homomorphic trace, summed.
+    pub trace: f64,
+    /// The detection output, the second level blind rotation plus the trace.
+    pub output: f64,
+
+    intermediate_half_step: f64,
+    output_half_step: f64,
+}
+
+impl NoiseBudget {
+    /// Returns how many standard deviations of intermediate noise fit in half a
+    /// plaintext step, the window of the second level LUT.
+    #[inline]
+    pub fn intermediate_margin(&self) -> f64 {
+        self.intermediate_half_step / self.intermediate.sqrt()
+    }
+
+    /// Returns how many standard deviations of output noise fit in `q / (2p)`.
+    ///
+    /// The indicator decodes wrongly with a probability around `erfc(margin / sqrt(2))`.
+    #[inline]
+    pub fn decode_margin(&self) -> f64 {
+        self.output_half_step / self.output.sqrt()
+    }
+}
+
+impl OmrParameters {
+    /// Returns the analytic [`NoiseBudget`] of this [`OmrParameters`].
+    pub fn noise_budget(&self) -> NoiseBudget {
+        let first_level_blind_rotation = blind_rotation_variance(
+            self.clue_params.dimension,
+            &self.first_level_blind_rotation_params,
+        );
+
+        let ks = &self.first_level_key_switching_params;
+        let key_switching = ks.input_cipher_dimension as f64
+            * ks.log_modulus.div_ceil(ks.log_basis) as f64
+            * digit_variance(2f64.powi(ks.log_basis as i32))
+            * ks.noise_standard_deviation.powi(2);
+
+        let intermediate_params = &self.intermediate_lwe_params;
+        let first_level_modulus: u64 = self.first_level_blind_rotation_params.modulus.as_into();
+        let intermediate_modulus =
+            modulus_value_to_u64(intermediate_params.cipher_modulus_value) as f64;
+        let scale = intermediate_modulus / first_level_modulus as f64;
+        let rounding = (1.0
+            + intermediate_params.dimension as f64
+                * lwe_key_variance(intermediate_params.secret_key_type))
+            / 12.0;
+        let intermediate =
+            (self.clue_count as f64 * first_level_blind_rotation + key_switching) * scale * scale
+                + rounding;
+
+        let second_level_blind_rotation = blind_rotation_variance(
+            intermediate_params.dimension,
+            &self.second_level_blind_rotation_params,
+        );
+
+        // Each of the `log N` trace steps doubles what came before and adds one
+        // automorphism key switching, which sums to `N - 1` key switchings.
+        let trace =
+            (self.hom_trace_params.dimension - 1) as f64 * gadget_variance(&self.hom_trace_params);
+
+        let second_level_modulus = self.second_level_blind_rotation_params.modulus as f64;
+
+        NoiseBudget {
+            first_level_blind_rotation,
+            key_switching,
+            intermediate,
+            second_level_blind_rotation,
+            trace,
+            output: second_level_blind_rotation + trace,
+            intermediate_half_step: intermediate_modulus
+                / (2.0 * intermediate_params.plain_modulus_value as f64),
+            output_half_step: second_level_modulus / (2.0 * self.output_plain_modulus_value as f64),
+        }
+    }
+}
+
+/// Variance of a blind rotation over an LWE key of dimension `lwe_dimension`,
+/// with one CMux of two gadget products per key coefficient.
+fn blind_rotation_variance<F: NttField>(
+    lwe_dimension: usize,
+    params: &GadgetRlweParameters<F>,
+) -> f64 {
+    lwe_dimension as f64 * 2.0 * gadget_variance(params)
+}
+
+/// Variance added by one gadget product with a key of `params`: the decomposed
+/// digits times the key noise, plus the precision the approximate basis drops
+/// times the ring key.
+fn gadget_variance<F: NttField>(params: &GadgetRlweParameters<F>) -> f64 {
+    let ring_dimension = params.dimension as f64;
+    let modulus: u64 = params.modulus.as_into();
+    let (basis, length) = basis_shape(&params.basis);
+
+    let precision = modulus as f64 / basis.powi(length as i32);
+
+    length as f64 * ring_dimension * digit_variance(basis) * params.noise_standard_deviation.powi(2)
+        + (1.0 + ring_dimension * ring_key_variance(params.secret_key_type)) * precision * precision
+            / 12.0
+}
+
+/// Returns the basis value and decompose length of `basis`.
+fn basis_shape<T: UnsignedInteger>(basis: &NonPowOf2ApproxSignedBasis<T>) -> (f64, usize) {
+    (
+        2f64.powi(basis.log_basis() as i32),
+        basis.decompose_length(),
+    )
+}
+
+/// Variance of a signed digit in base `basis`.
+#[inline]
+fn digit_variance(basis: f64) -> f64 {
+    basis * basis / 12.0
+}
+
+/// `E[s^2]` of an LWE secret key coefficient.
+fn lwe_key_variance(key_type: LweSecretKeyType) -> f64 {
+    if matches!(key_type, LweSecretKeyType::Binary) {
+        0.5
+    } else {
+        2.0 / 3.0
+    }
+}
+
+/// `E[s^2]` of a ring secret key coefficient.
+fn ring_key_variance(key_type: RingSecretKeyType) -> f64 {
+    if matches!(key_type, RingSecretKeyType::Binary) {
+        0.5
+    } else {
+        2.0 / 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use algebra::{ntt::NumberTheoryTransform, Field};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{test_utils::TestSetup, SecondLevelField};
+
+    #[test]
+    fn default_parameters_decode_with_margin() {
+        let budget = OmrParameters::new().noise_budget();
+        assert!(budget.intermediate_margin() > 4.0);
+        assert!(budget.decode_margin() > 4.0);
+    }
+
+    #[test]
+    fn trace_noise_matches_measurement() {
+        let mut rng = StdRng::seed_from_u64(31);
+        let budget = OmrParameters::new().noise_budget();
+
+        let TestSetup {
+            secret_key,
+            sender,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let clues = sender.gen_clues(&mut rng);
+        let result = detector.detect(&clues);
+        let ct = result.as_ntt();
+
+        let key = secret_key.second_level_ntt_rlwe_secret_key();
+        let poly = secret_key
+            .second_level_ntt_table()
+            .inverse_transform_inplace(ct.b() - ct.a().clone() * &**key);
+
+        // Every coefficient but the indicator holds trace noise only.
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let noise = poly.as_slice()[1..]
+            .iter()
+            .map(|&c| {
+                if c > q / 2 {
+                    c as f64 - q as f64
+                } else {
+                    c as f64
+                }
+            })
+            .collect::<Vec<_>>();
+        let measured = noise.iter().map(|e| e * e).sum::<f64>() / noise.len() as f64;
+
+        let ratio = measured.sqrt() / budget.trace.sqrt();
+        assert!((0.1..10.0).contains(&ratio), "ratio: {ratio}");
+    }
+}
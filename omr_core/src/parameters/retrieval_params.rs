@@ -4,13 +4,24 @@ use algebra::{
     integer::{AsInto, Bits, UnsignedInteger},
     Field, NttField,
 };
-use rand_distr::Uniform;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, Uniform};
+
+use crate::{detector::round_delta, payload::PayloadByteType, OmrError, PAYLOAD_LENGTH};
+
+use super::fnv1a;
+
+/// The number of bytes of a serialized [`RetrievalParamsFingerprint`].
+pub const RETRIEVAL_FINGERPRINT_BYTES: usize = 72;
 
 /// Parameters for encoding indices and payloads in RLWE ciphertexts.
 #[derive(Clone, Copy)]
 pub struct RetrievalParams<F: NttField> {
     /// Output message modulus and the index modulus.
     index_modulus: F::ValueT,
+    /// The base of the index digits, `index_modulus` unless set by
+    /// [`RetrievalParams::with_index_base`].
+    index_base: F::ValueT,
 
     /// The number of slots in Rlwe Ciphertext.
     polynomial_size: usize,
@@ -47,6 +58,11 @@ pub struct RetrievalParams<F: NttField> {
 
 impl<F: NttField> RetrievalParams<F> {
     /// Creates a new [`RetrievalParams<F>`].
+    ///
+    /// # Panics
+    ///
+    /// Panics with the error of [`RetrievalParams::try_new`], e.g.
+    /// [`OmrError::InsufficientPolynomialSize`] when not even one segment fits.
     pub fn new(
         index_modulus: F::ValueT,
         polynomial_size: usize,
@@ -56,40 +72,49 @@ impl<F: NttField> RetrievalParams<F> {
         segment_count: usize,
         cmb_count_per_cipher: usize,
     ) -> Self {
-        let index_slots_per_bucket = if index_modulus.is_power_of_two() {
-            all_payloads_count
-                .next_power_of_two()
-                .trailing_zeros()
-                .div_ceil(index_modulus.trailing_zeros()) as usize
-        } else {
-            let index_modulus: usize = index_modulus.as_into();
-            let mut pow = all_payloads_count.ilog(index_modulus);
-            if index_modulus.pow(pow) < all_payloads_count {
-                pow += 1;
-            }
-            if pow == 0 {
-                pow = 1;
-            }
-            assert!(index_modulus.pow(pow) >= all_payloads_count);
-            pow as usize
-        };
+        Self::try_new(
+            index_modulus,
+            polynomial_size,
+            all_payloads_count,
+            pertinent_count,
+            bucket_count_per_segment,
+            segment_count,
+            cmb_count_per_cipher,
+        )
+        .unwrap_or_else(|err| panic!("Invalid retrieval params: {err}."))
+    }
+
+    /// Computes the layout of [`RetrievalParams<F>`] without checking it.
+    fn layout(
+        index_modulus: F::ValueT,
+        polynomial_size: usize,
+        all_payloads_count: usize,
+        pertinent_count: usize,
+        bucket_count_per_segment: usize,
+        segment_count: usize,
+        cmb_count_per_cipher: usize,
+    ) -> Self {
+        let index_slots_per_bucket = index_slots_per_bucket(index_modulus, all_payloads_count);
 
         let slots_per_bucket = index_slots_per_bucket + 1;
         let slots_per_segment = slots_per_bucket * bucket_count_per_segment;
 
         let segment_per_cipher = polynomial_size / slots_per_segment;
-        let max_encode_indices_cipher_count = segment_count / segment_per_cipher;
+        // Zero when not even one segment fits, which `validate` reports.
+        let max_encode_indices_cipher_count =
+            segment_count.checked_div(segment_per_cipher).unwrap_or(0);
 
         let bucket_distr = Uniform::new(0, bucket_count_per_segment);
 
         let combination_count = if index_modulus.is_power_of_two() {
-            pertinent_count + 10
+            pertinent_count.saturating_add(10)
         } else {
-            pertinent_count + 5
+            pertinent_count.saturating_add(5)
         };
 
         Self {
             index_modulus,
+            index_base: index_modulus,
             polynomial_size,
             bucket_count_per_segment,
             slots_per_bucket,
@@ -105,10 +130,148 @@ impl<F: NttField> RetrievalParams<F> {
         }
     }
 
+    /// Creates a new [`RetrievalParams<F>`] like [`RetrievalParams::new`],
+    /// checking the inputs and then the layout with [`RetrievalParams::validate`].
+    pub fn try_new(
+        index_modulus: F::ValueT,
+        polynomial_size: usize,
+        all_payloads_count: usize,
+        pertinent_count: usize,
+        bucket_count_per_segment: usize,
+        segment_count: usize,
+        cmb_count_per_cipher: usize,
+    ) -> Result<Self, OmrError> {
+        let modulus: u64 = index_modulus.as_into();
+        if modulus < 2 || modulus >= <F as Field>::MODULUS_VALUE.as_into() {
+            return Err(OmrError::InvalidParameters {
+                reason: "index modulus must be in 2..field modulus",
+            });
+        }
+        if all_payloads_count == 0 {
+            return Err(OmrError::InvalidParameters {
+                reason: "the board must hold at least one payload",
+            });
+        }
+        if pertinent_count > all_payloads_count {
+            return Err(OmrError::InvalidParameters {
+                reason: "pertinent count must not exceed the board size",
+            });
+        }
+        if bucket_count_per_segment == 0 || segment_count == 0 {
+            return Err(OmrError::InvalidParameters {
+                reason: "the layout needs at least one bucket and one segment",
+            });
+        }
+
+        let params = Self::layout(
+            index_modulus,
+            polynomial_size,
+            all_payloads_count,
+            pertinent_count,
+            bucket_count_per_segment,
+            segment_count,
+            cmb_count_per_cipher,
+        );
+        params.validate()?;
+        // One weight per payload and combination, see `combination_weights`.
+        if params
+            .combination_count
+            .checked_mul(all_payloads_count)
+            .is_none()
+        {
+            return Err(OmrError::InvalidParameters {
+                reason: "the board is too large for a combination matrix",
+            });
+        }
+        Ok(params)
+    }
+
+    /// Creates a new [`RetrievalParams<F>`] packing exactly `segment_per_cipher` segments
+    /// into each index ciphertext, with as many buckets per segment as then fit.
+    ///
+    /// For small boards [`RetrievalParams::new`] with the recommended bucket count
+    /// leaves most of the ciphertext unused; fewer buckets per segment pack more
+    /// segments, at the price of more collisions within each segment.
+    pub fn try_with_segment_per_cipher(
+        index_modulus: F::ValueT,
+        polynomial_size: usize,
+        all_payloads_count: usize,
+        pertinent_count: usize,
+        segment_per_cipher: usize,
+        segment_count: usize,
+        cmb_count_per_cipher: usize,
+    ) -> Result<Self, OmrError> {
+        let slots_per_bucket = index_slots_per_bucket(index_modulus, all_payloads_count) + 1;
+        let bucket_count_per_segment = polynomial_size
+            .checked_div(segment_per_cipher * slots_per_bucket)
+            .unwrap_or(0);
+        if bucket_count_per_segment == 0 {
+            return Err(OmrError::InvalidParameters {
+                reason: "segment_per_cipher leaves no room for a bucket per segment",
+            });
+        }
+
+        let params = Self::try_new(
+            index_modulus,
+            polynomial_size,
+            all_payloads_count,
+            pertinent_count,
+            bucket_count_per_segment,
+            segment_count,
+            cmb_count_per_cipher,
+        )?;
+        if params.segment_per_cipher != segment_per_cipher {
+            return Err(OmrError::InvalidParameters {
+                reason: "segment_per_cipher is not reachable with whole buckets",
+            });
+        }
+        Ok(params)
+    }
+
+    /// Checks that one index segment and `cmb_count_per_cipher` payloads fit in one
+    /// ciphertext.
+    ///
+    /// Without a whole segment no index ciphertext can be produced, and retrieval
+    /// silently finds nothing. Overflowing payloads would misalign decoding with
+    /// `chunks_exact(PAYLOAD_LENGTH)`.
+    pub fn validate(&self) -> Result<(), OmrError> {
+        if self.segment_per_cipher == 0 {
+            return Err(OmrError::InsufficientPolynomialSize {
+                polynomial_size: self.polynomial_size,
+                slots_per_segment: self.slots_per_segment,
+            });
+        }
+        // `cmb_count_per_cipher <= polynomial_size / PAYLOAD_LENGTH` is exactly
+        // `cmb_count_per_cipher * PAYLOAD_LENGTH <= polynomial_size`, without overflow.
+        if self.cmb_count_per_cipher == 0
+            || self.cmb_count_per_cipher > self.payload_slots_per_cipher()
+        {
+            return Err(OmrError::InvalidParameters {
+                reason: "cmb_count_per_cipher payloads must fit in one ciphertext",
+            });
+        }
+        Ok(())
+    }
+
     pub fn index_modulus(&self) -> <F as Field>::ValueT {
         self.index_modulus
     }
 
+    pub fn index_base(&self) -> <F as Field>::ValueT {
+        self.index_base
+    }
+
+    /// Returns the plaintext scale `round(q / index_modulus)` of a combined payload byte.
+    ///
+    /// For the second level field this is the scale the second level LUT gives a
+    /// pertinent message, which the combine step carries over to every weighted
+    /// payload byte.
+    pub fn payload_delta(&self) -> <F as Field>::ValueT {
+        let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
+        let p: u64 = self.index_modulus.as_into();
+        round_delta(q, p).as_into()
+    }
+
     pub fn polynomial_size(&self) -> usize {
         self.polynomial_size
     }
@@ -156,4 +319,817 @@ impl<F: NttField> RetrievalParams<F> {
     pub fn cmb_count_per_cipher(&self) -> usize {
         self.cmb_count_per_cipher
     }
+
+    /// Returns these params with the indices written in base `index_base` digits.
+    ///
+    /// A small base such as 3 packs indices into more but smaller digits. Each digit
+    /// is still encoded modulo `index_modulus`, so the base must not exceed it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [`RetrievalParams::try_with_index_base`] fails.
+    pub fn with_index_base(self, index_base: F::ValueT) -> Self {
+        self.try_with_index_base(index_base)
+            .unwrap_or_else(|err| panic!("Invalid index base: {err}."))
+    }
+
+    /// Returns these params with the indices written in base `index_base` digits, like
+    /// [`RetrievalParams::with_index_base`], checking the base and the new layout.
+    pub fn try_with_index_base(mut self, index_base: F::ValueT) -> Result<Self, OmrError> {
+        let base: u64 = index_base.as_into();
+        let modulus: u64 = self.index_modulus.as_into();
+        if !(2..=modulus).contains(&base) {
+            return Err(OmrError::InvalidParameters {
+                reason: "index base must be in 2..=index_modulus",
+            });
+        }
+
+        self.index_base = index_base;
+        self.slots_per_bucket = index_slots_per_bucket(index_base, self.all_payloads_count) + 1;
+        self.slots_per_segment = self.slots_per_bucket * self.bucket_count_per_segment;
+        self.segment_per_cipher = self.polynomial_size / self.slots_per_segment;
+        self.max_encode_indices_cipher_count = self
+            .segment_count
+            .checked_div(self.segment_per_cipher)
+            .unwrap_or(0);
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Returns how many whole payloads fit in one combination ciphertext, the upper
+    /// bound on `cmb_count_per_cipher` checked by [`RetrievalParams::validate`].
+    ///
+    /// Payloads are packed at multiples of [`PAYLOAD_LENGTH`] and decoded with
+    /// `chunks_exact(PAYLOAD_LENGTH)`, so the `N % PAYLOAD_LENGTH` slots left over at
+    /// the end of the ring are never written and dropping them loses no payload data.
+    #[inline]
+    pub fn payload_slots_per_cipher(&self) -> usize {
+        payload_slots_per_cipher(self.polynomial_size)
+    }
+
+    /// Returns the number of combination ciphertexts holding `combination_count` combinations.
+    pub fn combination_cipher_count(&self) -> usize {
+        self.combination_count.div_ceil(self.cmb_count_per_cipher)
+    }
+
+    /// Returns the number of ciphertexts of each kind in a digest built with these params.
+    pub fn digest_shape(&self) -> DigestShape {
+        DigestShape {
+            index_ciphertexts: self.max_encode_indices_cipher_count,
+            combination_ciphertexts: self.combination_cipher_count(),
+        }
+    }
+
+    /// Returns the number of slots per index ciphertext left unused because
+    /// `slots_per_segment` does not divide `polynomial_size`.
+    pub fn wasted_slots_per_cipher(&self) -> usize {
+        self.polynomial_size - self.segment_per_cipher * self.slots_per_segment
+    }
+
+    /// Returns whether the segments exactly fill an index ciphertext.
+    pub fn is_exact_fit(&self) -> bool {
+        self.wasted_slots_per_cipher() == 0
+    }
+
+    /// Estimates the probability that the retriever recovers all pertinent indices
+    /// from the index ciphertexts.
+    ///
+    /// Each pertinent index is written to a uniformly random bucket of every segment,
+    /// and is recovered once it sits alone in its bucket in some segment. Treating the
+    /// indices as independent gives `(1 - (1 - (1 - 1/B)^(k - 1))^S)^k` for `B` buckets
+    /// per segment, `k` pertinent indices and `S` segments over all index ciphertexts.
+    pub fn index_recovery_probability(&self) -> f64 {
+        let segments = self.max_encode_indices_cipher_count * self.segment_per_cipher;
+        index_recovery_probability(
+            self.bucket_count_per_segment,
+            self.pertinent_count,
+            segments,
+        )
+    }
+
+    /// Recommends `(bucket_count_per_segment, segment_count)` for a board, such that
+    /// [`RetrievalParams::index_recovery_probability`] is at least
+    /// `1 - max_failure_probability`.
+    ///
+    /// About `2.5 * pertinent_count` buckets per segment, as many as a ciphertext can
+    /// hold, and then the fewest whole ciphertexts of segments reaching the target.
+    pub fn recommended_layout(
+        index_modulus: F::ValueT,
+        polynomial_size: usize,
+        all_payloads_count: usize,
+        pertinent_count: usize,
+        max_failure_probability: f64,
+    ) -> (usize, usize) {
+        assert!(
+            max_failure_probability > 0.0 && max_failure_probability < 1.0,
+            "Failure probability must be in (0, 1)."
+        );
+
+        let slots_per_bucket = index_slots_per_bucket(index_modulus, all_payloads_count) + 1;
+        let max_buckets = (polynomial_size / slots_per_bucket).max(1);
+        let bucket_count_per_segment = (pertinent_count * 5 / 2).clamp(1, max_buckets);
+        let segment_per_cipher =
+            (polynomial_size / (slots_per_bucket * bucket_count_per_segment)).max(1);
+
+        // `(1 - (1 - m^S)^k) <= f` holds once `m^S <= f / k`, for the probability `m`
+        // that an index shares its bucket with another one.
+        let k = pertinent_count.max(1);
+        let alone = (1.0 - 1.0 / bucket_count_per_segment as f64).powi(k as i32 - 1);
+        assert!(
+            alone > 0.0,
+            "Too few buckets to separate the pertinent indices."
+        );
+        let segments = if alone >= 1.0 {
+            1
+        } else {
+            ((max_failure_probability / k as f64).ln() / (1.0 - alone).ln()).ceil() as usize
+        };
+
+        let segment_count = segments.max(1).div_ceil(segment_per_cipher) * segment_per_cipher;
+        (bucket_count_per_segment, segment_count)
+    }
+
+    /// Returns how many dummy clues to add to a board of `actual_messages` so that it
+    /// holds at least `target_anonymity` messages.
+    ///
+    /// The detector can't tell a dummy from a real non-pertinent message, so every
+    /// message on the board is in the anonymity set of each pertinent one. Add the
+    /// dummies with a sender no retriever holds a key for, like [`pad_board_to_pow2`].
+    ///
+    /// [`pad_board_to_pow2`]: crate::pad_board_to_pow2
+    #[inline]
+    pub fn recommend_board_padding(actual_messages: usize, target_anonymity: usize) -> usize {
+        target_anonymity.saturating_sub(actual_messages)
+    }
+
+    /// Returns the weights the combinations derived from `seed` give every payload,
+    /// row-major with one row of `all_payloads_count` weights per combination.
+    pub(crate) fn combination_weights(&self, seed: [u8; 32]) -> Vec<PayloadByteType> {
+        let p: PayloadByteType = self.index_modulus.as_into();
+        let distr: Uniform<PayloadByteType> = Uniform::new(0, p);
+        distr
+            .sample_iter(StdRng::from_seed(seed))
+            .take(self.combination_count * self.all_payloads_count)
+            .collect()
+    }
+
+    /// Returns the rank of the combination matrix derived from `seed`, restricted to
+    /// the first `retrieval_count` payloads, so solvability can be checked before
+    /// decoding.
+    ///
+    /// For a power-of-two index modulus the rank is taken over GF(2): a matrix mod
+    /// `2^k` is solvable exactly when it has full column rank mod 2, which is what the
+    /// odd pivots of `solve_matrix_mod_256` need. Otherwise the index modulus is a prime
+    /// and the rank is taken modulo it.
+    pub fn expected_matrix_rank(&self, retrieval_count: usize, seed: [u8; 32]) -> usize {
+        assert!(
+            retrieval_count <= self.all_payloads_count,
+            "Retrieval count must not exceed the board size."
+        );
+        let p: u64 = self.index_modulus.as_into();
+        let p = if p.is_power_of_two() { 2 } else { p };
+
+        let mut matrix = self
+            .combination_weights(seed)
+            .chunks_exact(self.all_payloads_count)
+            .map(|row| {
+                row[..retrieval_count]
+                    .iter()
+                    .map(|&w| w as u64 % p)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        rank_mod_prime(&mut matrix, p)
+    }
+
+    /// Returns the smallest power-of-two polynomial size that fits at least one segment,
+    /// i.e. that makes `segment_per_cipher >= 1` in [`RetrievalParams::new`].
+    ///
+    /// The pertinent count does not enter here: it only decides how many segments,
+    /// and thus how many ciphertexts, the indices need.
+    pub fn min_polynomial_size(
+        index_modulus: F::ValueT,
+        all_payloads_count: usize,
+        bucket_count_per_segment: usize,
+    ) -> usize {
+        let slots_per_bucket = index_slots_per_bucket(index_modulus, all_payloads_count) + 1;
+        (slots_per_bucket * bucket_count_per_segment).next_power_of_two()
+    }
+
+    /// Returns the fingerprint of this [`RetrievalParams<F>`], which can be embedded
+    /// in a digest so that the retriever rebuilds exactly the same layout.
+    pub fn fingerprint(&self) -> RetrievalParamsFingerprint {
+        RetrievalParamsFingerprint {
+            index_modulus: self.index_modulus.as_into(),
+            index_base: self.index_base.as_into(),
+            polynomial_size: self.polynomial_size,
+            all_payloads_count: self.all_payloads_count,
+            pertinent_count: self.pertinent_count,
+            bucket_count_per_segment: self.bucket_count_per_segment,
+            segment_count: self.segment_count,
+            cmb_count_per_cipher: self.cmb_count_per_cipher,
+        }
+    }
+
+    /// Rebuilds [`RetrievalParams<F>`] from a fingerprint.
+    ///
+    /// A fingerprint read from a digest is untrusted, so every field goes through the
+    /// checks of [`RetrievalParams::try_new`] and [`RetrievalParams::try_with_index_base`].
+    pub fn from_fingerprint(fingerprint: &RetrievalParamsFingerprint) -> Result<Self, OmrError> {
+        // Both values must survive the conversion to `F::ValueT`.
+        let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
+        if fingerprint.index_modulus >= q || fingerprint.index_base >= q {
+            return Err(OmrError::InvalidParameters {
+                reason: "index modulus must be in 2..field modulus",
+            });
+        }
+
+        Self::try_new(
+            fingerprint.index_modulus.as_into(),
+            fingerprint.polynomial_size,
+            fingerprint.all_payloads_count,
+            fingerprint.pertinent_count,
+            fingerprint.bucket_count_per_segment,
+            fingerprint.segment_count,
+            fingerprint.cmb_count_per_cipher,
+        )?
+        .try_with_index_base(fingerprint.index_base.as_into())
+    }
+}
+
+/// `(1 - (1 - (1 - 1/B)^(k - 1))^S)^k` for `B` buckets per segment, `k` pertinent
+/// indices and `S` segments, see [`RetrievalParams::index_recovery_probability`].
+fn index_recovery_probability(buckets: usize, k: usize, segments: usize) -> f64 {
+    if k == 0 {
+        return 1.0;
+    }
+
+    let alone = (1.0 - 1.0 / buckets as f64).powi(k as i32 - 1);
+    let missed = (1.0 - alone).powi(segments as i32);
+    (1.0 - missed).powi(k as i32)
+}
+
+/// Returns the rank of `matrix` modulo the prime `p`, eliminating it in place.
+fn rank_mod_prime(matrix: &mut [Vec<u64>], p: u64) -> usize {
+    let cols = matrix.first().map_or(0, Vec::len);
+    let mut rank = 0;
+    for col in 0..cols {
+        let Some(pivot) = (rank..matrix.len()).find(|&r| matrix[r][col] != 0) else {
+            continue;
+        };
+        matrix.swap(rank, pivot);
+
+        let inv = pow_mod(matrix[rank][col], p - 2, p);
+        let pivot_row = matrix[rank]
+            .iter()
+            .map(|&v| v * inv % p)
+            .collect::<Vec<_>>();
+        for (r, row) in matrix.iter_mut().enumerate() {
+            if r == rank || row[col] == 0 {
+                continue;
+            }
+            let factor = row[col];
+            row.iter_mut()
+                .zip(&pivot_row)
+                .for_each(|(v, &w)| *v = (*v + p * p - factor * w) % p);
+        }
+        matrix[rank] = pivot_row;
+        rank += 1;
+    }
+    rank
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, p: u64) -> u64 {
+    let mut acc = 1 % p;
+    base %= p;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            acc = acc * base % p;
+        }
+        base = base * base % p;
+        exp >>= 1;
+    }
+    acc
+}
+
+/// The number of whole payloads in a combination ciphertext of `polynomial_size` slots,
+/// see [`RetrievalParams::payload_slots_per_cipher`].
+#[inline]
+pub(super) fn payload_slots_per_cipher(polynomial_size: usize) -> usize {
+    polynomial_size / PAYLOAD_LENGTH
+}
+
+/// The number of slots needed to store one index in base `index_modulus`.
+fn index_slots_per_bucket<T: UnsignedInteger + AsInto<usize>>(
+    index_modulus: T,
+    all_payloads_count: usize,
+) -> usize {
+    let modulus: usize = index_modulus.as_into();
+    assert!(modulus >= 2, "Index modulus must be at least 2.");
+
+    if index_modulus.is_power_of_two() {
+        // `ceil(log2(all_payloads_count))` bits, without overflowing `next_power_of_two`.
+        let bits = usize::BITS - all_payloads_count.saturating_sub(1).leading_zeros();
+        bits.div_ceil(index_modulus.trailing_zeros()) as usize
+    } else {
+        let mut pow = 1;
+        let mut capacity = modulus;
+        while capacity < all_payloads_count {
+            capacity = capacity.saturating_mul(modulus);
+            pow += 1;
+        }
+        pow
+    }
+}
+
+/// The number of ciphertexts in a digest, known before any of it is received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigestShape {
+    /// The number of index ciphertexts.
+    pub index_ciphertexts: usize,
+    /// The number of combination ciphertexts.
+    pub combination_ciphertexts: usize,
+}
+
+/// The constructor inputs of [`RetrievalParams`], enough to rebuild them exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetrievalParamsFingerprint {
+    index_modulus: u64,
+    index_base: u64,
+    polynomial_size: usize,
+    all_payloads_count: usize,
+    pertinent_count: usize,
+    bucket_count_per_segment: usize,
+    segment_count: usize,
+    cmb_count_per_cipher: usize,
+}
+
+impl RetrievalParamsFingerprint {
+    fn fields(&self) -> [u64; 8] {
+        [
+            self.index_modulus,
+            self.index_base,
+            self.polynomial_size as u64,
+            self.all_payloads_count as u64,
+            self.pertinent_count as u64,
+            self.bucket_count_per_segment as u64,
+            self.segment_count as u64,
+            self.cmb_count_per_cipher as u64,
+        ]
+    }
+
+    /// Returns the checksum of this [`RetrievalParamsFingerprint`].
+    pub fn checksum(&self) -> u64 {
+        fnv1a(self.fields().iter().flat_map(|v| v.to_le_bytes()))
+    }
+
+    /// Returns the polynomial size of this [`RetrievalParamsFingerprint`].
+    pub fn polynomial_size(&self) -> usize {
+        self.polynomial_size
+    }
+
+    /// Serializes the fingerprint followed by its checksum, as little-endian `u64`s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RETRIEVAL_FINGERPRINT_BYTES);
+        for v in self.fields() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.checksum().to_le_bytes());
+        bytes
+    }
+
+    /// Deserializes a fingerprint and verifies its checksum.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, OmrError> {
+        if bytes.len() != RETRIEVAL_FINGERPRINT_BYTES {
+            return Err(OmrError::InvalidLength {
+                expected: RETRIEVAL_FINGERPRINT_BYTES,
+                found: bytes.len(),
+            });
+        }
+
+        let mut values = bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || values.next().unwrap();
+
+        let fingerprint = Self {
+            index_modulus: next(),
+            index_base: next(),
+            polynomial_size: next() as usize,
+            all_payloads_count: next() as usize,
+            pertinent_count: next() as usize,
+            bucket_count_per_segment: next() as usize,
+            segment_count: next() as usize,
+            cmb_count_per_cipher: next() as usize,
+        };
+
+        let checksum = next();
+        if checksum != fingerprint.checksum() {
+            return Err(OmrError::ParameterMismatch {
+                expected: fingerprint.checksum(),
+                found: checksum,
+            });
+        }
+
+        Ok(fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SecondLevelField;
+
+    use super::*;
+
+    #[test]
+    fn default_margin_gives_full_rank() {
+        for index_modulus in [256, 257] {
+            let params =
+                <RetrievalParams<SecondLevelField>>::new(index_modulus, 2048, 1000, 50, 130, 25, 2);
+            let full = (0..20u8)
+                .filter(|&s| params.expected_matrix_rank(50, [s; 32]) == 50)
+                .count();
+            assert!(full >= 19, "{index_modulus}: {full} of 20");
+        }
+    }
+
+    #[test]
+    fn rank_counts_dependent_rows_once() {
+        let mut matrix = vec![vec![1, 2, 3], vec![2, 4, 6], vec![0, 1, 1]];
+        assert_eq!(rank_mod_prime(&mut matrix, 257), 2);
+        let mut matrix = vec![vec![1, 1], vec![1, 1], vec![0, 0]];
+        assert_eq!(rank_mod_prime(&mut matrix, 2), 1);
+    }
+
+    #[test]
+    fn oversized_board_is_rejected() {
+        // 2^50 messages need 7 base-257 digits plus the indicator: 8 * 260 > 2048.
+        assert!(matches!(
+            <RetrievalParams<SecondLevelField>>::try_new(257, 2048, 1 << 50, 50, 260, 25, 2),
+            Err(OmrError::InsufficientPolynomialSize {
+                polynomial_size: 2048,
+                slots_per_segment: 2080,
+            })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "smaller than one segment")]
+    fn new_reports_an_oversized_board() {
+        <RetrievalParams<SecondLevelField>>::new(257, 2048, 1 << 50, 50, 260, 25, 2);
+    }
+
+    #[test]
+    fn oversized_cmb_count_per_cipher_is_rejected() {
+        // 4 * 612 > 2048
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 3);
+        assert_eq!(params.payload_slots_per_cipher(), 3);
+        assert!(matches!(
+            <RetrievalParams<SecondLevelField>>::try_new(257, 2048, 1000, 50, 130, 25, 4),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+        assert!(matches!(
+            <RetrievalParams<SecondLevelField>>::try_new(257, 2048, 1000, 50, 130, 25, 0),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+        assert!(
+            <RetrievalParams<SecondLevelField>>::try_new(257, 2048, 1000, 50, 130, 25, 3).is_ok()
+        );
+
+        // 2 * 612 > 1024, even though the index segments fit.
+        assert!(matches!(
+            <RetrievalParams<SecondLevelField>>::try_new(257, 1024, 100, 4, 10, 4, 2),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+        assert!(<RetrievalParams<SecondLevelField>>::try_new(257, 1024, 100, 4, 10, 4, 1).is_ok());
+        assert!(matches!(
+            <RetrievalParams<SecondLevelField>>::try_new(257, 2048, 1000, 50, 130, 25, usize::MAX),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn payload_delta_matches_output_scale() {
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2);
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let delta = params.payload_delta();
+
+        assert!(delta * 257 <= q + 128 && q <= delta * 257 + 128);
+        assert_eq!(delta, crate::detector::second_level_delta(257));
+    }
+
+    #[test]
+    fn every_payload_byte_round_trips_through_delta() {
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        for p in [256u64, 257] {
+            let params = <RetrievalParams<SecondLevelField>>::new(p, 2048, 1000, 50, 130, 25, 2);
+            let delta = params.payload_delta();
+            assert_eq!(delta, crate::detector::second_level_delta(p as usize));
+
+            // The detector lifts bytes to `(-p/2, p/2]` before scaling by the indicator.
+            let half_p = (p + 1) >> 1;
+            for byte in 0..p {
+                let lifted = if byte < half_p { byte } else { q - p + byte };
+                let coeff = (lifted as u128 * delta as u128 % q as u128) as u64;
+                assert_eq!(crate::retriever::scale_round(coeff, p, q), byte);
+            }
+        }
+    }
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2);
+        let mut bytes = params.fingerprint().to_bytes();
+
+        let fingerprint = RetrievalParamsFingerprint::from_bytes(&bytes).unwrap();
+        assert_eq!(fingerprint, params.fingerprint());
+
+        let rebuilt = <RetrievalParams<SecondLevelField>>::from_fingerprint(&fingerprint).unwrap();
+        assert_eq!(rebuilt.slots_per_segment(), params.slots_per_segment());
+        assert_eq!(rebuilt.combination_count(), params.combination_count());
+
+        bytes[16] ^= 1;
+        assert!(matches!(
+            RetrievalParamsFingerprint::from_bytes(&bytes),
+            Err(OmrError::ParameterMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn min_polynomial_size_fits_one_segment() {
+        for all_payloads_count in [64, 1000, 1 << 16, 1 << 20] {
+            for index_modulus in [256, 257] {
+                let size = <RetrievalParams<SecondLevelField>>::min_polynomial_size(
+                    index_modulus,
+                    all_payloads_count,
+                    130,
+                );
+                assert!(size.is_power_of_two());
+
+                let params = <RetrievalParams<SecondLevelField>>::new(
+                    index_modulus,
+                    size,
+                    all_payloads_count,
+                    50,
+                    130,
+                    25,
+                    2,
+                );
+                assert!(params.segment_per_cipher() >= 1);
+                assert!(size / 2 < params.slots_per_segment());
+            }
+        }
+    }
+
+    #[test]
+    fn wasted_slots_are_reported() {
+        // 1000 payloads need 2 base-257 digits plus a flag: 3 slots per bucket,
+        // so a segment of 130 buckets takes 390 slots and 5 segments fill 1950 of 2048.
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2);
+        assert_eq!(params.slots_per_segment(), 390);
+        assert_eq!(params.segment_per_cipher(), 5);
+        assert_eq!(params.wasted_slots_per_cipher(), 98);
+        assert!(!params.is_exact_fit());
+
+        // 256 payloads need 1 base-256 digit plus a flag: 2 slots per bucket.
+        let params = <RetrievalParams<SecondLevelField>>::new(256, 2048, 256, 50, 128, 25, 2);
+        assert_eq!(params.wasted_slots_per_cipher(), 0);
+        assert!(params.is_exact_fit());
+    }
+
+    #[test]
+    fn recommended_layout_reaches_target() {
+        for (all_payloads_count, pertinent_count) in [
+            (100, 1),
+            (1000, 10),
+            (1000, 50),
+            (1 << 16, 100),
+            (1 << 20, 50),
+        ] {
+            let (buckets, segments) = <RetrievalParams<SecondLevelField>>::recommended_layout(
+                257,
+                2048,
+                all_payloads_count,
+                pertinent_count,
+                1e-9,
+            );
+            let params = <RetrievalParams<SecondLevelField>>::try_new(
+                257,
+                2048,
+                all_payloads_count,
+                pertinent_count,
+                buckets,
+                segments,
+                2,
+            )
+            .unwrap();
+
+            assert_eq!(segments % params.segment_per_cipher(), 0);
+            assert!(params.index_recovery_probability() >= 1.0 - 1e-9);
+        }
+    }
+
+    #[test]
+    fn index_recovery_probability_matches_simulation() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        // 5 segments in a single index ciphertext.
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 5, 2);
+        let segments = params.max_encode_indices_cipher_count() * params.segment_per_cipher();
+        assert_eq!(segments, 5);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let trials = 4000;
+        let k = params.pertinent_count();
+        let buckets = params.bucket_count_per_segment();
+
+        let recovered_all = (0..trials)
+            .filter(|_| {
+                let mut recovered = vec![false; k];
+                let mut occupancy = vec![0usize; buckets];
+                let mut chosen = vec![0usize; k];
+                for _ in 0..segments {
+                    occupancy.iter_mut().for_each(|c| *c = 0);
+                    for b in chosen.iter_mut() {
+                        *b = rng.gen_range(0..buckets);
+                        occupancy[*b] += 1;
+                    }
+                    for (r, &b) in recovered.iter_mut().zip(chosen.iter()) {
+                        *r |= occupancy[b] == 1;
+                    }
+                }
+                recovered.iter().all(|&r| r)
+            })
+            .count();
+
+        let simulated = recovered_all as f64 / trials as f64;
+        let estimated = params.index_recovery_probability();
+        assert!(
+            (simulated - estimated).abs() < 0.03,
+            "simulated {simulated}, estimated {estimated}"
+        );
+    }
+
+    #[test]
+    fn board_padding_reaches_the_anonymity_target() {
+        for (actual, target) in [(0, 1000), (300, 1000), (1000, 1000), (1500, 1000)] {
+            let padding =
+                <RetrievalParams<SecondLevelField>>::recommend_board_padding(actual, target);
+            assert!(actual + padding >= target);
+            assert!(padding == 0 || actual + padding == target);
+        }
+    }
+
+    #[test]
+    fn digest_shape_counts_ciphertexts() {
+        use fhe_core::RlweCiphertext;
+
+        use crate::{
+            detector::second_level_delta, test_utils::TestSetup, OutputValue, Payload,
+            PertinencyCiphertext,
+        };
+
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2);
+        let shape = params.digest_shape();
+
+        // 25 segments, 5 per ciphertext.
+        assert_eq!(shape.index_ciphertexts, 5);
+        // 50 + 5 combinations, 2 per ciphertext.
+        assert_eq!(params.combination_count(), 55);
+        assert_eq!(shape.combination_ciphertexts, 28);
+
+        // The detector emits exactly this shape, and a digest of it decodes.
+        let mut rng = StdRng::seed_from_u64(13);
+        let TestSetup {
+            secret_key,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let mut retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        let params = retriever.params();
+        let shape = params.digest_shape();
+        assert_eq!(
+            shape.index_ciphertexts,
+            params.max_encode_indices_cipher_count()
+        );
+
+        // With `a = 0` the ciphertext decrypts to `b` under any key.
+        let ntt_table = secret_key.second_level_ntt_table();
+        let delta = second_level_delta(params.index_modulus() as usize);
+        let pertinent = (0..10).map(|i| i * 97 + 2).collect::<Vec<_>>();
+        let pertinency_vector = (0..1000)
+            .map(|i| {
+                let mut ct = RlweCiphertext::<SecondLevelField>::zero(params.polynomial_size());
+                ct.b_mut().as_mut_slice()[0] = pertinent.contains(&i) as OutputValue * delta;
+                PertinencyCiphertext::from_coeff(&ct, ntt_table)
+            })
+            .collect::<Vec<_>>();
+        let payloads = (0..1000)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        let indices = (0..shape.index_ciphertexts)
+            .map(|_| detector.encode_pertinent_indices(params, &pertinency_vector))
+            .collect::<Vec<_>>();
+        let seed = [4u8; 32];
+        let combinations = detector.encode_pertinent_payloads(
+            &pertinency_vector,
+            &payloads,
+            params.combination_count(),
+            params.cmb_count_per_cipher(),
+            &mut StdRng::from_seed(seed),
+        );
+        assert_eq!(combinations.len(), shape.combination_ciphertexts);
+
+        let (decoded, _) = retriever
+            .decode_digest(&indices, &combinations, seed)
+            .unwrap();
+        assert_eq!(decoded, pertinent);
+    }
+
+    #[test]
+    fn small_index_base_layout() {
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2)
+            .with_index_base(3);
+        // 3^6 = 729 < 1000 <= 3^7, plus the indicator slot.
+        assert_eq!(params.slots_per_bucket(), 8);
+        assert_eq!(params.index_modulus(), 257);
+
+        let rebuilt =
+            <RetrievalParams<SecondLevelField>>::from_fingerprint(&params.fingerprint()).unwrap();
+        assert_eq!(rebuilt.index_base(), 3);
+        assert_eq!(rebuilt.slots_per_segment(), params.slots_per_segment());
+    }
+
+    #[test]
+    fn forged_fingerprints_are_rejected() {
+        let valid =
+            <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2).fingerprint();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+
+        for forged in [
+            RetrievalParamsFingerprint {
+                bucket_count_per_segment: 0,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                segment_count: 0,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                index_modulus: 0,
+                index_base: 0,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                index_modulus: 1,
+                index_base: 1,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                index_modulus: q,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                index_base: 258,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                index_base: 1,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                all_payloads_count: 0,
+                pertinent_count: 0,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                pertinent_count: 1001,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                all_payloads_count: usize::MAX,
+                pertinent_count: usize::MAX,
+                bucket_count_per_segment: 1,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                cmb_count_per_cipher: 0,
+                ..valid
+            },
+            RetrievalParamsFingerprint {
+                polynomial_size: 0,
+                ..valid
+            },
+        ] {
+            assert!(
+                matches!(
+                    <RetrievalParams<SecondLevelField>>::from_fingerprint(&forged),
+                    Err(OmrError::InvalidParameters { .. }
+                        | OmrError::InsufficientPolynomialSize { .. })
+                ),
+                "{forged:?}"
+            );
+        }
+    }
 }
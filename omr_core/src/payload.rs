@@ -1,14 +1,27 @@
 //! Payload representation and arithmetic helpers.
+
+use std::ops::{BitXor, BitXorAssign};
 
 use algebra::reduce::RingReduce;
 use itertools::izip;
 use rand::RngCore;
 
+use crate::{parameters::fnv1a, OmrError};
+
 /// Number of bytes per payload (as used in the paper's experiments).
 pub const PAYLOAD_LENGTH: usize = 612;
 /// Element type used in payload arithmetic.
 pub type PayloadByteType = u16;
 
+/// Number of bytes of the length prefix written by [`Payload::with_content`].
+const LENGTH_PREFIX_BYTES: usize = 2;
+/// Maximum content length of a length-prefixed payload.
+pub const PAYLOAD_CONTENT_CAPACITY: usize = PAYLOAD_LENGTH - LENGTH_PREFIX_BYTES;
+/// Number of bytes of the checksum written by [`Payload::with_checksum`].
+const CHECKSUM_BYTES: usize = 8;
+/// Maximum content length of a checksummed payload.
+pub const PAYLOAD_CHECKSUM_CONTENT_CAPACITY: usize = PAYLOAD_CONTENT_CAPACITY - CHECKSUM_BYTES;
+
 /// Fixed-length payload used by InstantOMR.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Payload(pub [PayloadByteType; PAYLOAD_LENGTH]);
@@ -37,6 +50,88 @@ impl Payload {
         payload
     }
 
+    /// Creates a [`Payload`] holding `data` behind a 2-byte big-endian length prefix,
+    /// zero-padded to [`PAYLOAD_LENGTH`].
+    pub fn with_content(data: &[u8]) -> Result<Self, OmrError> {
+        if data.len() > PAYLOAD_CONTENT_CAPACITY {
+            return Err(OmrError::InvalidLength {
+                expected: PAYLOAD_CONTENT_CAPACITY,
+                found: data.len(),
+            });
+        }
+
+        let mut payload = Self::new();
+        payload.0[0] = (data.len() >> 8) as PayloadByteType;
+        payload.0[1] = (data.len() & 0xff) as PayloadByteType;
+        payload.0[LENGTH_PREFIX_BYTES..]
+            .iter_mut()
+            .zip(data.iter())
+            .for_each(|(p, &b)| *p = PayloadByteType::from(b));
+        Ok(payload)
+    }
+
+    /// Returns the content of a [`Payload`] built by [`Payload::with_content`].
+    pub fn content(&self) -> Result<Vec<u8>, OmrError> {
+        let len = ((self.0[0] as usize) << 8) | self.0[1] as usize;
+        if len > PAYLOAD_CONTENT_CAPACITY {
+            return Err(OmrError::InvalidLength {
+                expected: PAYLOAD_CONTENT_CAPACITY,
+                found: len,
+            });
+        }
+
+        Ok(self.0[LENGTH_PREFIX_BYTES..LENGTH_PREFIX_BYTES + len]
+            .iter()
+            .map(|&b| b as u8)
+            .collect())
+    }
+
+    /// Creates a [`Payload`] like [`Payload::with_content`], with the last 8 bytes
+    /// holding a 64-bit FNV-1a checksum of everything before them.
+    ///
+    /// The content is read back with [`Payload::content`]. A retrieved payload that
+    /// fails [`Payload::verify_checksum`] was corrupted by a decode error.
+    pub fn with_checksum(data: &[u8]) -> Result<Self, OmrError> {
+        if data.len() > PAYLOAD_CHECKSUM_CONTENT_CAPACITY {
+            return Err(OmrError::InvalidLength {
+                expected: PAYLOAD_CHECKSUM_CONTENT_CAPACITY,
+                found: data.len(),
+            });
+        }
+
+        let mut payload = Self::with_content(data)?;
+        let (body, checksum) = payload.0.split_at_mut(PAYLOAD_LENGTH - CHECKSUM_BYTES);
+        checksum
+            .iter_mut()
+            .zip(body_checksum(body).to_be_bytes())
+            .for_each(|(p, b)| *p = PayloadByteType::from(b));
+        Ok(payload)
+    }
+
+    /// Checks the checksum written by [`Payload::with_checksum`].
+    ///
+    /// Every payload byte enters the checksum as a full [`PayloadByteType`], so a
+    /// byte decoded out of the 8-bit range is caught as well.
+    pub fn verify_checksum(&self) -> bool {
+        let (body, checksum) = self.0.split_at(PAYLOAD_LENGTH - CHECKSUM_BYTES);
+        checksum
+            .iter()
+            .zip(body_checksum(body).to_be_bytes())
+            .all(|(&p, b)| p == PayloadByteType::from(b))
+    }
+
+    /// Compares only the content of two length-prefixed payloads, ignoring the padding
+    /// after it.
+    ///
+    /// Payloads without a valid length prefix, such as [`Payload::random`] ones, are
+    /// compared byte for byte.
+    pub fn content_eq(&self, other: &Self) -> bool {
+        match (self.content(), other.content()) {
+            (Ok(x), Ok(y)) => x == y,
+            _ => self == other,
+        }
+    }
+
     /// Returns an iterator over the payload.
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = &PayloadByteType> {
@@ -79,6 +174,51 @@ impl Payload {
         }
     }
 
+    /// Returns `sum(weights[i] * payloads[i])`, accumulating one whole payload at a time.
+    pub fn combine<M: RingReduce<PayloadByteType> + Copy>(
+        payloads: &[Payload],
+        weights: &[PayloadByteType],
+        modulus: M,
+    ) -> Self {
+        payloads
+            .iter()
+            .zip(weights.iter())
+            .fold(Self::new(), |mut acc, (payload, &weight)| {
+                acc.add_scaled_assign(payload, weight, modulus);
+                acc
+            })
+    }
+
+    /// Computes `self += rhs * scalar` in a single pass.
+    #[inline]
+    pub fn add_scaled_assign<M: RingReduce<PayloadByteType>>(
+        &mut self,
+        rhs: &Self,
+        scalar: PayloadByteType,
+        modulus: M,
+    ) {
+        for (r, &b) in izip!(self.0.iter_mut(), rhs.0.iter()) {
+            let mut t = b;
+            modulus.reduce_mul_assign(&mut t, scalar);
+            modulus.reduce_add_assign(r, t);
+        }
+    }
+
+    /// Computes `self -= rhs * scalar` in a single pass.
+    #[inline]
+    pub fn sub_scaled_assign<M: RingReduce<PayloadByteType>>(
+        &mut self,
+        rhs: &Self,
+        scalar: PayloadByteType,
+        modulus: M,
+    ) {
+        for (r, &b) in izip!(self.0.iter_mut(), rhs.0.iter()) {
+            let mut t = b;
+            modulus.reduce_mul_assign(&mut t, scalar);
+            modulus.reduce_sub_assign(r, t);
+        }
+    }
+
     #[inline]
     pub fn mul_scalar<M: RingReduce<PayloadByteType>>(
         mut self,
@@ -102,3 +242,232 @@ impl Payload {
         }
     }
 }
+
+/// Returns the checksum of the payload bytes before the checksum.
+fn body_checksum(body: &[PayloadByteType]) -> u64 {
+    fnv1a(body.iter().flat_map(|b| b.to_le_bytes()))
+}
+
+impl BitXorAssign<&Payload> for Payload {
+    /// XORs `rhs` into `self` byte-wise, the addition of GF(2)-coded combinations.
+    #[inline]
+    fn bitxor_assign(&mut self, rhs: &Payload) {
+        for (r, &b) in izip!(self.0.iter_mut(), rhs.0.iter()) {
+            *r ^= b;
+        }
+    }
+}
+
+impl BitXorAssign for Payload {
+    #[inline]
+    fn bitxor_assign(&mut self, rhs: Payload) {
+        *self ^= &rhs;
+    }
+}
+
+impl BitXor<&Payload> for Payload {
+    type Output = Payload;
+
+    #[inline]
+    fn bitxor(mut self, rhs: &Payload) -> Self::Output {
+        self ^= rhs;
+        self
+    }
+}
+
+impl BitXor for Payload {
+    type Output = Payload;
+
+    #[inline]
+    fn bitxor(mut self, rhs: Payload) -> Self::Output {
+        self ^= &rhs;
+        self
+    }
+}
+
+/// Payloads stored column-major, byte `j` of every payload being contiguous.
+///
+/// [`PayloadMatrix::combine`] walks one byte column across all payloads at a time,
+/// which keeps the accumulation in cache for large payload counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadMatrix {
+    columns: Vec<PayloadByteType>,
+    payload_count: usize,
+}
+
+impl PayloadMatrix {
+    /// Creates a new [`PayloadMatrix`] by transposing `payloads`.
+    pub fn from_payloads(payloads: &[Payload]) -> Self {
+        let payload_count = payloads.len();
+        let mut columns = vec![0; PAYLOAD_LENGTH * payload_count];
+        for (i, payload) in payloads.iter().enumerate() {
+            for (j, &byte) in payload.iter().enumerate() {
+                columns[j * payload_count + i] = byte;
+            }
+        }
+        Self {
+            columns,
+            payload_count,
+        }
+    }
+
+    /// Returns the payload count of this [`PayloadMatrix`].
+    #[inline]
+    pub fn payload_count(&self) -> usize {
+        self.payload_count
+    }
+
+    /// Returns byte `j` of every payload.
+    #[inline]
+    pub fn column(&self, j: usize) -> &[PayloadByteType] {
+        &self.columns[j * self.payload_count..(j + 1) * self.payload_count]
+    }
+
+    /// Transposes this [`PayloadMatrix`] back into payloads.
+    pub fn to_payloads(&self) -> Vec<Payload> {
+        let mut payloads = vec![Payload::new(); self.payload_count];
+        for j in 0..PAYLOAD_LENGTH {
+            for (payload, &byte) in payloads.iter_mut().zip(self.column(j)) {
+                payload.0[j] = byte;
+            }
+        }
+        payloads
+    }
+
+    /// Returns `sum(weights[i] * payloads[i])`, the same as [`Payload::combine`].
+    pub fn combine<M: RingReduce<PayloadByteType> + Copy>(
+        &self,
+        weights: &[PayloadByteType],
+        modulus: M,
+    ) -> Payload {
+        let mut result = Payload::new();
+        for (j, r) in result.iter_mut().enumerate() {
+            for (&byte, &weight) in self.column(j).iter().zip(weights.iter()) {
+                let mut t = byte;
+                modulus.reduce_mul_assign(&mut t, weight);
+                modulus.reduce_add_assign(r, t);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use algebra::modulus::PowOf2Modulus;
+
+    use super::*;
+
+    #[test]
+    fn xor_twice_is_identity() {
+        let mut rng = rand::thread_rng();
+        let a = Payload::random(&mut rng);
+        let b = Payload::random(&mut rng);
+
+        assert_eq!(a ^ b ^ b, a);
+        assert_eq!(a ^ a, Payload::new());
+
+        let mut c = a;
+        c ^= &b;
+        assert_ne!(c, a);
+        c ^= b;
+        assert_eq!(c, a);
+    }
+
+    #[test]
+    fn column_major_combine_matches_row_major() {
+        use algebra::modulus::BarrettModulus;
+        use rand::Rng;
+
+        let mut rng = rand::thread_rng();
+        let payloads: Vec<Payload> = (0..50).map(|_| Payload::random(&mut rng)).collect();
+        let matrix = PayloadMatrix::from_payloads(&payloads);
+        assert_eq!(matrix.to_payloads(), payloads);
+
+        let weights: Vec<PayloadByteType> = (0..50).map(|_| rng.gen_range(0..257)).collect();
+        let modulus = <BarrettModulus<PayloadByteType>>::new(257);
+        assert_eq!(
+            matrix.combine(&weights, modulus),
+            Payload::combine(&payloads, &weights, modulus)
+        );
+
+        let modulus = <PowOf2Modulus<PayloadByteType>>::new(256);
+        assert_eq!(
+            matrix.combine(&weights, modulus),
+            Payload::combine(&payloads, &weights, modulus)
+        );
+    }
+
+    #[test]
+    fn content_eq_ignores_padding() {
+        let x = Payload::with_content(b"hello").unwrap();
+        let mut y = x;
+        y.0[PAYLOAD_LENGTH - 1] = 7;
+
+        assert_ne!(x, y);
+        assert!(x.content_eq(&y));
+        assert!(!x.content_eq(&Payload::with_content(b"hellO").unwrap()));
+    }
+
+    #[test]
+    fn checksum_catches_a_flipped_byte() {
+        let mut rng = rand::thread_rng();
+        let mut data = [0u8; 100];
+        rng.fill_bytes(&mut data);
+
+        let payload = Payload::with_checksum(&data).unwrap();
+        assert!(payload.verify_checksum());
+        assert_eq!(payload.content().unwrap(), data);
+
+        // A solve gone wrong in one byte, inside or past the content.
+        for i in [
+            LENGTH_PREFIX_BYTES + 10,
+            PAYLOAD_LENGTH - CHECKSUM_BYTES - 1,
+        ] {
+            let mut flipped = payload;
+            flipped.0[i] ^= 1;
+            assert!(!flipped.verify_checksum());
+        }
+        let mut out_of_range = payload;
+        out_of_range.0[LENGTH_PREFIX_BYTES] += 256;
+        assert!(!out_of_range.verify_checksum());
+
+        assert!(!Payload::with_content(&data).unwrap().verify_checksum());
+        assert!(Payload::with_checksum(&[0; PAYLOAD_CHECKSUM_CONTENT_CAPACITY + 1]).is_err());
+    }
+
+    #[test]
+    fn content_round_trip() {
+        let mut rng = rand::thread_rng();
+        let mut data = [0u8; 100];
+        rng.fill_bytes(&mut data);
+
+        let payload = Payload::with_content(&data).unwrap();
+        assert_eq!(payload.content().unwrap(), data);
+
+        let full = [0xab; PAYLOAD_CONTENT_CAPACITY];
+        assert_eq!(
+            Payload::with_content(&full).unwrap().content().unwrap(),
+            full
+        );
+        assert!(Payload::with_content(&[0; PAYLOAD_CONTENT_CAPACITY + 1]).is_err());
+    }
+
+    #[test]
+    fn sub_scaled_assign_matches_two_step() {
+        let mut rng = rand::thread_rng();
+        let modulus = <PowOf2Modulus<PayloadByteType>>::new(256);
+
+        let a = Payload::random(&mut rng);
+        let b = Payload::random(&mut rng);
+        let scalar = 173;
+
+        let mut fused = a;
+        fused.sub_scaled_assign(&b, scalar, modulus);
+
+        let mut two_step = a;
+        two_step.sub_assign(&b.mul_scalar(scalar, modulus), modulus);
+
+        assert_eq!(fused, two_step);
+    }
+}
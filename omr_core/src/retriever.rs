@@ -4,22 +4,25 @@ use std::{collections::HashSet, sync::Arc};
 
 use algebra::{
     integer::{AsInto, Bits, UnsignedInteger},
-    modulus::BarrettModulus,
+    modulus::{BarrettModulus, PowOf2Modulus},
     ntt::{NttTable, NumberTheoryTransform},
     polynomial::FieldNttPolynomial,
+    reduce::RingReduce,
     Field, NttField,
 };
-use bigdecimal::{BigDecimal, RoundingMode};
+#[cfg(feature = "noise-analysis")]
+use bigdecimal::BigDecimal;
 use fhe_core::{NttRlweCiphertext, NttRlweSecretKey};
 use lattice::NttRlwe;
-use num_traits::{ConstZero, FromPrimitive, One, ToPrimitive, Zero};
-use rand::{rngs::StdRng, SeedableRng};
-use rand_distr::{Distribution, Uniform};
+use num_traits::One;
+use rayon::prelude::*;
 
 use crate::{
+    log,
     matrix::{solve_matrix, solve_matrix_mod_256, solve_matrix_mod_257},
     payload::PayloadByteType,
-    OmrError, Payload, RetrievalParams, PAYLOAD_LENGTH,
+    OmrError, Payload, RetrievalParams, RetrievalParamsFingerprint, SecondLevelField,
+    PAYLOAD_LENGTH,
 };
 
 /// Client-side decoder for digests produced by the detector.
@@ -29,6 +32,10 @@ pub struct Retriever<F: NttField> {
     ntt_table: Arc<<F as NttField>::Table>,
     key: NttRlweSecretKey<F>,
     pertinent_indices_set: HashSet<usize>,
+    combined_payloads: Vec<Payload>,
+    indicator_margin: Option<f64>,
+    max_indices: usize,
+    max_combinations: usize,
 }
 
 impl<F: NttField> Retriever<F> {
@@ -44,89 +51,241 @@ impl<F: NttField> Retriever<F> {
             ntt_table,
             key,
             pertinent_indices_set: HashSet::with_capacity(params.pertinent_count()),
+            combined_payloads: Vec::with_capacity(params.combination_count()),
+            indicator_margin: None,
+            max_indices: params.pertinent_count() * 2,
+            max_combinations: params.combination_count(),
         }
     }
 
+    /// Creates a new [`Retriever<F>`] whose [`RetrievalParams<F>`] are rebuilt from
+    /// the fingerprint embedded in a digest, so that it matches the detector's layout.
+    pub fn new_matching(
+        params_fingerprint: &[u8],
+        ntt_table: Arc<<F as NttField>::Table>,
+        key: NttRlweSecretKey<F>,
+    ) -> Result<Self, OmrError> {
+        let fingerprint = RetrievalParamsFingerprint::from_bytes(params_fingerprint)?;
+        Self::from_fingerprint(&fingerprint, ntt_table, key)
+    }
+
+    /// Creates a new [`Retriever<F>`] like [`Retriever::new_matching`], from an already
+    /// deserialized fingerprint such as the one returned by
+    /// [`deserialize_combinations`](crate::deserialize_combinations).
+    pub fn from_fingerprint(
+        fingerprint: &RetrievalParamsFingerprint,
+        ntt_table: Arc<<F as NttField>::Table>,
+        key: NttRlweSecretKey<F>,
+    ) -> Result<Self, OmrError> {
+        if fingerprint.polynomial_size() != ntt_table.dimension() {
+            return Err(OmrError::DimensionMismatch {
+                expected: ntt_table.dimension(),
+                found: fingerprint.polynomial_size(),
+            });
+        }
+
+        let params = RetrievalParams::from_fingerprint(fingerprint)?;
+        Ok(Self::new(params, ntt_table, key))
+    }
+
     /// Returns the retrieval parameters.
     #[inline]
     pub fn params(&self) -> RetrievalParams<F> {
         self.params
     }
 
+    /// Returns this [`Retriever<F>`] accepting a bucket indicator only when its
+    /// unrounded value `c * p / q` is within `margin` of 1.
+    ///
+    /// Without a margin any indicator rounding to 1 is accepted, so noise pushing an
+    /// empty bucket's 0 past 1/2 inserts a spurious index. A margin below 1/2 rejects
+    /// those at the cost of occasionally missing a real index.
+    pub fn with_indicator_margin(mut self, margin: f64) -> Self {
+        assert!(
+            margin > 0.0 && margin <= 0.5,
+            "Indicator margin must be in (0, 0.5]."
+        );
+        self.indicator_margin = Some(margin);
+        self
+    }
+
+    /// Returns this [`Retriever<F>`] rejecting index digests which decode to more
+    /// than `max_indices` distinct indices, `pertinent_count * 2` by default.
+    ///
+    /// This bounds the memory a malformed digest can make the retriever allocate.
+    pub fn with_max_indices(mut self, max_indices: usize) -> Self {
+        assert!(
+            max_indices >= self.params.pertinent_count(),
+            "Max indices must be at least the pertinent count."
+        );
+        self.max_indices = max_indices;
+        self
+    }
+
+    /// Returns this [`Retriever<F>`] decoding at most `max_combinations` combination
+    /// rows, `combination_count` by default. Ciphertexts beyond are never decrypted.
+    ///
+    /// This bounds the work a digest with excess combinations costs, at the price
+    /// of the rows kept as margin over the pertinent count.
+    pub fn with_max_combinations(mut self, max_combinations: usize) -> Self {
+        assert!(
+            max_combinations >= self.params.pertinent_count(),
+            "Max combinations must be at least the pertinent count."
+        );
+        self.max_combinations = max_combinations;
+        self
+    }
+
+    /// Returns the number of combination rows this [`Retriever<F>`] decodes,
+    /// at most `combination_count`.
+    #[inline]
+    pub fn max_combinations(&self) -> usize {
+        self.max_combinations.min(self.params.combination_count())
+    }
+
+    /// Returns the maximum number of distinct indices this [`Retriever<F>`] accepts.
+    #[inline]
+    pub fn max_indices(&self) -> usize {
+        self.max_indices
+    }
+
     /// Returns a reference to the pertinent indices set of this [`Retriever<F>`].
     #[inline]
     pub fn pertinent_indices_set(&self) -> &HashSet<usize> {
         &self.pertinent_indices_set
     }
 
-    #[allow(clippy::result_unit_err)]
-    pub fn decode_pertinent_indices(&mut self, encoded_indices: &NttRlwe<F>) -> Result<usize, ()> {
-        // Decode index digest into a set of pertinent indices.
-        // Power-of-two moduli use bit unpacking; otherwise use base-(index_modulus).
-        let slots_per_bucket = self.params.slots_per_bucket();
-        let slots_per_segment = self.params.slots_per_segment();
-        let index_modulus = self.params.index_modulus();
+    /// Returns the pertinent indices decoded so far, in ascending order, as a checkpoint
+    /// for [`Retriever::import_progress`].
+    #[inline]
+    pub fn export_progress(&self) -> Vec<usize> {
+        self.sorted_pertinent_indices()
+    }
 
-        let is_power_of_two = index_modulus.is_power_of_two();
+    /// Restores pertinent indices exported by [`Retriever::export_progress`], so index
+    /// ciphertexts can keep being decoded after a restart.
+    ///
+    /// Fails with [`OmrError::IndexOutOfRange`] if an index is not on the board, or
+    /// with [`OmrError::TooManyIndices`] if the set would exceed
+    /// [`Retriever::max_indices`]. Nothing is imported on failure.
+    pub fn import_progress(&mut self, indices: &[usize]) -> Result<(), OmrError> {
+        check_indices_in_range(indices, self.params.all_payloads_count())?;
 
-        let shift_bits = index_modulus.trailing_zeros();
+        let new_count = indices
+            .iter()
+            .filter(|&index| !self.pertinent_indices_set.contains(index))
+            .collect::<HashSet<_>>()
+            .len();
+        if self.pertinent_indices_set.len() + new_count > self.max_indices {
+            return Err(OmrError::TooManyIndices {
+                bound: self.max_indices,
+            });
+        }
 
-        let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
-        let q = BigDecimal::from(q);
-        let p: u16 = index_modulus.as_into();
-        let p = BigDecimal::from(p);
+        self.pertinent_indices_set.extend(indices);
+        Ok(())
+    }
+
+    /// Decrypts an index ciphertext and rounds every coefficient to the index modulus,
+    /// before any bucket is interpreted.
+    ///
+    /// Slot `slots_per_bucket - 1` of each bucket is its indicator, which is 1 when
+    /// exactly one pertinent index landed in the bucket.
+    pub fn decode_index_coefficients(&self, encoded_indices: &NttRlwe<F>) -> Vec<F::ValueT> {
+        round_coefficients::<F>(
+            self.decrypt_index_coefficients(encoded_indices).into_iter(),
+            self.params.index_modulus(),
+        )
+    }
+
+    /// Decodes every occupied bucket of an index ciphertext into `(bucket, index)`
+    /// pairs without touching the pertinent indices set, for debugging collisions.
+    ///
+    /// Buckets are numbered across segments. A bucket whose indicator says `k > 1`
+    /// messages landed in it yields `k` pairs with index `None`, since the summed
+    /// digits no longer name any of them.
+    pub fn decode_pertinent_indices_debug(
+        &self,
+        encoded_indices: &NttRlwe<F>,
+    ) -> Result<Vec<(usize, Option<usize>)>, OmrError> {
+        let index_base = self.params.index_base();
+        let all_payloads_count = self.params.all_payloads_count();
+
+        let decoded = self.decode_index_coefficients(encoded_indices);
+        let buckets = decoded
+            .chunks_exact(self.params.slots_per_segment())
+            .flat_map(|segment| segment.chunks_exact(self.params.slots_per_bucket()));
 
+        let mut pairs = Vec::new();
+        for (bucket_index, bucket) in buckets.enumerate() {
+            let count: u64 = (*bucket.last().unwrap()).as_into();
+            if count == 1 {
+                let index = decode_bucket::<F>(bucket, index_base, all_payloads_count)?;
+                pairs.push((bucket_index, index));
+            } else {
+                pairs.extend(std::iter::repeat_n((bucket_index, None), count as usize));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Decrypts an index ciphertext to its unrounded coefficients modulo `q`.
+    fn decrypt_index_coefficients(&self, encoded_indices: &NttRlwe<F>) -> Vec<F::ValueT> {
         let decrypted_ntt = encoded_indices.b() - encoded_indices.a().clone() * &*self.key;
-        let decrypted = self.ntt_table.inverse_transform_inplace(decrypted_ntt);
-        let decoded = decrypted
+        self.ntt_table
+            .inverse_transform_inplace(decrypted_ntt)
             .into_iter()
-            .map(|c: F::ValueT| {
-                let mut t = (BigDecimal::from_u64(c.as_into()).unwrap() * &p / &q)
-                    .with_scale_round(0, RoundingMode::HalfUp);
-                if t >= p {
-                    t -= &p;
+            .collect()
+    }
+
+    /// Decodes an index ciphertext into the pertinent indices set, returning whether
+    /// all `pertinent_count` indices are now known.
+    ///
+    /// Fails with [`OmrError::TooManyIndices`] once the set would exceed
+    /// [`Retriever::max_indices`].
+    pub fn decode_pertinent_indices(
+        &mut self,
+        encoded_indices: &NttRlwe<F>,
+    ) -> Result<bool, OmrError> {
+        // Decode index digest into a set of pertinent indices.
+        // Power-of-two bases use bit unpacking; otherwise use base-(index_base).
+        let slots_per_bucket = self.params.slots_per_bucket();
+        let slots_per_segment = self.params.slots_per_segment();
+        let index_base = self.params.index_base();
+        let all_payloads_count = self.params.all_payloads_count();
+        let index_modulus = self.params.index_modulus();
+
+        let coefficients = self.decrypt_index_coefficients(encoded_indices);
+        let decoded = round_coefficients::<F>(coefficients.iter().copied(), index_modulus);
+
+        for (chunk, raw_chunk) in decoded
+            .chunks_exact(slots_per_segment)
+            .zip(coefficients.chunks_exact(slots_per_segment))
+        {
+            for (bucket, raw_bucket) in chunk
+                .chunks_exact(slots_per_bucket)
+                .zip(raw_chunk.chunks_exact(slots_per_bucket))
+            {
+                let indicator = *raw_bucket.last().unwrap();
+                if self.indicator_margin.is_some_and(|margin| {
+                    !indicator_within_margin::<F>(indicator, index_modulus, margin)
+                }) {
+                    continue;
                 }
-                t.to_u64().unwrap().as_into()
-            })
-            .collect::<Vec<F::ValueT>>();
-
-        if is_power_of_two {
-            decoded.chunks_exact(slots_per_segment).for_each(|chunk| {
-                chunk.chunks_exact(slots_per_bucket).for_each(|bucket| {
-                    if bucket.last().unwrap().is_one() {
-                        let index = bucket
-                            .iter()
-                            .rev()
-                            .skip(1)
-                            .fold(<F::ValueT as ConstZero>::ZERO, |acc, &v| {
-                                (acc << shift_bits) | v
-                            });
-                        self.pertinent_indices_set.insert(index.as_into());
-                    }
-                });
-            });
-        } else {
-            decoded.chunks_exact(slots_per_segment).for_each(|chunk| {
-                chunk.chunks_exact(slots_per_bucket).for_each(|bucket| {
-                    if bucket.last().unwrap().is_one() {
-                        let index = bucket
-                            .iter()
-                            .rev()
-                            .skip(1)
-                            .fold(<F::ValueT as ConstZero>::ZERO, |acc, &v| {
-                                acc * index_modulus + v
-                            });
-                        self.pertinent_indices_set.insert(index.as_into());
+                if let Some(index) = decode_bucket::<F>(bucket, index_base, all_payloads_count)? {
+                    if !self.pertinent_indices_set.contains(&index)
+                        && self.pertinent_indices_set.len() == self.max_indices
+                    {
+                        return Err(OmrError::TooManyIndices {
+                            bound: self.max_indices,
+                        });
                     }
-                });
-            });
+                    self.pertinent_indices_set.insert(index);
+                }
+            }
         }
 
-        if self.pertinent_indices_set.len() == self.params.pertinent_count() {
-            Ok(self.params.pertinent_count())
-        } else {
-            Err(())
-        }
+        Ok(self.pertinent_indices_set.len() == self.params.pertinent_count())
     }
 
     // pub fn test_combine(
@@ -193,173 +352,504 @@ impl<F: NttField> Retriever<F> {
     ) -> Result<(Vec<usize>, Vec<Payload>), OmrError> {
         // Full digest decode: indices first, then payloads.
         // Payloads are recovered by solving a small linear system per combination set.
-        let combination_count = self.params.combination_count();
-        let all_payloads_count = self.params.all_payloads_count();
-        let p: PayloadByteType = self.params.index_modulus().as_into();
+        for ciphertext in encode_pertinent_indices.iter() {
+            if self.decode_pertinent_indices(ciphertext)? {
+                break;
+            }
+        }
+
+        let indices = self.sorted_pertinent_indices();
+        log::debug!("decoded {} pertinent indices", indices.len());
+
+        let (matrix, mut combined_payloads) = rayon::join(
+            || self.combination_matrix(&indices, seed),
+            || self.decode_combined_payloads(encode_pertinent_payloads),
+        );
+        let mut matrix = matrix?;
+        matrix.truncate(combined_payloads.len());
+
+        let payloads = self.solve_combinations(&mut matrix, &mut combined_payloads)?;
+
+        Ok((indices, payloads))
+    }
 
+    /// Decodes a full digest like [`Retriever::decode_digest`], holding back some
+    /// combination rows to validate the solved payloads.
+    ///
+    /// Half of the rows beyond the pertinent count are left out of the solve. A payload
+    /// is flagged inconsistent when a held-back row that weights it disagrees with the
+    /// solution. With dense random weights one bad row usually flags every payload:
+    /// the flags say which payloads cannot be trusted, not which row went wrong.
+    pub fn retrieve_validated(
+        &mut self,
+        encode_pertinent_indices: &[NttRlwe<F>],
+        encode_pertinent_payloads: &[NttRlweCiphertext<F>],
+        seed: [u8; 32],
+    ) -> Result<(Vec<usize>, Vec<(Payload, bool)>), OmrError> {
         for ciphertext in encode_pertinent_indices.iter() {
-            if self.decode_pertinent_indices(ciphertext).is_ok() {
+            if self.decode_pertinent_indices(ciphertext)? {
                 break;
             }
         }
 
-        let pertinent_indices_set = self.pertinent_indices_set();
-        let mut indices = pertinent_indices_set
+        let indices = self.sorted_pertinent_indices();
+
+        let (matrix, combined_payloads) = rayon::join(
+            || self.combination_matrix(&indices, seed),
+            || self.decode_combined_payloads(encode_pertinent_payloads),
+        );
+        let mut matrix = matrix?;
+        matrix.truncate(combined_payloads.len());
+
+        let validation_count = combined_payloads.len().saturating_sub(indices.len()) / 2;
+        let solve_count = combined_payloads.len() - validation_count;
+
+        let mut solve_matrix = matrix[..solve_count].to_vec();
+        let mut solve_payloads = combined_payloads[..solve_count].to_vec();
+        let payloads = self.solve_combinations(&mut solve_matrix, &mut solve_payloads)?;
+
+        let mut consistent = vec![true; payloads.len()];
+        for (row, combined) in matrix[solve_count..]
+            .iter()
+            .zip(&combined_payloads[solve_count..])
+        {
+            if !self.verify_solution(
+                std::slice::from_ref(row),
+                &payloads,
+                std::slice::from_ref(combined),
+            ) {
+                consistent
+                    .iter_mut()
+                    .zip(row)
+                    .filter(|(_, &weight)| weight != 0)
+                    .for_each(|(flag, _)| *flag = false);
+            }
+        }
+
+        Ok((indices, payloads.into_iter().zip(consistent).collect()))
+    }
+
+    /// Decodes one combination ciphertext and appends its rows to the internal buffer.
+    ///
+    /// Combination ciphertexts must be fed in the order the detector produced them,
+    /// the ciphertext can be dropped right after this call.
+    /// Rows beyond [`Retriever::max_combinations`] are ignored.
+    pub fn feed_combination(&mut self, cipher: &NttRlweCiphertext<F>) {
+        let remaining = self
+            .max_combinations()
+            .saturating_sub(self.combined_payloads.len());
+        let count = self.params.cmb_count_per_cipher().min(remaining);
+        if count == 0 {
+            return;
+        }
+
+        let mut combined_payloads = std::mem::take(&mut self.combined_payloads);
+        let start = combined_payloads.len();
+        combined_payloads.resize(start + count, Payload::new());
+
+        let mut temp = <FieldNttPolynomial<F>>::zero(self.ntt_table.dimension());
+        self.decode_combination(cipher, &mut temp, &mut combined_payloads[start..]);
+
+        self.combined_payloads = combined_payloads;
+    }
+
+    /// Returns the number of combined payload rows fed so far.
+    #[inline]
+    pub fn fed_combination_count(&self) -> usize {
+        self.combined_payloads.len()
+    }
+
+    /// Solves the payloads from the combination rows fed by [`Self::feed_combination`].
+    ///
+    /// The pertinent indices must already be decoded. The internal buffer is
+    /// consumed, so a new round of feeding can start afterwards.
+    pub fn solve(&mut self, seed: [u8; 32]) -> Result<(Vec<usize>, Vec<Payload>), OmrError> {
+        let indices = self.sorted_pertinent_indices();
+        let mut combined_payloads = std::mem::take(&mut self.combined_payloads);
+
+        if combined_payloads.len() < indices.len() {
+            return Err(OmrError::InsufficientCombinations {
+                fed: combined_payloads.len(),
+                needed: indices.len(),
+            });
+        }
+
+        let mut matrix = self.combination_matrix(&indices, seed)?;
+        matrix.truncate(combined_payloads.len());
+
+        let payloads = self.solve_combinations(&mut matrix, &mut combined_payloads)?;
+
+        Ok((indices, payloads))
+    }
+
+    /// Checks that re-combining `solved_payloads` with the weights derived from `seed`
+    /// reproduces the payloads decoded from `combinations`.
+    ///
+    /// This only needs the retriever's own outputs, so it catches solve errors
+    /// without access to the source payloads.
+    pub fn self_check(
+        &self,
+        indices: &[usize],
+        solved_payloads: &[Payload],
+        combinations: &[NttRlweCiphertext<F>],
+        seed: [u8; 32],
+    ) -> bool {
+        if indices.len() != solved_payloads.len() {
+            return false;
+        }
+
+        let (matrix, combined_payloads) = rayon::join(
+            || self.combination_matrix(indices, seed),
+            || self.decode_combined_payloads(combinations),
+        );
+        let Ok(matrix) = matrix else {
+            return false;
+        };
+
+        self.verify_solution(&matrix, solved_payloads, &combined_payloads)
+    }
+
+    /// Checks `matrix * solved_payloads == decoded_rhs` modulo the index modulus.
+    ///
+    /// `matrix` is the weight matrix from the combination seed and `decoded_rhs` the
+    /// payloads decoded from the combination ciphertexts, so unlike comparing against
+    /// the source payloads this is a check the retriever can actually perform.
+    pub fn verify_solution(
+        &self,
+        matrix: &[Vec<PayloadByteType>],
+        solved_payloads: &[Payload],
+        decoded_rhs: &[Payload],
+    ) -> bool {
+        combinations_match_mod(
+            self.params.index_modulus().as_into(),
+            matrix,
+            solved_payloads,
+            decoded_rhs,
+        )
+    }
+
+    /// Returns the decoded pertinent indices in ascending order.
+    fn sorted_pertinent_indices(&self) -> Vec<usize> {
+        let mut indices = self
+            .pertinent_indices_set
             .iter()
             .copied()
             .collect::<Vec<usize>>();
         indices.sort_unstable();
+        indices
+    }
 
+    /// Rebuilds the combination weights of the pertinent `indices` from `seed`.
+    ///
+    /// Returns [`OmrError::IndexOutOfRange`] if a decoded index is not on the board.
+    fn combination_matrix(
+        &self,
+        indices: &[usize],
+        seed: [u8; 32],
+    ) -> Result<Vec<Vec<PayloadByteType>>, OmrError> {
+        let combination_count = self.params.combination_count();
+        let all_payloads_count = self.params.all_payloads_count();
+        check_indices_in_range(indices, all_payloads_count)?;
         let pertinent_count = indices.len();
 
-        let get_matrix = || {
-            let mut seed_rng = StdRng::from_seed(seed);
-            let mut weights: Vec<PayloadByteType> = vec![0; combination_count * all_payloads_count];
+        let weights = self.params.combination_weights(seed);
 
-            let distr: Uniform<PayloadByteType> = Uniform::new(0, p);
-
-            distr
-                .sample_iter(&mut seed_rng)
-                .zip(weights.iter_mut())
-                .for_each(|(weight, w): (PayloadByteType, &mut PayloadByteType)| {
-                    *w = weight;
-                });
-
-            let mut matrix: Vec<Vec<PayloadByteType>> =
-                vec![vec![0; pertinent_count]; combination_count];
-            let mut matrix_iter = matrix.iter_mut();
-            for weights_chunk in weights.chunks_exact(all_payloads_count) {
-                let row = matrix_iter.next().unwrap();
+        let mut matrix: Vec<Vec<PayloadByteType>> =
+            vec![vec![0; pertinent_count]; combination_count];
+        matrix
+            .par_iter_mut()
+            .zip(weights.par_chunks_exact(all_payloads_count))
+            .for_each(|(row, weights_chunk)| {
                 row.iter_mut().zip(indices.iter()).for_each(
                     |(ele, &i): (&mut PayloadByteType, &usize)| {
                         *ele = weights_chunk[i];
                     },
                 )
-            }
-            matrix
-        };
+            });
+        Ok(matrix)
+    }
 
-        let (mut matrix, mut combined_payloads) = rayon::join(get_matrix, || {
-            self.decode_combined_payloads(encode_pertinent_payloads)
-        });
+    /// Solves `matrix * payloads = combined_payloads` modulo the index modulus.
+    ///
+    /// This is the ring the detector combined in, see [`Detector::encode_pertinent_payloads`]
+    /// for why there is no GF(2^8) path.
+    ///
+    /// [`Detector::encode_pertinent_payloads`]: crate::Detector::encode_pertinent_payloads
+    fn solve_combinations(
+        &self,
+        matrix: &mut [Vec<PayloadByteType>],
+        combined_payloads: &mut [Payload],
+    ) -> Result<Vec<Payload>, OmrError> {
+        let p: PayloadByteType = self.params.index_modulus().as_into();
 
-        let payloads = if p == 256 {
-            solve_matrix_mod_256(&mut matrix, &mut combined_payloads)?
+        if p == 256 {
+            solve_matrix_mod_256(matrix, combined_payloads)
         } else if p == 257 {
-            solve_matrix_mod_257(&mut matrix, &mut combined_payloads)?
+            solve_matrix_mod_257(matrix, combined_payloads)
         } else {
             solve_matrix(
-                &mut matrix,
-                &mut combined_payloads,
+                matrix,
+                combined_payloads,
                 <BarrettModulus<PayloadByteType>>::new(p),
                 p,
-            )?
-        };
-
-        Ok((indices, payloads))
+            )
+        }
     }
 
-    // pub fn decode_combined_payloads_with_noise(
-    //     &self,
-    //     combinations: &[NttRlweCiphertext<F>],
-    // ) -> Vec<Payload> {
-    //     let combination_count = self.params.combination_count();
-    //     let cmb_count_per_cipher = self.params.cmb_count_per_cipher();
-    //     let all_count = self.params.combination_count() * 612;
-
-    //     let q: <F as Field>::ValueT = <F as Field>::MODULUS_VALUE;
-    //     let delta: <F as Field>::ValueT = q / 256u16.as_into();
-    //     let sigma = 349228353888.975f64;
-    //     let mut noise_sigma_info = NoiseSigmaInfo::<F>::new(sigma, q, all_count);
-
-    //     let q_d = BigDecimal::from_u64(<F as Field>::MODULUS_VALUE.as_into()).unwrap();
-    //     let p = BigDecimal::from_u16(256).unwrap();
-
-    //     let mut payloads = vec![Payload::new(); combination_count];
-    //     let mut temp = <FieldNttPolynomial<F>>::zero(self.ntt_table.dimension());
-
-    //     payloads
-    //         .chunks_mut(cmb_count_per_cipher)
-    //         .zip(combinations.iter())
-    //         .for_each(
-    //             |(payload_chunk, cipher): (&mut [Payload], &NttRlweCiphertext<F>)| {
-    //                 sub_mul(cipher.b(), cipher.a(), &self.key, &mut temp);
-    //                 self.ntt_table.inverse_transform_slice(temp.as_mut_slice());
-    //                 payload_chunk
-    //                     .iter_mut()
-    //                     .zip(temp.as_slice().chunks_exact(PAYLOAD_LENGTH))
-    //                     .for_each(|(payload, dec_chunk)| {
-    //                         payload
-    //                             .iter_mut()
-    //                             .zip(dec_chunk.iter())
-    //                             .for_each(|(byte, &coeff)| {
-    //                                 let mut t = (BigDecimal::from_u64(coeff.as_into()).unwrap()
-    //                                     * &p
-    //                                     / &q_d)
-    //                                     .with_scale_round(0, RoundingMode::HalfUp);
-    //                                 if t >= p {
-    //                                     t -= &p;
-    //                                 }
-    //                                 *byte = t.to_u64().unwrap() as u8;
-    //                                 let value = F::mul(<F as Field>::ValueT::as_from(*byte), delta);
-    //                                 let x = F::sub(coeff, value);
-
-    //                                 noise_sigma_info.check_noise_sigma(x);
-    //                             });
-    //                     });
-    //             },
-    //         );
-
-    //     noise_sigma_info.print();
-
-    //     payloads
-    // }
-
-    pub fn decode_combined_payloads(&self, combinations: &[NttRlweCiphertext<F>]) -> Vec<Payload> {
-        // Decode linearly combined payloads into raw payloads.
-        // Inverse NTT + modulus rounding yields payload bytes.
-        let combination_count = self.params.combination_count();
+    /// Decodes like [`Retriever::decode_combined_payloads`], also collecting the
+    /// noise of every decoded coefficient against an expected standard deviation `sigma`.
+    ///
+    /// The parameters carry no output noise estimate yet, so the caller supplies `sigma`.
+    #[cfg(feature = "noise-analysis")]
+    pub fn decode_combined_payloads_with_noise(
+        &self,
+        combinations: &[NttRlweCiphertext<F>],
+        sigma: f64,
+    ) -> (Vec<Payload>, NoiseSigmaInfo<F>) {
+        let combination_count = self.max_combinations();
         let cmb_count_per_cipher = self.params.cmb_count_per_cipher();
 
-        let index_modulus = self.params.index_modulus();
-
         let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
-        let q = BigDecimal::from(q);
-        let p: u16 = index_modulus.as_into();
-        let p = BigDecimal::from(p);
+        let delta: u64 = self.params.payload_delta().as_into();
+        let mut noise_sigma_info = NoiseSigmaInfo::<F>::new(sigma, <F as Field>::MODULUS_VALUE);
 
         let mut payloads = vec![Payload::new(); combination_count];
         let mut temp = <FieldNttPolynomial<F>>::zero(self.ntt_table.dimension());
 
-        payloads
+        for (payload_chunk, cipher) in payloads
             .chunks_mut(cmb_count_per_cipher)
             .zip(combinations.iter())
-            .for_each(
-                |(payload_chunk, cipher): (&mut [Payload], &NttRlweCiphertext<F>)| {
-                    sub_mul(cipher.b(), cipher.a(), &self.key, &mut temp);
-                    self.ntt_table.inverse_transform_slice(temp.as_mut_slice());
-                    payload_chunk
-                        .iter_mut()
-                        .zip(temp.as_slice().chunks_exact(PAYLOAD_LENGTH))
-                        .for_each(|(payload, dec_chunk)| {
-                            payload
-                                .iter_mut()
-                                .zip(dec_chunk.iter())
-                                .for_each(|(byte, &coeff)| {
-                                    let mut t =
-                                        (BigDecimal::from_u64(coeff.as_into()).unwrap() * &p / &q)
-                                            .with_scale_round(0, RoundingMode::HalfUp);
-                                    if t >= p {
-                                        t -= &p;
-                                    }
-                                    *byte = t.to_u64().unwrap() as PayloadByteType;
-                                });
-                        })
+        {
+            self.decode_combination(cipher, &mut temp, payload_chunk);
+            payload_chunk
+                .iter()
+                .zip(temp.as_slice().chunks_exact(PAYLOAD_LENGTH))
+                .for_each(|(payload, dec_chunk)| {
+                    payload
+                        .iter()
+                        .zip(dec_chunk.iter())
+                        .for_each(|(&byte, &coeff)| {
+                            let coeff: u64 = coeff.as_into();
+                            let value = (byte as u128 * delta as u128 % q as u128) as u64;
+                            let noise = if coeff >= value {
+                                coeff - value
+                            } else {
+                                coeff + (q - value)
+                            };
+                            noise_sigma_info.check_noise_sigma(noise.as_into());
+                        });
+                });
+        }
+
+        (payloads, noise_sigma_info)
+    }
+
+    pub fn decode_combined_payloads(&self, combinations: &[NttRlweCiphertext<F>]) -> Vec<Payload> {
+        // Decode linearly combined payloads into raw payloads.
+        // Inverse NTT + modulus rounding yields payload bytes.
+        let combination_count = self.max_combinations();
+        let cmb_count_per_cipher = self.params.cmb_count_per_cipher();
+
+        let dimension = self.ntt_table.dimension();
+        let mut payloads = vec![Payload::new(); combination_count];
+
+        payloads
+            .par_chunks_mut(cmb_count_per_cipher)
+            .zip(combinations.par_iter())
+            .for_each_init(
+                || <FieldNttPolynomial<F>>::zero(dimension),
+                |temp, (payload_chunk, cipher): (&mut [Payload], &NttRlweCiphertext<F>)| {
+                    self.decode_combination(cipher, temp, payload_chunk);
                 },
             );
 
         payloads
     }
+
+    /// Decodes a single combination ciphertext into `payload_chunk`.
+    fn decode_combination(
+        &self,
+        cipher: &NttRlweCiphertext<F>,
+        temp: &mut FieldNttPolynomial<F>,
+        payload_chunk: &mut [Payload],
+    ) {
+        let index_modulus = self.params.index_modulus();
+
+        let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
+        let p: u64 = index_modulus.as_into();
+
+        sub_mul(cipher.b(), cipher.a(), &self.key, temp);
+        self.ntt_table.inverse_transform_slice(temp.as_mut_slice());
+        payload_chunk
+            .iter_mut()
+            .zip(temp.as_slice().chunks_exact(PAYLOAD_LENGTH))
+            .for_each(|(payload, dec_chunk)| {
+                payload
+                    .iter_mut()
+                    .zip(dec_chunk.iter())
+                    .for_each(|(byte, &coeff)| {
+                        *byte = scale_round(coeff.as_into(), p, q) as PayloadByteType;
+                    });
+            });
+
+        #[cfg(test)]
+        tests::DECODED_CIPHERS.with(|count| count.set(count.get() + 1));
+    }
+}
+
+/// One recipient's digest: the encoded indices and the combined payloads.
+#[derive(Clone)]
+pub struct Digest<F: NttField> {
+    /// The ciphertexts encoding the pertinent indices.
+    pub indices: Vec<NttRlwe<F>>,
+    /// The ciphertexts holding the combined pertinent payloads.
+    pub combinations: Vec<NttRlweCiphertext<F>>,
+}
+
+/// Decodes `digests[i]` with `retrievers[i]`, one recipient per rayon task.
+///
+/// Each retriever's decoded indices are cleared first, so a retriever reused from an
+/// earlier digest doesn't leak its old indices into this one.
+///
+/// # Panics
+///
+/// Panics if `retrievers` and `digests` differ in length.
+pub fn retrieve_many(
+    retrievers: &mut [Retriever<SecondLevelField>],
+    digests: &[Digest<SecondLevelField>],
+    seed: [u8; 32],
+) -> Vec<Result<(Vec<usize>, Vec<Payload>), OmrError>> {
+    assert_eq!(retrievers.len(), digests.len());
+    retrievers
+        .par_iter_mut()
+        .zip(digests)
+        .map(|(retriever, digest)| {
+            retriever.pertinent_indices_set.clear();
+            retriever.decode_digest(&digest.indices, &digest.combinations, seed)
+        })
+        .collect()
+}
+
+/// Returns the index stored in `bucket`, if its indicator slot is 1.
+///
+/// The digits are in base `index_base`, least significant first, followed by the indicator.
+/// A decode error can leave a digit out of range or a spurious high digit where the
+/// encoder wrote nothing, so indices outside `0..all_payloads_count` are rejected.
+///
+/// Returns [`OmrError::DecodeOverflow`] if the digits don't fit in 64 bits, which only
+/// happens with a bucket layout no valid [`RetrievalParams`] produce.
+fn decode_bucket<F: NttField>(
+    bucket: &[F::ValueT],
+    index_base: F::ValueT,
+    all_payloads_count: usize,
+) -> Result<Option<usize>, OmrError> {
+    let Some((indicator, digits)) = bucket.split_last() else {
+        return Ok(None);
+    };
+    if !indicator.is_one() || digits.iter().any(|&v| v >= index_base) {
+        return Ok(None);
+    }
+
+    let base: u64 = index_base.as_into();
+    let mut digits = digits.iter().rev().map(|&v| -> u64 { v.as_into() });
+    let index = if base.is_power_of_two() {
+        let shift_bits = base.trailing_zeros();
+        digits.try_fold(0u64, |acc, v| {
+            (acc.leading_zeros() >= shift_bits).then(|| (acc << shift_bits) | v)
+        })
+    } else {
+        digits.try_fold(0u64, |acc, v| acc.checked_mul(base)?.checked_add(v))
+    }
+    .ok_or(OmrError::DecodeOverflow)?;
+
+    Ok((index < all_payloads_count as u64).then_some(index as usize))
+}
+
+/// Checks that the unrounded indicator `c * p / q` is within `margin` of 1.
+fn indicator_within_margin<F: NttField>(c: F::ValueT, p: F::ValueT, margin: f64) -> bool {
+    let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
+    let c: u64 = c.as_into();
+    let p: u64 = p.as_into();
+    let x = c as f64 * p as f64 / q as f64;
+    (x - 1.0).abs() < margin
+}
+
+/// Rounds coefficients modulo `q` to the nearest multiple of `q / p`, returning the
+/// multiples modulo `p`.
+pub(crate) fn round_coefficients<F: NttField>(
+    coefficients: impl Iterator<Item = F::ValueT>,
+    p: F::ValueT,
+) -> Vec<F::ValueT> {
+    let q: u64 = <F as Field>::MODULUS_VALUE.as_into();
+    let p: u64 = p.as_into();
+
+    coefficients
+        .map(|c: F::ValueT| scale_round(c.as_into(), p, q).as_into())
+        .collect()
+}
+
+/// Returns `round(c * p / q) mod p` with ties rounded up, for `c < q`.
+#[inline]
+pub(crate) fn scale_round(c: u64, p: u64, q: u64) -> u64 {
+    let (c, p, q) = (c as u128, p as u128, q as u128);
+    let t = (2 * c * p + q) / (2 * q);
+    (if t >= p { t - p } else { t }) as u64
+}
+
+/// Checks that every index is less than `bound`.
+fn check_indices_in_range(indices: &[usize], bound: usize) -> Result<(), OmrError> {
+    match indices.iter().find(|&&index| index >= bound) {
+        Some(&index) => Err(OmrError::IndexOutOfRange { index, bound }),
+        None => Ok(()),
+    }
+}
+
+/// [`combinations_match`] modulo `p`.
+fn combinations_match_mod(
+    p: PayloadByteType,
+    matrix: &[Vec<PayloadByteType>],
+    solved_payloads: &[Payload],
+    combined_payloads: &[Payload],
+) -> bool {
+    if matrix.len() != combined_payloads.len() {
+        return false;
+    }
+
+    if p.is_power_of_two() {
+        combinations_match(
+            matrix,
+            solved_payloads,
+            combined_payloads,
+            <PowOf2Modulus<PayloadByteType>>::new(p),
+        )
+    } else {
+        combinations_match(
+            matrix,
+            solved_payloads,
+            combined_payloads,
+            <BarrettModulus<PayloadByteType>>::new(p),
+        )
+    }
+}
+
+fn combinations_match<M: RingReduce<PayloadByteType> + Copy>(
+    matrix: &[Vec<PayloadByteType>],
+    solved_payloads: &[Payload],
+    combined_payloads: &[Payload],
+    modulus: M,
+) -> bool {
+    matrix
+        .iter()
+        .zip(combined_payloads.iter())
+        .all(|(row, combined)| {
+            let expected = row
+                .iter()
+                .zip(solved_payloads.iter())
+                .fold(Payload::new(), |acc, (&weight, payload)| {
+                    acc.add(&payload.mul_scalar(weight, modulus), modulus)
+                });
+            expected == *combined
+        })
 }
 
 pub fn sub_mul<F: NttField>(
@@ -387,6 +877,7 @@ pub fn sub_mul<F: NttField>(
 }
 
 /// Tracks decoded-noise statistics for evaluation/debugging.
+#[cfg(feature = "noise-analysis")]
 pub struct NoiseSigmaInfo<F: Field> {
     sigma: f64,
     one_sigma: <F as Field>::ValueT,
@@ -408,8 +899,12 @@ pub struct NoiseSigmaInfo<F: Field> {
     half_q: <F as Field>::ValueT,
 }
 
+#[cfg(feature = "noise-analysis")]
 impl<F: Field> NoiseSigmaInfo<F> {
-    pub fn new(sigma: f64, modulus: <F as Field>::ValueT, all_count: usize) -> Self {
+    /// Creates a new [`NoiseSigmaInfo<F>`] for noise with standard deviation `sigma`.
+    ///
+    /// The number of samples is counted by [`Self::check_noise_sigma`].
+    pub fn new(sigma: f64, modulus: <F as Field>::ValueT) -> Self {
         let one_sigma = sigma.trunc().as_into();
         let two_sigma = (sigma * 2.0).trunc().as_into();
         let three_sigma = (sigma * 3.0).trunc().as_into();
@@ -431,15 +926,22 @@ impl<F: Field> NoiseSigmaInfo<F> {
             four_sigma_count: 0,
             five_sigma_count: 0,
             six_sigma_count: 0,
-            all_count,
-            sum: BigDecimal::zero(),
-            sq_sum: BigDecimal::zero(),
+            all_count: 0,
+            sum: BigDecimal::from(0u64),
+            sq_sum: BigDecimal::from(0u64),
             q: modulus,
             half_q: modulus >> 1u32,
         }
     }
 
+    /// Returns the number of samples checked so far.
+    #[inline]
+    pub fn all_count(&self) -> usize {
+        self.all_count
+    }
+
     pub fn check_noise_sigma(&mut self, value: <F as Field>::ValueT) {
+        self.all_count += 1;
         if value <= self.half_q {
             if value <= self.one_sigma {
                 self.one_sigma_count += 1;
@@ -558,3 +1060,717 @@ impl<F: Field> NoiseSigmaInfo<F> {
         println!("-------------------------------------------------");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::test_utils::TestSetup;
+
+    thread_local! {
+        /// The number of combination ciphertexts decoded on this thread.
+        pub(super) static DECODED_CIPHERS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[test]
+    fn scale_round_rounds_half_up() {
+        let (p, q) = (257, 1_000_000);
+        // c * p / q == 0.499865 and 0.500122
+        assert_eq!(scale_round(1945, p, q), 0);
+        assert_eq!(scale_round(1946, p, q), 1);
+        // Values close to q wrap to 0.
+        assert_eq!(scale_round(q - 1, p, q), 0);
+        assert_eq!(scale_round(q / 2, p, q), 129);
+    }
+
+    #[cfg(feature = "noise-analysis")]
+    #[test]
+    fn scale_round_matches_big_decimal() {
+        use bigdecimal::RoundingMode;
+        use num_traits::ToPrimitive;
+        use rand::Rng;
+
+        use crate::SecondLevelField;
+
+        let mut rng = rand::thread_rng();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        for p in [256u64, 257] {
+            for _ in 0..1000 {
+                let c = rng.gen_range(0..q);
+                let mut t = (BigDecimal::from(c) * BigDecimal::from(p) / BigDecimal::from(q))
+                    .with_scale_round(0, RoundingMode::HalfUp);
+                if t >= BigDecimal::from(p) {
+                    t -= BigDecimal::from(p);
+                }
+                assert_eq!(scale_round(c, p, q), t.to_u64().unwrap());
+            }
+        }
+    }
+
+    #[cfg(feature = "noise-analysis")]
+    #[test]
+    fn noise_analysis_counts_every_decoded_coefficient() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let retriever = TestSetup::shared().retriever(1000, 10).unwrap();
+        let rows = (0..retriever.max_combinations())
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let combinations = combination_ciphers(&retriever, &rows);
+
+        let (payloads, info) = retriever.decode_combined_payloads_with_noise(&combinations, 1.0);
+        assert_eq!(payloads, retriever.decode_combined_payloads(&combinations));
+        assert_eq!(payloads, rows);
+        assert_eq!(info.all_count(), payloads.len() * PAYLOAD_LENGTH);
+    }
+
+    #[test]
+    fn small_prime_index_base_round_trip() {
+        use fhe_core::RlweCiphertext;
+
+        use crate::{detector::second_level_delta, PertinencyCiphertext, SecondLevelField};
+
+        let params = <RetrievalParams<SecondLevelField>>::new(257, 2048, 1000, 50, 130, 25, 2)
+            .with_index_base(3);
+        let slots_per_bucket = params.slots_per_bucket();
+
+        for index in 0..1000u64 {
+            // Same digit layout as `Detector::encode_pertinent_indices`.
+            let mut bucket = vec![0u64; slots_per_bucket];
+            let mut i = index;
+            let mut k = 0;
+            while i != 0 {
+                bucket[k] = i % 3;
+                i /= 3;
+                k += 1;
+            }
+            assert_eq!(
+                decode_bucket::<SecondLevelField>(&bucket, 3, 1000).unwrap(),
+                None
+            );
+
+            bucket[slots_per_bucket - 1] = 1;
+            assert_eq!(
+                decode_bucket::<SecondLevelField>(&bucket, 3, 1000).unwrap(),
+                Some(index as usize)
+            );
+        }
+
+        // The same layout through the detector's encoding.
+        let TestSetup {
+            secret_key,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let mut retriever = Retriever::new(
+            params,
+            Arc::clone(secret_key.second_level_ntt_table()),
+            secret_key.second_level_ntt_rlwe_secret_key().clone(),
+        );
+
+        // With `a = 0` the ciphertext decrypts to `b` under any key.
+        let delta = second_level_delta(params.index_modulus() as usize);
+        let pertinent = (0..10).map(|i| i * 99 + 7).collect::<HashSet<usize>>();
+        let pertinency_vector = (0..1000)
+            .map(|i| {
+                let mut ct = RlweCiphertext::<SecondLevelField>::zero(params.polynomial_size());
+                ct.b_mut().as_mut_slice()[0] = pertinent.contains(&i) as u64 * delta;
+                PertinencyCiphertext::from_coeff(&ct, secret_key.second_level_ntt_table())
+            })
+            .collect::<Vec<_>>();
+
+        for _ in 0..params.max_encode_indices_cipher_count() {
+            let cipher = detector.encode_pertinent_indices(params, &pertinency_vector);
+            if retriever.decode_pertinent_indices(&cipher).unwrap() {
+                break;
+            }
+        }
+        assert_eq!(retriever.pertinent_indices_set(), &pertinent);
+    }
+
+    #[test]
+    fn indicator_margin_reduces_false_insertions() {
+        use rand::Rng;
+
+        use crate::SecondLevelField;
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let p = 257u64;
+        let delta = q / p;
+        let trials = 10_000;
+
+        // Empty buckets (plaintext 0) with noise up to 0.7 delta, full buckets with
+        // noise up to 0.1 delta.
+        let noisy = |m: u64, bound: f64, rng: &mut StdRng| {
+            let e = (rng.gen_range(-bound..bound) * delta as f64) as i64;
+            ((m * delta) as i64 + e).rem_euclid(q as i64) as u64
+        };
+        let accepted = |c: u64, margin: Option<f64>| {
+            round_coefficients::<SecondLevelField>(std::iter::once(c), p)[0] == 1
+                && margin.is_none_or(|m| indicator_within_margin::<SecondLevelField>(c, p, m))
+        };
+
+        let empty = (0..trials)
+            .map(|_| noisy(0, 0.7, &mut rng))
+            .collect::<Vec<_>>();
+        let full = (0..trials)
+            .map(|_| noisy(1, 0.1, &mut rng))
+            .collect::<Vec<_>>();
+
+        let false_insertions = |margin| empty.iter().filter(|&&c| accepted(c, margin)).count();
+        let hits = |margin| full.iter().filter(|&&c| accepted(c, margin)).count();
+
+        assert!(false_insertions(None) > trials / 10);
+        assert_eq!(false_insertions(Some(0.25)), 0);
+        assert_eq!(hits(None), trials);
+        assert_eq!(hits(Some(0.25)), trials);
+    }
+
+    #[test]
+    fn too_many_indices_are_rejected() {
+        let secret_key = &TestSetup::shared().secret_key;
+        let mut retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        assert_eq!(retriever.max_indices(), 20);
+
+        // Every bucket claims a distinct index with its indicator set.
+        let cipher = index_cipher(&retriever, &(0..1000).collect::<Vec<_>>());
+
+        assert!(matches!(
+            retriever.decode_pertinent_indices(&cipher),
+            Err(OmrError::TooManyIndices { bound: 20 })
+        ));
+        assert_eq!(retriever.pertinent_indices_set().len(), 20);
+    }
+
+    #[test]
+    fn progress_resumes_in_a_fresh_retriever() {
+        let secret_key = &TestSetup::shared().secret_key;
+        let indices = [3, 17, 256, 511, 600, 742, 800, 901, 950, 999];
+
+        let mut first = secret_key.generate_retriever(1000, 10).unwrap();
+        assert!(!first
+            .decode_pertinent_indices(&index_cipher(&first, &indices[..4]))
+            .unwrap());
+        let progress = first.export_progress();
+        assert_eq!(progress, indices[..4]);
+
+        let mut resumed = secret_key.generate_retriever(1000, 10).unwrap();
+        resumed.import_progress(&progress).unwrap();
+        assert!(resumed
+            .decode_pertinent_indices(&index_cipher(&resumed, &indices[4..]))
+            .unwrap());
+        assert_eq!(resumed.export_progress(), indices);
+
+        assert!(matches!(
+            resumed.import_progress(&[1000]),
+            Err(OmrError::IndexOutOfRange {
+                index: 1000,
+                bound: 1000
+            })
+        ));
+    }
+
+    /// Builds an index ciphertext whose `k`-th bucket holds `indices[k]`, wrapping
+    /// around `indices` until every bucket is filled if there are more buckets.
+    ///
+    /// With `a = 0` the ciphertext decrypts to `b` under any key.
+    fn index_cipher(
+        retriever: &Retriever<crate::SecondLevelField>,
+        indices: &[usize],
+    ) -> NttRlwe<crate::SecondLevelField> {
+        use algebra::polynomial::FieldPolynomial;
+
+        use crate::SecondLevelField;
+
+        let params = retriever.params();
+        let n = params.polynomial_size();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let p = params.index_modulus();
+        let base = params.index_base();
+        let scale = |m: u64| ((m as u128 * q as u128 + p as u128 / 2) / p as u128) as u64;
+
+        let mut plaintext = <FieldPolynomial<SecondLevelField>>::zero(n);
+        let buckets = plaintext
+            .as_mut_slice()
+            .chunks_exact_mut(params.slots_per_segment())
+            .flat_map(|segment| segment.chunks_exact_mut(params.slots_per_bucket()));
+        for (bucket, &index) in buckets.zip(indices.iter().cycle()) {
+            let (indicator, digits) = bucket.split_last_mut().unwrap();
+            let mut index = index as u64;
+            for digit in digits {
+                *digit = scale(index % base);
+                index /= base;
+            }
+            *indicator = scale(1);
+        }
+
+        let mut cipher = <NttRlwe<SecondLevelField>>::zero(n);
+        *cipher.b_mut() = retriever.ntt_table.transform_inplace(plaintext);
+        cipher
+    }
+
+    /// Builds combination ciphertexts holding `rows`, packed as the detector packs them.
+    ///
+    /// With `a = 0` the ciphertext decrypts to `b` under any key.
+    fn combination_ciphers(
+        retriever: &Retriever<SecondLevelField>,
+        rows: &[Payload],
+    ) -> Vec<NttRlweCiphertext<SecondLevelField>> {
+        use algebra::polynomial::FieldPolynomial;
+
+        let params = retriever.params();
+        let n = params.polynomial_size();
+        let delta = params.payload_delta();
+        rows.chunks(params.cmb_count_per_cipher())
+            .map(|chunk| {
+                let mut plaintext = <FieldPolynomial<SecondLevelField>>::zero(n);
+                plaintext
+                    .as_mut_slice()
+                    .chunks_exact_mut(PAYLOAD_LENGTH)
+                    .zip(chunk)
+                    .for_each(|(slots, row)| {
+                        slots
+                            .iter_mut()
+                            .zip(row.iter())
+                            .for_each(|(slot, &byte)| *slot = byte as u64 * delta);
+                    });
+                let mut cipher = NttRlweCiphertext::<SecondLevelField>::zero(n);
+                *cipher.b_mut() = retriever.ntt_table.transform_inplace(plaintext);
+                cipher
+            })
+            .collect()
+    }
+
+    /// Returns the combined rows of `payloads` at `indices` under the weights of `seed`.
+    fn combined_rows(
+        params: RetrievalParams<SecondLevelField>,
+        indices: &[usize],
+        payloads: &[Payload],
+        seed: [u8; 32],
+    ) -> Vec<Payload> {
+        let p: PayloadByteType = params.index_modulus().as_into();
+        let modulus = <BarrettModulus<PayloadByteType>>::new(p);
+        params
+            .combination_weights(seed)
+            .chunks_exact(params.all_payloads_count())
+            .map(|row| {
+                let row = indices.iter().map(|&i| row[i]).collect::<Vec<_>>();
+                Payload::combine(payloads, &row, modulus)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn many_recipients_decode_in_one_call() {
+        use crate::{KeyGen, OmrParameters};
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let seed = [3u8; 32];
+        let recipients = [
+            (0..10).map(|i| i * 97).collect::<Vec<_>>(),
+            (0..10).map(|i| i * 89 + 5).collect::<Vec<_>>(),
+        ];
+
+        let mut retrievers = Vec::new();
+        let mut digests = Vec::new();
+        let mut expected = Vec::new();
+        for indices in &recipients {
+            let secret_key = KeyGen::generate_secret_key(OmrParameters::new(), &mut rng);
+            let mut retriever = secret_key.generate_retriever(1000, 10).unwrap();
+            // Left over from an earlier digest, must not show up in this one.
+            retriever.import_progress(&[999]).unwrap();
+
+            let payloads = (0..10)
+                .map(|_| Payload::random(&mut rng))
+                .collect::<Vec<_>>();
+            let rows = combined_rows(retriever.params(), indices, &payloads, seed);
+            digests.push(Digest {
+                indices: vec![index_cipher(&retriever, indices)],
+                combinations: combination_ciphers(&retriever, &rows),
+            });
+            retrievers.push(retriever);
+            expected.push((indices.clone(), payloads));
+        }
+
+        let results = retrieve_many(&mut retrievers, &digests, seed);
+        assert_eq!(results.len(), 2);
+        for (result, expected) in results.into_iter().zip(expected) {
+            assert_eq!(result.unwrap(), expected);
+        }
+    }
+
+    /// Builds the digest of `payloads` at `indices`, laid out like the detector's.
+    fn digest_of(
+        retriever: &Retriever<SecondLevelField>,
+        indices: &[usize],
+        payloads: &[Payload],
+        seed: [u8; 32],
+    ) -> Digest<SecondLevelField> {
+        let rows = combined_rows(retriever.params(), indices, payloads, seed);
+        Digest {
+            indices: vec![index_cipher(retriever, indices)],
+            combinations: combination_ciphers(retriever, &rows),
+        }
+    }
+
+    #[test]
+    fn fed_combinations_solve_like_a_batch_decode() {
+        let mut rng = StdRng::seed_from_u64(16);
+        let secret_key = &TestSetup::shared().secret_key;
+        let retriever = secret_key.generate_retriever(1000, 10).unwrap();
+
+        let seed = [5u8; 32];
+        let indices = (0..10).map(|i| i * 61 + 3).collect::<Vec<_>>();
+        let payloads = (0..10)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let digest = digest_of(&retriever, &indices, &payloads, seed);
+
+        let batch = retriever
+            .clone()
+            .decode_digest(&digest.indices, &digest.combinations, seed)
+            .unwrap();
+        assert_eq!(batch, (indices, payloads));
+
+        let mut streaming = retriever;
+        assert!(streaming
+            .decode_pertinent_indices(&digest.indices[0])
+            .unwrap());
+        for cipher in &digest.combinations {
+            streaming.feed_combination(cipher);
+        }
+        assert_eq!(streaming.solve(seed).unwrap(), batch);
+        assert_eq!(streaming.fed_combination_count(), 0);
+    }
+
+    #[test]
+    fn self_check_rejects_a_wrong_payload() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let secret_key = &TestSetup::shared().secret_key;
+        let mut retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        let p: PayloadByteType = retriever.params().index_modulus().as_into();
+
+        let seed = [6u8; 32];
+        let indices = (0..10).map(|i| i * 53 + 1).collect::<Vec<_>>();
+        let payloads = (0..10)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let digest = digest_of(&retriever, &indices, &payloads, seed);
+
+        let (indices, solved) = retriever
+            .decode_digest(&digest.indices, &digest.combinations, seed)
+            .unwrap();
+        assert!(retriever.self_check(&indices, &solved, &digest.combinations, seed));
+
+        let mut wrong = solved.clone();
+        wrong[3].0[0] = (wrong[3].0[0] + 1) % p;
+        assert!(!retriever.self_check(&indices, &wrong, &digest.combinations, seed));
+        assert!(!retriever.self_check(&indices, &solved[1..], &digest.combinations, seed));
+    }
+
+    #[test]
+    fn matching_retriever_decodes_the_detector_layout() {
+        let mut rng = StdRng::seed_from_u64(18);
+        let secret_key = &TestSetup::shared().secret_key;
+        let detector_side = secret_key.generate_retriever(1000, 10).unwrap();
+        let params = detector_side.params();
+        let (ntt_table, key) = (detector_side.ntt_table.clone(), detector_side.key.clone());
+
+        let seed = [8u8; 32];
+        let indices = (0..10).map(|i| i * 71 + 2).collect::<Vec<_>>();
+        let payloads = (0..10)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let digest = digest_of(&detector_side, &indices, &payloads, seed);
+
+        let mut bytes = params.fingerprint().to_bytes();
+        let mut retriever =
+            Retriever::new_matching(&bytes, ntt_table.clone(), key.clone()).unwrap();
+        assert_eq!(retriever.params().fingerprint(), params.fingerprint());
+        assert_eq!(
+            retriever
+                .decode_digest(&digest.indices, &digest.combinations, seed)
+                .unwrap(),
+            (indices, payloads)
+        );
+
+        // A layout for another ring dimension doesn't fit the key.
+        let other = <RetrievalParams<SecondLevelField>>::new(256, 4096, 1000, 10, 25, 25, 2);
+        assert!(matches!(
+            Retriever::new_matching(
+                &other.fingerprint().to_bytes(),
+                ntt_table.clone(),
+                key.clone()
+            ),
+            Err(OmrError::DimensionMismatch { .. })
+        ));
+
+        bytes[8] ^= 1;
+        assert!(matches!(
+            Retriever::new_matching(&bytes, ntt_table, key),
+            Err(OmrError::ParameterMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn validation_rows_flag_a_corrupted_combination() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let secret_key = &TestSetup::shared().secret_key;
+        let mut retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        let params = retriever.params();
+        let p: PayloadByteType = params.index_modulus().as_into();
+
+        let indices = (0..10).map(|i| i * 97).collect::<Vec<_>>();
+        let index_ciphers = [index_cipher(&retriever, &indices)];
+        let payloads = (0..10)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+
+        let seed = [9u8; 32];
+        let mut rows = combined_rows(params, &indices, &payloads, seed);
+        // A decode error in the first row, which the solve uses.
+        rows[0].0[5] = (rows[0].0[5] + 1) % p;
+
+        let combinations = combination_ciphers(&retriever, &rows);
+
+        let (decoded, validated) = retriever
+            .retrieve_validated(&index_ciphers, &combinations, seed)
+            .unwrap();
+        assert_eq!(decoded, indices);
+        assert!(validated
+            .iter()
+            .zip(&payloads)
+            .filter(|((solved, _), source)| solved != *source)
+            .all(|((_, consistent), _)| !consistent));
+        assert!(validated.iter().any(|(_, consistent)| !consistent));
+    }
+
+    #[test]
+    fn combinations_beyond_the_cap_are_not_decoded() {
+        use crate::SecondLevelField;
+
+        let secret_key = &TestSetup::shared().secret_key;
+        let retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        assert_eq!(retriever.max_combinations(), 15);
+
+        let per_cipher = retriever.params().cmb_count_per_cipher();
+        let n = retriever.params().polynomial_size();
+        let combinations = vec![NttRlweCiphertext::<SecondLevelField>::zero(n); 20];
+
+        // On a single thread pool, the decoding runs on the thread reading the counter.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let decoded = |retriever: &Retriever<SecondLevelField>| {
+            pool.install(|| {
+                let before = DECODED_CIPHERS.with(Cell::get);
+                let len = retriever.decode_combined_payloads(&combinations).len();
+                (len, DECODED_CIPHERS.with(Cell::get) - before)
+            })
+        };
+        assert_eq!(decoded(&retriever), (15, 15usize.div_ceil(per_cipher)));
+
+        let mut retriever = retriever.with_max_combinations(12);
+        assert_eq!(decoded(&retriever), (12, 12usize.div_ceil(per_cipher)));
+
+        let before = DECODED_CIPHERS.with(Cell::get);
+        for cipher in &combinations {
+            retriever.feed_combination(cipher);
+        }
+        assert_eq!(retriever.fed_combination_count(), 12);
+        assert_eq!(
+            DECODED_CIPHERS.with(Cell::get) - before,
+            12usize.div_ceil(per_cipher)
+        );
+    }
+
+    #[test]
+    fn packed_segments_still_retrieve() {
+        use crate::SecondLevelField;
+
+        let secret_key = &TestSetup::shared().secret_key;
+        let params = RetrievalParams::<SecondLevelField>::try_with_segment_per_cipher(
+            257, 2048, 16, 4, 8, 8, 2,
+        )
+        .unwrap();
+        assert_eq!(params.segment_per_cipher(), 8);
+        assert_eq!(params.bucket_count_per_segment(), 128);
+        assert!(
+            RetrievalParams::<SecondLevelField>::try_with_segment_per_cipher(
+                257, 2048, 16, 4, 2048, 8, 2
+            )
+            .is_err()
+        );
+
+        let mut retriever = Retriever::new(
+            params,
+            Arc::clone(secret_key.second_level_ntt_table()),
+            secret_key.second_level_ntt_rlwe_secret_key().clone(),
+        );
+        let cipher = index_cipher(&retriever, &[1, 5, 9, 12]);
+        assert!(retriever.decode_pertinent_indices(&cipher).unwrap());
+        assert_eq!(retriever.sorted_pertinent_indices(), [1, 5, 9, 12]);
+    }
+
+    #[test]
+    fn debug_decode_shows_shared_buckets() {
+        let secret_key = &TestSetup::shared().secret_key;
+        let retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        let bucket_count = retriever.params().polynomial_size()
+            / retriever.params().slots_per_segment()
+            * retriever.params().bucket_count_per_segment();
+
+        let single = index_cipher(&retriever, &[3]);
+        let pairs = retriever.decode_pertinent_indices_debug(&single).unwrap();
+        assert_eq!(pairs.len(), bucket_count);
+        assert!(pairs.iter().all(|&(_, index)| index == Some(3)));
+
+        // Messages 3 and 5 land in every bucket together.
+        let collided = single.add_element_wise(&index_cipher(&retriever, &[5]));
+        let pairs = retriever.decode_pertinent_indices_debug(&collided).unwrap();
+        assert_eq!(pairs.len(), 2 * bucket_count);
+        assert_eq!(pairs[0], (0, None));
+        assert_eq!(pairs[1], (0, None));
+    }
+
+    #[test]
+    fn spurious_high_digit_is_rejected() {
+        use crate::SecondLevelField;
+
+        // Index 5 of a 1000 message board in base 257: digits [5, 0], then the indicator.
+        let mut bucket = [5u64, 0, 1];
+        assert_eq!(
+            decode_bucket::<SecondLevelField>(&bucket, 257, 1000).unwrap(),
+            Some(5)
+        );
+
+        // A noisy high digit gives 3 * 257 + 5 = 776, still a valid index.
+        bucket[1] = 3;
+        assert_eq!(
+            decode_bucket::<SecondLevelField>(&bucket, 257, 1000).unwrap(),
+            Some(776)
+        );
+
+        // 4 * 257 + 5 = 1033 is past the board.
+        bucket[1] = 4;
+        assert_eq!(
+            decode_bucket::<SecondLevelField>(&bucket, 257, 1000).unwrap(),
+            None
+        );
+
+        // A digit outside the base can't come from the encoder.
+        let bucket = [300u64, 0, 1];
+        assert_eq!(
+            decode_bucket::<SecondLevelField>(&bucket, 257, 1000).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn oversized_bucket_overflows() {
+        use crate::SecondLevelField;
+
+        // 257^10 doesn't fit in 64 bits.
+        let mut bucket = [256u64; 11];
+        bucket[10] = 1;
+        assert!(matches!(
+            decode_bucket::<SecondLevelField>(&bucket, 257, 1000),
+            Err(OmrError::DecodeOverflow)
+        ));
+
+        // Neither do 17 base-16 digits.
+        let mut bucket = [15u64; 18];
+        bucket[17] = 1;
+        assert!(matches!(
+            decode_bucket::<SecondLevelField>(&bucket, 16, 1000),
+            Err(OmrError::DecodeOverflow)
+        ));
+    }
+
+    #[test]
+    fn solution_is_verified_against_decoded_rhs() {
+        let mut rng = rand::thread_rng();
+        let modulus = <PowOf2Modulus<PayloadByteType>>::new(256);
+
+        let solved = (0..3)
+            .map(|_| Payload::random(&mut rng))
+            .collect::<Vec<_>>();
+        let matrix = vec![
+            vec![1, 2, 3],
+            vec![250, 0, 7],
+            vec![9, 9, 9],
+            vec![0, 1, 255],
+        ];
+        let rhs = matrix
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .zip(solved.iter())
+                    .fold(Payload::new(), |acc, (&w, payload)| {
+                        acc.add(&payload.mul_scalar(w, modulus), modulus)
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        assert!(combinations_match_mod(256, &matrix, &solved, &rhs));
+
+        let mut tampered = rhs.clone();
+        tampered[1].0[100] ^= 1;
+        assert!(!combinations_match_mod(256, &matrix, &solved, &tampered));
+        assert!(!combinations_match_mod(256, &matrix, &solved, &rhs[..3]));
+    }
+
+    #[test]
+    fn indicator_slots_round_to_one() {
+        use crate::SecondLevelField;
+
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let p = 257u64;
+        let delta = q / p;
+
+        // Two buckets of 3 slots, only the second one is filled with index 2 * 257 + 5.
+        let slots = [0, 0, 0, 5, 2, 1];
+        let noise = [3, q - 7, 100, q - 2000, 42, q - 1];
+        let coefficients = slots
+            .iter()
+            .zip(noise.iter())
+            .map(|(&v, &e)| (v * delta + e) % q);
+
+        let decoded = round_coefficients::<SecondLevelField>(coefficients, p);
+        assert_eq!(decoded, slots);
+        assert_eq!(decoded[2], 0);
+        assert_eq!(decoded[5], 1);
+
+        // Every bucket of the helper ciphertext holds index 2 * 257 + 5.
+        let secret_key = &TestSetup::shared().secret_key;
+        let retriever = secret_key.generate_retriever(1000, 10).unwrap();
+        let params = retriever.params();
+        assert_eq!(params.slots_per_bucket(), 3);
+
+        let decoded =
+            retriever.decode_index_coefficients(&index_cipher(&retriever, &[2 * 257 + 5]));
+        let (segments, unused) =
+            decoded.split_at(params.segment_per_cipher() * params.slots_per_segment());
+        for bucket in segments.chunks_exact(params.slots_per_bucket()) {
+            assert_eq!(bucket, [5, 2, 1]);
+        }
+        // Slots past the last segment stay empty.
+        assert!(unused.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn out_of_range_index_is_rejected() {
+        assert!(check_indices_in_range(&[0, 3, 63], 64).is_ok());
+        assert!(matches!(
+            check_indices_in_range(&[0, 64, 3], 64),
+            Err(OmrError::IndexOutOfRange {
+                index: 64,
+                bound: 64
+            })
+        ));
+    }
+}
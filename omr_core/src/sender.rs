@@ -4,24 +4,34 @@ use algebra::utils::Size;
 use fhe_core::CmLweCiphertext;
 use rand::{CryptoRng, Rng};
 
-use crate::{ClueKey, ClueValue};
+use crate::{ClueKey, ClueValue, OmrError, OmrParameters};
 
 /// Sender-side helper that produces encrypted clue strings.
 pub struct Sender {
     clue_key: ClueKey,
     clue_count: usize,
+    fingerprint: u64,
 }
 
 impl Sender {
     /// Creates a new [`Sender`].
     #[inline]
     pub fn new(clue_key: ClueKey, clue_count: usize) -> Self {
+        debug_assert!(clue_count > 0, "Clue count must be positive.");
         Self {
             clue_key,
             clue_count,
+            fingerprint: 0,
         }
     }
 
+    /// Sets the [`OmrParameters::fingerprint`] attached by [`Sender::gen_tagged_clues`].
+    #[inline]
+    pub fn with_fingerprint(mut self, fingerprint: u64) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
     /// Generates clues.
     #[inline]
     pub fn gen_clues<R>(&self, rng: &mut R) -> CmLweCiphertext<ClueValue>
@@ -31,9 +41,253 @@ impl Sender {
         self.clue_key.gen_clues(self.clue_count, rng)
     }
 
+    /// Generates clues and checks their shape and clue count before returning them.
+    ///
+    /// The sender cannot decrypt, but a malformed clue key shows up as a bundle of
+    /// the wrong shape, see [`ClueKey::check_clues`].
+    #[inline]
+    pub fn gen_clues_checked<R>(&self, rng: &mut R) -> Result<CmLweCiphertext<ClueValue>, OmrError>
+    where
+        R: Rng + CryptoRng,
+    {
+        let clues = self.gen_clues(rng);
+        self.clue_key.check_clues(&clues, self.clue_count)?;
+        Ok(clues)
+    }
+
+    /// Generates clues tagged with the parameter fingerprint of this [`Sender`].
+    #[inline]
+    pub fn gen_tagged_clues<R>(&self, rng: &mut R) -> TaggedClues
+    where
+        R: Rng + CryptoRng,
+    {
+        TaggedClues {
+            fingerprint: self.fingerprint,
+            clues: self.gen_clues(rng),
+        }
+    }
+
+    /// Returns the size of the clue key.
+    #[inline]
+    pub fn clue_key_size(&self) -> usize {
+        self.clue_key.size()
+    }
+
+    /// Returns the parameter fingerprint of this [`Sender`].
+    #[inline]
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Returns the clue count of this [`Sender`].
+    #[inline]
+    pub fn clue_count(&self) -> usize {
+        self.clue_count
+    }
+
+    /// Converts this [`Sender`] into a [`FixedSender`] with clue count `C`.
+    #[inline]
+    pub fn into_fixed<const C: usize>(self) -> Result<FixedSender<C>, OmrError> {
+        if self.clue_count != C {
+            return Err(OmrError::ClueCountMismatch {
+                expected: C,
+                found: self.clue_count,
+            });
+        }
+        Ok(FixedSender::new(self.clue_key))
+    }
+}
+
+/// Appends clues of `sender_dummy` to `clues` until the board size is a power of two.
+///
+/// With a power-of-two index modulus, the index digits of a bucket are sized for
+/// `all_payloads_count.next_power_of_two()`, so the padded board costs no extra index
+/// slots and every digit pattern of the layout names a real message. The dummy
+/// messages still cost one detection each, and with other index moduli the digits
+/// are sized for the exact board size, where padding only adds work.
+///
+/// `sender_dummy` should encrypt under a key no retriever holds, so the dummies are
+/// never pertinent.
+pub fn pad_board_to_pow2<R>(
+    clues: &mut Vec<CmLweCiphertext<ClueValue>>,
+    sender_dummy: &Sender,
+    rng: &mut R,
+) where
+    R: Rng + CryptoRng,
+{
+    let padded_len = clues.len().next_power_of_two();
+    clues.extend((clues.len()..padded_len).map(|_| sender_dummy.gen_clues(rng)));
+}
+
+/// Sender whose clue count `C` is part of its type.
+///
+/// Its clues can only be detected by a [`FixedDetector`](crate::FixedDetector)
+/// with the same `C`, mixing different counts is a type error:
+///
+/// ```compile_fail
+/// use omr_core::{FixedClues, FixedDetector};
+///
+/// fn mix(detector: &FixedDetector<'_, 5>, clues: &FixedClues<7>) {
+///     detector.detect(clues);
+/// }
+/// ```
+pub struct FixedSender<const C: usize> {
+    clue_key: ClueKey,
+}
+
+impl<const C: usize> FixedSender<C> {
+    /// Creates a new [`FixedSender<C>`].
+    #[inline]
+    pub fn new(clue_key: ClueKey) -> Self {
+        Self { clue_key }
+    }
+
+    /// Generates `C` clues.
+    #[inline]
+    pub fn gen_clues<R>(&self, rng: &mut R) -> FixedClues<C>
+    where
+        R: Rng + CryptoRng,
+    {
+        FixedClues(self.clue_key.gen_clues(C, rng))
+    }
+
     /// Returns the size of the clue key.
     #[inline]
     pub fn clue_key_size(&self) -> usize {
         self.clue_key.size()
     }
 }
+
+/// A clue bundle which contains exactly `C` clues.
+pub struct FixedClues<const C: usize>(CmLweCiphertext<ClueValue>);
+
+impl<const C: usize> FixedClues<C> {
+    /// Returns a reference to the inner clue bundle.
+    #[inline]
+    pub fn as_clues(&self) -> &CmLweCiphertext<ClueValue> {
+        &self.0
+    }
+
+    /// Returns the inner clue bundle.
+    #[inline]
+    pub fn into_inner(self) -> CmLweCiphertext<ClueValue> {
+        self.0
+    }
+}
+
+/// A clue bundle tagged with the [`OmrParameters::fingerprint`] it was generated under.
+pub struct TaggedClues {
+    fingerprint: u64,
+    clues: CmLweCiphertext<ClueValue>,
+}
+
+impl TaggedClues {
+    /// Creates a new [`TaggedClues`].
+    #[inline]
+    pub fn new(fingerprint: u64, clues: CmLweCiphertext<ClueValue>) -> Self {
+        Self { fingerprint, clues }
+    }
+
+    /// Returns the parameter fingerprint of this [`TaggedClues`].
+    #[inline]
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Returns a reference to the inner clue bundle.
+    #[inline]
+    pub fn as_clues(&self) -> &CmLweCiphertext<ClueValue> {
+        &self.clues
+    }
+
+    /// Returns the inner clue bundle.
+    #[inline]
+    pub fn into_inner(self) -> CmLweCiphertext<ClueValue> {
+        self.clues
+    }
+
+    /// Returns the inner clue bundle if it was generated under `params`.
+    pub fn verify(&self, params: &OmrParameters) -> Result<&CmLweCiphertext<ClueValue>, OmrError> {
+        let expected = params.fingerprint();
+        if self.fingerprint != expected {
+            return Err(OmrError::ParameterMismatch {
+                expected,
+                found: self.fingerprint,
+            });
+        }
+        Ok(&self.clues)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{test_utils::TestSetup, KeyGen};
+
+    #[test]
+    fn tagged_clues_from_other_parameters_are_rejected() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let params = OmrParameters::new();
+        let other = params.clone().with_clue_count(5).unwrap();
+        assert_ne!(params.fingerprint(), other.fingerprint());
+
+        let TestSetup {
+            sender, detector, ..
+        } = TestSetup::shared();
+        let foreign_sender =
+            KeyGen::generate_secret_key(other.clone(), &mut rng).generate_sender(&mut rng);
+
+        assert!(detector
+            .try_detect(&sender.gen_tagged_clues(&mut rng))
+            .is_ok());
+        assert!(matches!(
+            detector.try_detect(&foreign_sender.gen_tagged_clues(&mut rng)),
+            Err(OmrError::ParameterMismatch { expected, found })
+                if expected == params.fingerprint() && found == other.fingerprint()
+        ));
+    }
+
+    #[test]
+    fn padded_board_retrieves_the_same_indices() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let TestSetup {
+            secret_key,
+            sender,
+            detector,
+            stranger: dummy,
+        } = TestSetup::shared();
+
+        let mut clues = (0..5)
+            .map(|i| {
+                if i == 1 || i == 3 {
+                    sender.gen_clues(&mut rng)
+                } else {
+                    dummy.gen_clues(&mut rng)
+                }
+            })
+            .collect::<Vec<_>>();
+        pad_board_to_pow2(&mut clues, dummy, &mut rng);
+        assert_eq!(clues.len(), 8);
+
+        let pertinency_vector = detector.detect_batch(&clues);
+        let retrieve = |board: &[_]| {
+            let mut retriever = secret_key.generate_retriever(board.len(), 2).unwrap();
+            let retrieval_params = retriever.params();
+            for _ in 0..retrieval_params.max_encode_indices_cipher_count() {
+                let cipher = detector.encode_pertinent_indices(retrieval_params, board);
+                if retriever.decode_pertinent_indices(&cipher).unwrap() {
+                    break;
+                }
+            }
+            retriever.pertinent_indices_set().clone()
+        };
+
+        let expected = HashSet::from([1, 3]);
+        assert_eq!(retrieve(&pertinency_vector[..5]), expected);
+        assert_eq!(retrieve(&pertinency_vector), expected);
+    }
+}
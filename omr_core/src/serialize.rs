@@ -0,0 +1,217 @@
+//! Wire format of the digest combination ciphertexts.
+
+use algebra::Field;
+use fhe_core::NttRlweCiphertext;
+
+use crate::{
+    OmrError, OutputValue, RetrievalParams, RetrievalParamsFingerprint, SecondLevelField,
+    RETRIEVAL_FINGERPRINT_BYTES,
+};
+
+const VALUE_BYTES: usize = std::mem::size_of::<OutputValue>();
+
+/// Serializes combination ciphertexts built with `params`: the fingerprint of
+/// `params`, then the little-endian coefficients of `a` followed by `b`, one
+/// ciphertext after another.
+///
+/// The fingerprint lets the retriever rebuild the layout with
+/// [`Retriever::from_fingerprint`](crate::Retriever::from_fingerprint).
+pub fn serialize_combinations(
+    params: &RetrievalParams<SecondLevelField>,
+    combinations: &[NttRlweCiphertext<SecondLevelField>],
+) -> Vec<u8> {
+    let polynomial_size = params.polynomial_size();
+
+    let mut bytes = Vec::with_capacity(
+        RETRIEVAL_FINGERPRINT_BYTES + combinations.len() * 2 * polynomial_size * VALUE_BYTES,
+    );
+    bytes.extend_from_slice(&params.fingerprint().to_bytes());
+    for cipher in combinations {
+        for value in cipher.a().iter().chain(cipher.b().iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Deserializes the fingerprint and the combination ciphertexts written by
+/// [`serialize_combinations`].
+///
+/// The fingerprint must rebuild valid [`RetrievalParams`], which fix the number and
+/// the dimension of the ciphertexts. Coefficients not reduced modulo the second level
+/// modulus are rejected with [`OmrError::UnreducedValue`].
+pub fn deserialize_combinations(
+    bytes: &[u8],
+) -> Result<
+    (
+        RetrievalParamsFingerprint,
+        Vec<NttRlweCiphertext<SecondLevelField>>,
+    ),
+    OmrError,
+> {
+    if bytes.len() < RETRIEVAL_FINGERPRINT_BYTES {
+        return Err(OmrError::InvalidLength {
+            expected: RETRIEVAL_FINGERPRINT_BYTES,
+            found: bytes.len(),
+        });
+    }
+    let (fingerprint_bytes, bytes) = bytes.split_at(RETRIEVAL_FINGERPRINT_BYTES);
+    let fingerprint = RetrievalParamsFingerprint::from_bytes(fingerprint_bytes)?;
+    let params = RetrievalParams::<SecondLevelField>::from_fingerprint(&fingerprint)?;
+
+    let polynomial_size = params.polynomial_size();
+    let expected = polynomial_size
+        .checked_mul(2 * VALUE_BYTES)
+        .and_then(|cipher_bytes| cipher_bytes.checked_mul(params.combination_cipher_count()))
+        .ok_or(OmrError::InvalidParameters {
+            reason: "the combination ciphertexts overflow the address space",
+        })?;
+    if bytes.len() != expected {
+        return Err(OmrError::InvalidLength {
+            expected: RETRIEVAL_FINGERPRINT_BYTES + expected,
+            found: RETRIEVAL_FINGERPRINT_BYTES + bytes.len(),
+        });
+    }
+
+    let combinations = bytes
+        .chunks_exact(2 * polynomial_size * VALUE_BYTES)
+        .map(|chunk| {
+            let (a_bytes, b_bytes) = chunk.split_at(polynomial_size * VALUE_BYTES);
+            let mut cipher = <NttRlweCiphertext<SecondLevelField>>::zero(polynomial_size);
+            read_values(a_bytes, cipher.a_mut().as_mut_slice())?;
+            read_values(b_bytes, cipher.b_mut().as_mut_slice())?;
+            Ok(cipher)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((fingerprint, combinations))
+}
+
+fn read_values(
+    bytes: &[u8],
+    values: &mut [<SecondLevelField as Field>::ValueT],
+) -> Result<(), OmrError> {
+    let modulus = SecondLevelField::MODULUS_VALUE;
+    for (value, chunk) in values.iter_mut().zip(bytes.chunks_exact(VALUE_BYTES)) {
+        *value = OutputValue::from_le_bytes(chunk.try_into().unwrap());
+        if *value >= modulus {
+            return Err(OmrError::UnreducedValue {
+                value: *value,
+                modulus,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combinations_round_trip() {
+        // 6 combinations, one per ciphertext of 1024 slots.
+        let params =
+            RetrievalParams::<SecondLevelField>::try_new(257, 1024, 4, 1, 2, 1, 1).unwrap();
+        let polynomial_size = params.polynomial_size();
+        assert_eq!(params.combination_cipher_count(), 6);
+        let combinations = (0..6u64)
+            .map(|i| {
+                let mut cipher = <NttRlweCiphertext<SecondLevelField>>::zero(polynomial_size);
+                for (j, v) in cipher.a_mut().iter_mut().enumerate() {
+                    *v = i * 1000 + j as u64;
+                }
+                for (j, v) in cipher.b_mut().iter_mut().enumerate() {
+                    *v = SecondLevelField::MODULUS_VALUE - 1 - i * 1000 - j as u64;
+                }
+                cipher
+            })
+            .collect::<Vec<_>>();
+
+        let bytes = serialize_combinations(&params, &combinations);
+        let (fingerprint, decoded) = deserialize_combinations(&bytes).unwrap();
+
+        assert_eq!(fingerprint, params.fingerprint());
+        assert_eq!(decoded.len(), combinations.len());
+        for (x, y) in decoded.iter().zip(combinations.iter()) {
+            assert_eq!(x.a().as_slice(), y.a().as_slice());
+            assert_eq!(x.b().as_slice(), y.b().as_slice());
+        }
+
+        // One ciphertext short, or no fingerprint at all.
+        assert!(matches!(
+            deserialize_combinations(&serialize_combinations(&params, &combinations[1..])),
+            Err(OmrError::InvalidLength { .. })
+        ));
+        assert!(matches!(
+            deserialize_combinations(&bytes[..RETRIEVAL_FINGERPRINT_BYTES - 1]),
+            Err(OmrError::InvalidLength { .. })
+        ));
+
+        // A fingerprint whose layout does not fit its ring is rejected before any
+        // ciphertext is read.
+        let mut cramped = params.fingerprint().to_bytes();
+        cramped[16..24].copy_from_slice(&512u64.to_le_bytes());
+        let checksum = crate::parameters::fnv1a(cramped[..64].iter().copied());
+        cramped[64..].copy_from_slice(&checksum.to_le_bytes());
+        assert!(matches!(
+            deserialize_combinations(&cramped),
+            Err(OmrError::InvalidParameters { .. })
+        ));
+
+        // The last coefficient of `b` set to the modulus is not reduced.
+        let mut unreduced = bytes.clone();
+        let last = unreduced.len() - VALUE_BYTES;
+        unreduced[last..].copy_from_slice(&SecondLevelField::MODULUS_VALUE.to_le_bytes());
+        assert!(matches!(
+            deserialize_combinations(&unreduced),
+            Err(OmrError::UnreducedValue { .. })
+        ));
+    }
+
+    #[test]
+    fn deserialized_combinations_solve_to_the_payloads() {
+        use std::sync::Arc;
+
+        use rand::{rngs::StdRng, SeedableRng};
+
+        use crate::{test_utils::TestSetup, OmrContext, Payload, Retriever};
+
+        let TestSetup {
+            secret_key,
+            sender,
+            detector,
+            ..
+        } = TestSetup::shared();
+        let mut context = OmrContext::new(StdRng::seed_from_u64(61));
+        let retrieval_params = secret_key.generate_retriever(4, 4).unwrap().params();
+
+        let clues = (0..4)
+            .map(|_| context.gen_clues(sender))
+            .collect::<Vec<_>>();
+        let payloads = (0..4)
+            .map(|_| Payload::random(context.rng_mut()))
+            .collect::<Vec<_>>();
+        let pertinency_vector = detector.detect_batch(&clues);
+        let indices = detector.encode_pertinent_indices(retrieval_params, &pertinency_vector);
+        let (seed, combinations) = context.encode_pertinent_payloads(
+            detector,
+            retrieval_params,
+            &pertinency_vector,
+            &payloads,
+        );
+
+        // The retriever rebuilds the layout from the serialized fingerprint alone.
+        let bytes = serialize_combinations(&retrieval_params, &combinations);
+        let (fingerprint, decoded) = deserialize_combinations(&bytes).unwrap();
+        let mut retriever = Retriever::from_fingerprint(
+            &fingerprint,
+            Arc::clone(secret_key.second_level_ntt_table()),
+            secret_key.second_level_ntt_rlwe_secret_key().clone(),
+        )
+        .unwrap();
+
+        let (solved_indices, solved) = retriever.decode_digest(&[indices], &decoded, seed).unwrap();
+        assert_eq!(solved_indices, [0, 1, 2, 3]);
+        assert_eq!(solved, payloads);
+    }
+}
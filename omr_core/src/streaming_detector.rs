@@ -0,0 +1,137 @@
+//! Detection over a sliding window of a clue stream.
+
+use std::collections::VecDeque;
+
+use fhe_core::CmLweCiphertext;
+use lattice::NttRlwe;
+
+use crate::{ClueValue, Detector, PertinencyCiphertext, RetrievalParams, SecondLevelField};
+
+/// Wraps a [`Detector`] for a relay receiving clues as a stream, keeping the
+/// pertinency ciphertexts of the latest `window_size` messages.
+pub struct StreamingDetector {
+    detector: Detector,
+    window: VecDeque<PertinencyCiphertext>,
+    window_size: usize,
+    pushed: usize,
+}
+
+impl StreamingDetector {
+    /// Creates a new [`StreamingDetector`] with an empty window of `window_size` messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_size` is zero.
+    pub fn new(detector: Detector, window_size: usize) -> Self {
+        assert!(window_size > 0, "Window size must be positive.");
+        Self {
+            detector,
+            window: VecDeque::with_capacity(window_size),
+            window_size,
+            pushed: 0,
+        }
+    }
+
+    /// Returns a reference to the inner [`Detector`].
+    #[inline]
+    pub fn detector(&self) -> &Detector {
+        &self.detector
+    }
+
+    /// Returns the number of messages currently in the window.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Returns whether no message has been pushed yet.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    /// Returns the stream position of the oldest message in the window.
+    ///
+    /// Index `i` decoded from [`StreamingDetector::compress_window`] is the message
+    /// at stream position `window_start() + i`.
+    #[inline]
+    pub fn window_start(&self) -> usize {
+        self.pushed - self.window.len()
+    }
+
+    /// Detects `clues` and appends the result to the window, evicting the oldest
+    /// message once the window is full.
+    #[inline]
+    pub fn push(&mut self, clues: &CmLweCiphertext<ClueValue>) {
+        let pertinency = self.detector.detect(clues);
+        self.push_detected(pertinency);
+    }
+
+    /// Appends an already detected message to the window, like [`StreamingDetector::push`].
+    pub fn push_detected(&mut self, pertinency: PertinencyCiphertext) {
+        if self.window.len() == self.window_size {
+            self.window.pop_front();
+        }
+        self.window.push_back(pertinency);
+        self.pushed += 1;
+    }
+
+    /// Encodes the indices of the pertinent messages in the current window, see
+    /// [`Detector::encode_pertinent_indices`].
+    ///
+    /// `retrieval_params` should be sized for a board of the window size.
+    pub fn compress_window(
+        &self,
+        retrieval_params: RetrievalParams<SecondLevelField>,
+    ) -> NttRlwe<SecondLevelField> {
+        let (front, back) = self.window.as_slices();
+        if back.is_empty() {
+            return self
+                .detector
+                .encode_pertinent_indices(retrieval_params, front);
+        }
+        let window = self.window.iter().cloned().collect::<Vec<_>>();
+        self.detector
+            .encode_pertinent_indices(retrieval_params, &window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use algebra::{ntt::NttTable, Field};
+    use fhe_core::RlweCiphertext;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{KeyGen, OmrParameters};
+
+    #[test]
+    fn only_the_latest_messages_contribute() {
+        let mut rng = StdRng::seed_from_u64(25);
+        let secret_key = KeyGen::generate_secret_key(OmrParameters::new(), &mut rng);
+        let ntt_table = secret_key.second_level_ntt_table();
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let delta = q / secret_key.parameters().output_plain_modulus_value();
+
+        // With `a = 0` the ciphertext decrypts to `b` under any key.
+        let indicator = |m: u64| {
+            let mut ct = RlweCiphertext::<SecondLevelField>::zero(ntt_table.dimension());
+            ct.b_mut().as_mut_slice()[0] = m * delta;
+            PertinencyCiphertext::from_coeff(&ct, ntt_table)
+        };
+
+        let mut streaming = StreamingDetector::new(secret_key.generate_detector(&mut rng), 3);
+        for m in [1, 0, 1, 0, 0, 1] {
+            streaming.push_detected(indicator(m));
+        }
+        assert_eq!(streaming.len(), 3);
+        assert_eq!(streaming.window_start(), 3);
+
+        let mut retriever = secret_key.generate_retriever(3, 1).unwrap();
+        let cipher = streaming.compress_window(retriever.params());
+        assert!(retriever.decode_pertinent_indices(&cipher).unwrap());
+        assert_eq!(retriever.pertinent_indices_set(), &HashSet::from([2]));
+    }
+}
@@ -0,0 +1,166 @@
+//! Helpers for testing decode robustness, enabled by the `test-utils` feature.
+
+use std::sync::{Arc, OnceLock};
+
+use algebra::Field;
+use fhe_core::{CmLweCiphertext, RlweCiphertext};
+use rand::{rngs::StdRng, CryptoRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+use crate::{
+    ClueValue, Detector, KeyGen, OmrError, OmrParameters, OutputValue, PertinencyCiphertext,
+    Retriever, SecondLevelField, SecretKeyPack, Sender,
+};
+
+/// A recipient with its detector, and a stranger whose clues are never pertinent.
+pub struct TestSetup {
+    /// The recipient's secret key pack.
+    pub secret_key: SecretKeyPack,
+    /// A sender for the recipient.
+    pub sender: Sender,
+    /// The recipient's detector, shared so it can also drive `detect_async`.
+    pub detector: Arc<Detector>,
+    /// A sender for another, unrelated recipient.
+    pub stranger: Sender,
+}
+
+impl TestSetup {
+    /// Generates the recipient and the stranger for `params`.
+    pub fn new<R>(params: OmrParameters, rng: &mut R) -> Self
+    where
+        R: Rng + CryptoRng,
+    {
+        let secret_key = KeyGen::generate_secret_key(params.clone(), rng);
+        let stranger_key = KeyGen::generate_secret_key(params, rng);
+        Self {
+            sender: secret_key.generate_sender(rng),
+            detector: Arc::new(secret_key.generate_detector(rng)),
+            stranger: stranger_key.generate_sender(rng),
+            secret_key,
+        }
+    }
+
+    /// Returns a [`TestSetup`] for [`OmrParameters::new`], generated on first use and
+    /// shared by every caller, so tests only pay for key generation once.
+    pub fn shared() -> &'static Self {
+        static SHARED: OnceLock<TestSetup> = OnceLock::new();
+        SHARED.get_or_init(|| Self::new(OmrParameters::new(), &mut StdRng::seed_from_u64(0)))
+    }
+
+    /// Generates the recipient's [`Retriever`] for a board.
+    #[inline]
+    pub fn retriever(
+        &self,
+        all_payloads_count: usize,
+        pertinent_count: usize,
+    ) -> Result<Retriever<SecondLevelField>, OmrError> {
+        self.secret_key
+            .generate_retriever(all_payloads_count, pertinent_count)
+    }
+
+    /// Generates a board with one clue bundle per tag, from the recipient's sender
+    /// where the tag is `true` and from the stranger elsewhere.
+    pub fn gen_board<R>(
+        &self,
+        pertinent_tag: &[bool],
+        rng: &mut R,
+    ) -> Vec<CmLweCiphertext<ClueValue>>
+    where
+        R: Rng + CryptoRng,
+    {
+        pertinent_tag
+            .iter()
+            .map(|&pertinent| {
+                let sender = if pertinent {
+                    &self.sender
+                } else {
+                    &self.stranger
+                };
+                sender.gen_clues(rng)
+            })
+            .collect()
+    }
+
+    /// Decrypts the indicator of every pertinency ciphertext under the recipient's key.
+    pub fn indicators(&self, pertinency_vector: &[PertinencyCiphertext]) -> Vec<OutputValue> {
+        pertinency_vector
+            .iter()
+            .map(|ct| self.secret_key.decrypt_pertinency(ct)[0])
+            .collect()
+    }
+}
+
+/// Adds rounded Gaussian noise of standard deviation `sigma` to every coefficient
+/// of the `b` part of `ct`.
+///
+/// # Panics
+///
+/// Panics if `sigma` is negative or not finite.
+pub fn add_gaussian_noise<R: Rng>(
+    ct: &mut RlweCiphertext<SecondLevelField>,
+    sigma: f64,
+    rng: &mut R,
+) {
+    let q = <SecondLevelField as Field>::MODULUS_VALUE as i128;
+    let normal = Normal::new(0.0, sigma).expect("Sigma must be finite and non-negative.");
+
+    ct.b_mut().iter_mut().for_each(|b| {
+        let e = normal.sample(rng).round() as i128;
+        *b = (*b as i128 + e).rem_euclid(q) as u64;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::retriever::round_coefficients;
+
+    #[test]
+    fn only_tagged_clues_detect() {
+        let mut rng = StdRng::seed_from_u64(14);
+        let setup = TestSetup::shared();
+
+        let tags = [true, false, false, true];
+        let board = setup.gen_board(&tags, &mut rng);
+        let detected = setup.indicators(&setup.detector.detect_batch(&board));
+
+        assert_eq!(detected, tags.map(OutputValue::from));
+    }
+
+    #[test]
+    fn decode_breaks_down_past_half_delta() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let n = 2048;
+        let p = 257u64;
+        let q = <SecondLevelField as Field>::MODULUS_VALUE;
+        let delta = q / p;
+
+        let messages = (0..n).map(|_| rng.gen_range(0..p)).collect::<Vec<_>>();
+        let errors_at = |sigma: f64, rng: &mut StdRng| {
+            // With `a = 0` the ciphertext decrypts to `b` under any key.
+            let mut ct = RlweCiphertext::<SecondLevelField>::zero(n);
+            ct.b_mut()
+                .iter_mut()
+                .zip(messages.iter())
+                .for_each(|(b, &m)| *b = m * delta);
+            add_gaussian_noise(&mut ct, sigma, rng);
+
+            round_coefficients::<SecondLevelField>(ct.b().iter().copied(), p)
+                .iter()
+                .zip(messages.iter())
+                .filter(|(x, m)| x != m)
+                .count()
+        };
+
+        let breakdown = (0..=6)
+            .rev()
+            .map(|k| delta as f64 / (1u64 << k) as f64)
+            .find(|&sigma| errors_at(sigma, &mut rng) > 0)
+            .unwrap();
+
+        assert!(breakdown > delta as f64 / 16.0);
+        assert!(breakdown <= delta as f64 / 2.0);
+    }
+}